@@ -0,0 +1,204 @@
+// Purpose: Pan, zoom and orbit camera for the viewer, with a top-down orthographic mode and fit to network.
+
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+use crate::charts::pointer_over_ui;
+use crate::comp::World;
+
+// Vertical field of view of the perspective camera.
+const FOV: f32 = std::f32::consts::FRAC_PI_4;
+
+// Tilt from straight down is kept below this angle so the camera stays above the ground.
+const MAX_PITCH: f32 = 1.45;
+
+// Closest and farthest distance from the focus point, in meters.
+const MIN_DISTANCE: f32 = 5.0;
+const MAX_DISTANCE: f32 = 200000.0;
+
+// Zoom factor per wheel line, per wheel pixel and per second of a held key.
+const WHEEL_LINE_ZOOM: f32 = 1.15;
+const WHEEL_PIXEL_ZOOM: f32 = 1.002;
+const KEY_ZOOM: f32 = 3.0;
+
+// Orbit speed in radians per pixel of mouse motion and per second of a held key.
+const MOUSE_ORBIT_SPEED: f32 = 0.005;
+const KEY_ORBIT_SPEED: f32 = 1.2;
+
+// Extra space around the network when fitting it to the view.
+const FIT_MARGIN: f32 = 1.1;
+
+// Camera orbiting a focus point on the ground, z is up.
+// A pitch of zero looks straight down, the yaw turns the view around the vertical axis.
+#[derive(Component)]
+pub struct CameraController{
+    pub focus: Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub orthographic: bool,
+    // Frame the network on the next update, set on startup and when a file is loaded
+    pub fit_pending: bool,
+}
+
+impl Default for CameraController{
+    fn default() -> CameraController{
+        CameraController{
+            focus: Vec3::ZERO,
+            distance: 1000.0,
+            yaw: 0.0,
+            pitch: 0.3,
+            orthographic: false,
+            fit_pending: true,
+        }
+    }
+}
+
+impl CameraController{
+    pub fn transform(&self) -> Transform{
+        let pitch = if self.orthographic { 0.0 } else { self.pitch };
+        let rotation = Quat::from_rotation_z(self.yaw) * Quat::from_rotation_x(pitch);
+        Transform{
+            translation: self.focus + rotation * Vec3::Z * self.distance,
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    // Projection matching the distance, the orthographic view shows the same height as the perspective one at the focus.
+    pub fn projection(&self) -> Projection{
+        let far = self.distance * 4.0 + 1000.0;
+        if self.orthographic{
+            Projection::Orthographic(OrthographicProjection{
+                far,
+                scaling_mode: ScalingMode::FixedVertical(2.0 * self.distance * (FOV / 2.0).tan()),
+                ..Default::default()
+            })
+        }
+        else{
+            Projection::Perspective(PerspectiveProjection{
+                fov: FOV,
+                far,
+                ..Default::default()
+            })
+        }
+    }
+
+    // Center the bounding box of all roads and zoom out until it is in view.
+    pub fn fit(&mut self,world:&World,aspect_ratio:f32){
+        let mut points = world.roads.iter().flat_map(|road| [road.from,road.to]).map(|(x,y,z)| Vec3::new(x,y,z));
+        let first = match points.next(){
+            Some(first) => first,
+            None => return,
+        };
+        let (min,max) = points.fold((first,first),|(min,max),point| (min.min(point),max.max(point)));
+        let size = max - min;
+        let height = size.y.max(size.x / aspect_ratio.max(0.1));
+        self.focus = (min + max) / 2.0;
+        self.distance = (height * FIT_MARGIN / 2.0 / (FOV / 2.0).tan()).clamp(MIN_DISTANCE,MAX_DISTANCE);
+        self.yaw = 0.0;
+    }
+
+    // Meters on the ground per pixel of the window at the focus point.
+    fn meters_per_pixel(&self,window_height:f32) -> f32{
+        2.0 * self.distance * (FOV / 2.0).tan() / window_height.max(1.0)
+    }
+
+    // Move the focus by a distance in meters to the right and towards the top of the screen.
+    fn pan(&mut self,right:f32,up:f32){
+        let rotation = Quat::from_rotation_z(self.yaw);
+        self.focus += rotation * Vec3::X * right + rotation * Vec3::Y * up;
+    }
+
+    fn zoom(&mut self,factor:f32){
+        self.distance = (self.distance * factor).clamp(MIN_DISTANCE,MAX_DISTANCE);
+    }
+
+    fn orbit(&mut self,yaw:f32,pitch:f32){
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(0.0,MAX_PITCH);
+    }
+}
+
+// Spawn the camera, it is fitted to the network once the roads exist.
+pub fn spawn_camera(mut commands: Commands){
+    let controller = CameraController::default();
+    commands.spawn((Camera3dBundle{
+        transform: controller.transform(),
+        projection: controller.projection(),
+        ..Default::default()
+    },
+    controller));
+}
+
+// Right drag pans, middle drag or alt with right drag orbits and the wheel zooms.
+// Shift with the arrow keys pans, control with the arrow keys orbits, plus and minus zoom,
+// O toggles the top-down orthographic view and F fits the network to the window.
+#[allow(clippy::too_many_arguments)]
+pub fn camera_control_system(
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion_events: EventReader<MouseMotion>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut drop_events: EventReader<FileDragAndDrop>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    time: Res<Time>,
+    world: Res<World>,
+    mut cameras: Query<(&mut CameraController, &mut Transform, &mut Projection)>,
+    mut contexts: EguiContexts) {
+    let (window_width,window_height) = windows.get_single().map_or((1280.0,720.0),|window| (window.width(),window.height()));
+    //Drags and scrolls in the charts window move the window, not the camera
+    let over_ui = pointer_over_ui(&mut contexts);
+    let motion:Vec2 = motion_events.iter().filter(|_| !over_ui).map(|event| event.delta).sum();
+    let wheel:f32 = wheel_events.iter().filter(|_| !over_ui).map(|event| match event.unit {
+        MouseScrollUnit::Line => WHEEL_LINE_ZOOM.powf(-event.y),
+        MouseScrollUnit::Pixel => WHEEL_PIXEL_ZOOM.powf(-event.y),
+    }).product();
+    let dropped = drop_events.iter().any(|event| matches!(event, FileDragAndDrop::DroppedFile { .. }));
+    let t = time.delta_seconds();
+    let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+    let control = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    let alt = keys.pressed(KeyCode::LAlt) || keys.pressed(KeyCode::RAlt);
+    //Held keys as -1, 0 or 1 along the horizontal and vertical screen axes
+    let axis = |negative:KeyCode,positive:KeyCode| keys.pressed(positive) as i32 as f32 - keys.pressed(negative) as i32 as f32;
+    let arrows = Vec2::new(axis(KeyCode::Left,KeyCode::Right),axis(KeyCode::Down,KeyCode::Up));
+
+    for (mut controller,mut transform,mut projection) in &mut cameras {
+        if dropped || keys.just_pressed(KeyCode::F) {
+            controller.fit_pending = true;
+        }
+        //Wait for the roads, the sample world is created by another startup system
+        if controller.fit_pending && !world.roads.is_empty() {
+            controller.fit(&world,window_width / window_height.max(1.0));
+            controller.fit_pending = false;
+        }
+        if keys.just_pressed(KeyCode::O) {
+            controller.orthographic = !controller.orthographic;
+        }
+
+        let meters_per_pixel = controller.meters_per_pixel(window_height);
+        if mouse.pressed(MouseButton::Middle) || (alt && mouse.pressed(MouseButton::Right)) {
+            controller.orbit(-motion.x * MOUSE_ORBIT_SPEED,-motion.y * MOUSE_ORBIT_SPEED);
+        }
+        //Control with right drag paints a flow instead
+        else if mouse.pressed(MouseButton::Right) && !control {
+            //The ground follows the cursor, screen y grows downwards
+            controller.pan(-motion.x * meters_per_pixel,motion.y * meters_per_pixel);
+        }
+        if shift {
+            //Cross the window height in about two seconds
+            let speed = meters_per_pixel * window_height / 2.0 * t;
+            controller.pan(arrows.x * speed,arrows.y * speed);
+        }
+        if control {
+            controller.orbit(-arrows.x * KEY_ORBIT_SPEED * t,arrows.y * KEY_ORBIT_SPEED * t);
+        }
+        let zoom_keys = axis(KeyCode::Equals,KeyCode::Minus) + axis(KeyCode::NumpadAdd,KeyCode::NumpadSubtract);
+        controller.zoom(wheel * KEY_ZOOM.powf(zoom_keys * t));
+
+        *transform = controller.transform();
+        *projection = controller.projection();
+    }
+}
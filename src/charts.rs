@@ -0,0 +1,159 @@
+// Purpose: Live time-series charts of network metrics and of the selected road, drawn with egui.
+
+use bevy::prelude::*;
+use bevy_egui::egui::plot::{Line, Plot, PlotPoints};
+use bevy_egui::{egui, EguiContexts};
+use crate::comp::World;
+use crate::inspect::Selection;
+use crate::replay::Replay;
+use std::collections::VecDeque;
+
+// Simulation time between samples, in seconds.
+const SAMPLE_INTERVAL: f32 = 1.0;
+
+// Samples kept, the oldest are dropped first.
+const MAX_SAMPLES: usize = 7200;
+
+// Window of the per minute rates, in seconds.
+const RATE_WINDOW: f32 = 60.0;
+
+const CHART_HEIGHT: f32 = 90.0;
+
+// Network state at one moment. Delay, distance and arrivals count since the start of the run.
+struct MetricSample{
+    time: f32,
+    vehicles: usize,
+    // None when no vehicle is in the network
+    mean_speed: Option<f32>,
+    // Seconds beyond the travel time at the speed limits, of finished trips and of the vehicles still driving
+    total_delay: f32,
+    distance: f32,
+    arrivals: usize,
+}
+
+// Samples of the running simulation and whether the charts window is shown.
+#[derive(Resource, Default)]
+pub struct Charts{
+    pub open: bool,
+    samples: VecDeque<MetricSample>,
+    // Finished trips already summed into the delay and distance
+    counted_trips: usize,
+    finished_delay: f32,
+    finished_distance: f32,
+}
+
+impl Charts{
+    fn clear(&mut self){
+        self.samples.clear();
+        self.counted_trips = 0;
+        self.finished_delay = 0.0;
+        self.finished_distance = 0.0;
+    }
+
+    fn sample(&mut self,world:&World){
+        for trip in world.trips.iter().skip(self.counted_trips){
+            self.finished_delay += trip.delay;
+            self.finished_distance += trip.distance;
+        }
+        self.counted_trips = world.trips.len();
+        let vehicles = world.vehicles.len();
+        let active_delay:f32 = world.vehicles.iter().map(|vehicle| (world.time - vehicle.trip.departure_time - vehicle.trip.free_flow_time).max(0.0)).sum();
+        let active_distance:f32 = world.vehicles.iter().map(|vehicle| vehicle.trip.distance).sum();
+        if self.samples.len() == MAX_SAMPLES{
+            self.samples.pop_front();
+        }
+        self.samples.push_back(MetricSample{
+            time: world.time,
+            vehicles,
+            mean_speed: (vehicles > 0).then(|| world.vehicles.iter().map(|vehicle| vehicle.velocity).sum::<f32>() / vehicles as f32),
+            total_delay: self.finished_delay + active_delay,
+            distance: self.finished_distance + active_distance,
+            arrivals: world.trips.len(),
+        });
+    }
+
+    fn series(&self,value:impl Fn(&MetricSample) -> Option<f32>) -> Vec<[f64; 2]>{
+        self.samples.iter().filter_map(|sample| value(sample).map(|value| [sample.time as f64,value as f64])).collect()
+    }
+
+    // Change of a cumulative value per minute over the last rate window of every sample.
+    fn rate_series(&self,value:impl Fn(&MetricSample) -> f32) -> Vec<[f64; 2]>{
+        let mut start = 0;
+        self.samples.iter().enumerate().skip(1).map(|(index,sample)| {
+            while self.samples[start].time < sample.time - RATE_WINDOW{
+                start += 1;
+            }
+            let first = &self.samples[start.min(index - 1)];
+            let rate = (value(sample) - value(first)) / (sample.time - first.time).max(f32::EPSILON) * 60.0;
+            [sample.time as f64,rate as f64]
+        }).collect()
+    }
+}
+
+// Whether the pointer is over an egui window or dragging in one, the world then ignores the mouse.
+pub(crate) fn pointer_over_ui(contexts:&mut EguiContexts) -> bool{
+    let ctx = contexts.ctx_mut();
+    ctx.wants_pointer_input() || ctx.is_pointer_over_area()
+}
+
+fn chart(ui:&mut egui::Ui,title:&str,points:Vec<[f64; 2]>){
+    ui.label(title);
+    Plot::new(title)
+        .height(CHART_HEIGHT)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .include_y(0.0)
+        .show(ui,|plot| plot.line(Line::new(PlotPoints::new(points))));
+}
+
+// Sample the network every second of simulation time, starting over when another scenario is loaded.
+pub fn chart_sampling_system(world: Res<World>,replay: Option<Res<Replay>>,mut charts: ResMut<Charts>) {
+    if replay.is_some() {
+        return;
+    }
+    let restarted = charts.samples.back().is_some_and(|sample| world.time < sample.time) || world.trips.len() < charts.counted_trips;
+    if restarted {
+        charts.clear();
+    }
+    if charts.samples.back().is_none_or(|sample| world.time >= sample.time + SAMPLE_INTERVAL) {
+        charts.sample(&world);
+    }
+}
+
+// M shows or hides the charts of the network and of the selected road.
+pub fn charts_system(
+    keys: Res<Input<KeyCode>>,
+    world: Res<World>,
+    selection: Res<Selection>,
+    mut charts: ResMut<Charts>,
+    mut contexts: EguiContexts) {
+    if keys.just_pressed(KeyCode::M) {
+        charts.open = !charts.open;
+    }
+    if !charts.open {
+        return;
+    }
+    let mut open = true;
+    egui::Window::new("Charts").default_width(360.0).vscroll(true).open(&mut open).show(contexts.ctx_mut(),|ui| {
+        chart(ui,"Vehicles in network",charts.series(|sample| Some(sample.vehicles as f32)));
+        chart(ui,"Mean speed (m/s)",charts.series(|sample| sample.mean_speed));
+        chart(ui,"Total delay (vehicle hours)",charts.series(|sample| Some(sample.total_delay / 3600.0)));
+        //Meters driven per minute times 60/1000 are vehicle kilometers per hour
+        chart(ui,"Throughput (vehicle km/h)",charts.rate_series(|sample| sample.distance * 60.0 / 1000.0));
+        chart(ui,"Arrivals per minute",charts.rate_series(|sample| sample.arrivals as f32));
+
+        let Selection::Road(road) = *selection else {
+            ui.label("Select a road to chart its flow and density");
+            return;
+        };
+        let Some(statistics) = &world.link_statistics else {
+            ui.label("Link statistics are not collected");
+            return;
+        };
+        let measurements:Vec<_> = statistics.road_measurements(road).collect();
+        chart(ui,&format!("Road {} flow (veh/h)", road),measurements.iter().map(|measurement| [measurement.end as f64,measurement.flow as f64]).collect());
+        chart(ui,&format!("Road {} density (veh/km)", road),measurements.iter().map(|measurement| [measurement.end as f64,measurement.density as f64]).collect());
+    });
+    charts.open = open;
+}
@@ -2,25 +2,62 @@
 
 use ordered_float::OrderedFloat;
 use bevy::prelude::Resource;
-use serde::Deserialize;
-use std::collections::{BinaryHeap, HashMap};
-use std::cmp::Reverse;
+use crate::contraction::ContractionHierarchy;
+use crate::detector::{AreaDetector, DetectorData, PointDetector};
+use crate::link_stats::LinkStatistics;
+use crate::recorder::TrajectoryRecorder;
+use crate::rng::{SimRng, DRIVER_STREAM};
+use crate::trip::{TripRecord, TripStats};
+use crate::vehicle_type::{VehicleType, VehicleTypeDistributions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Speed at the end of an imported road that ends at a traffic signal.
+pub const SIGNAL_SPEED: f32 = 2.0;
+
+// Speed at the end of an imported road that ends at a junction.
+pub const JUNCTION_SPEED: f32 = 5.0;
+
+// Distance before the end of the last road where vehicles arrive when no arrival position is given.
+pub const ARRIVAL_MARGIN: f32 = 5.0;
 
 // World struct contains all the roads and vehicles in the simulation.
 #[derive(Resource)]
 pub struct World{
     pub roads: Vec<Road>,
     pub vehicles: Vec<Vehicle>,
+    pub rng: SimRng,
+    pub vehicle_types: HashMap<VehicleType,VehicleTypeDistributions>,
+    pub connections: HashMap<(usize,usize),Connection>,
+    pub route_index: Option<ContractionHierarchy>,
+    //Latitude and longitude of the local origin, when the network was imported from map data
+    pub geo_origin: Option<(f64,f64)>,
+    pub next_vehicle_id: usize,
+    //Simulation time in seconds
+    pub time: f32,
+    //Vehicles waiting for their departure time, latest first
+    pub departures: Vec<Departure>,
+    //Writes vehicle trajectories while recording
+    pub recorder: Option<TrajectoryRecorder>,
+    //Trips of the vehicles that reached their destination
+    pub trips: Vec<TripRecord>,
+    pub point_detectors: Vec<PointDetector>,
+    pub area_detectors: Vec<AreaDetector>,
+    //Per-road flow, density and speed, when collected
+    pub link_statistics: Option<LinkStatistics>,
 }
 
 // Implement the Display trait for the World struct.
 impl std::fmt::Display for World {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Roads: {:?}\nVehicles:{:?})", self.roads, self.vehicles)
+        write!(f, "Seed: {}\nRoads: {:?}\nVehicles:{:?})", self.rng.seed(), self.roads, self.vehicles)
     }
 }
 // Deserialize the JSON data into corresponding structs
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct RoadData {
     from: [f32; 3],
     to: [f32; 3],
@@ -31,50 +68,113 @@ struct RoadData {
     end_speed_limit: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+struct ConnectionData {
+    from_road: usize,
+    to_road: usize,
+    #[serde(default)]
+    allowed_types: Option<Vec<VehicleType>>,
+    #[serde(default)]
+    turn_penalty: f32,
+    #[serde(default)]
+    speed_limit: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize)]
 struct VehicleData {
+    #[serde(default, rename = "type")]
+    vehicle_type: VehicleType,
     position: f32,
     velocity: f32,
-    acceleration: f32,
-    break_deceleration: f32,
+    #[serde(default)]
+    acceleration: Option<f32>,
+    #[serde(default)]
+    break_deceleration: Option<f32>,
     on_road: usize,
-    watch_distance: f32,
+    #[serde(default)]
+    watch_distance: Option<f32>,
     destination: usize,
     destination_position: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct WorldData {
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    vehicle_types: HashMap<VehicleType,VehicleTypeDistributions>,
     roads: Vec<RoadData>,
+    #[serde(default)]
+    connections: Vec<ConnectionData>,
+    #[serde(default)]
+    detectors: Vec<DetectorData>,
     vehicles: Vec<VehicleData>,
 }
 
 
 impl World{
-    fn new() -> World{
+    pub fn new() -> World{
         World{
             roads: Vec::new(),
             vehicles: Vec::new(),
+            rng: SimRng::from_entropy(),
+            vehicle_types: HashMap::new(),
+            connections: HashMap::new(),
+            route_index: None,
+            geo_origin: None,
+            next_vehicle_id: 0,
+            time: 0.0,
+            departures: Vec::new(),
+            recorder: None,
+            trips: Vec::new(),
+            point_detectors: Vec::new(),
+            area_detectors: Vec::new(),
+            link_statistics: None,
         }
     }
-    pub fn add_vehicle(&mut self,position:f32,velocity:f32,acceleration:f32,break_decceleration:f32,on_road:usize,watch_distance:f32,destination:usize,destination_position:f32){
+    pub fn add_vehicle(&mut self,params:VehicleParams){
         let mut vehicle = Vehicle{
-            position: position,
-            velocity: velocity,
-            acceleration: acceleration,
-            break_decceleration: break_decceleration,
-            on_road: on_road,
-            watch_distance: watch_distance,
-            destination: destination,
-            destination_position: destination_position,
-            path: Vec::new()
+            id: self.next_vehicle_id,
+            vehicle_type: params.vehicle_type,
+            position: params.position,
+            velocity: params.velocity,
+            acceleration: params.acceleration,
+            break_decceleration: params.break_decceleration,
+            on_road: params.on_road,
+            watch_distance: params.watch_distance,
+            destination: params.destination,
+            destination_position: params.destination_position,
+            path: Vec::new(),
+            trip: TripStats::new(self.time,params.on_road),
+            control_law: ControlLaw::default()
         };
         
-        vehicle.path = self.find_shortest_path(vehicle.on_road, vehicle.destination);
+        vehicle.path = self.find_shortest_path(vehicle.on_road, vehicle.destination, vehicle.vehicle_type);
         vehicle.path.remove(0);
-        println!("Path: {:?}", vehicle.path);
+        self.next_vehicle_id += 1;
         self.vehicles.push(vehicle);
     }
+    // Schedule a vehicle to enter the network at its departure time.
+    pub fn add_departure(&mut self,departure:Departure){
+        //Vehicles with the same departure time enter in the order they were added
+        let index = self.departures.partition_point(|other| other.time > departure.time);
+        self.departures.insert(index,departure);
+    }
+    // Add the vehicles whose departure time has been reached.
+    pub fn release_departures(&mut self){
+        while self.departures.last().is_some_and(|departure| departure.time <= self.time){
+            let departure = self.departures.pop().unwrap();
+            self.add_vehicle(departure.params());
+            if departure.route.len() > 1{
+                self.vehicles.last_mut().unwrap().path = departure.route[1..].to_vec();
+            }
+        }
+    }
+    // Sample (acceleration, break decceleration, watch distance) for a new vehicle of the given type.
+    pub fn sample_vehicle_params(&mut self,vehicle_type:VehicleType) -> (f32,f32,f32){
+        let distributions = self.vehicle_types.get(&vehicle_type).cloned().unwrap_or_default();
+        distributions.sample(vehicle_type,self.rng.stream(DRIVER_STREAM))
+    }
     pub fn add_road(&mut self,from:(f32,f32,f32),to:(f32,f32,f32),lanes:u8,speed_limit:f32,from_road:Vec<usize>,to_road:Vec<usize>,end_speed_limit:f32){
         let mut road = Road{
             from: from,
@@ -85,20 +185,158 @@ impl World{
             from_road: from_road,
             to_road: to_road,
             obstacle_map: HashMap::new(),
-            end_speed_limit: end_speed_limit
+            end_speed_limit: end_speed_limit,
+            signal: false,
         };
 
         //.x0 is for road ends
         road.obstacle_map.insert(OrderedFloat((road.length*10.0).round()/10.0),road.end_speed_limit);
+
+        //Every successor gets an unrestricted connection until the scenario says otherwise
+        let road_index = self.roads.len();
+        for &next_road in road.to_road.iter(){
+            self.connections.insert((road_index,next_road),Connection::new(road_index,next_road));
+        }
         self.roads.push(road);
+        //The route index no longer covers the network
+        self.route_index = None;
+    }
+    // Add a movement between two roads, replacing the existing one if present.
+    pub fn add_connection(&mut self,from_road:usize,to_road:usize,allowed_types:Option<Vec<VehicleType>>,turn_penalty:f32,speed_limit:Option<f32>){
+        if from_road < self.roads.len() && !self.roads[from_road].to_road.contains(&to_road){
+            self.roads[from_road].to_road.push(to_road);
+        }
+        if to_road < self.roads.len() && !self.roads[to_road].from_road.contains(&from_road){
+            self.roads[to_road].from_road.push(from_road);
+        }
+        self.connections.insert((from_road,to_road),Connection{
+            from_road,
+            to_road,
+            allowed_types,
+            turn_penalty,
+            speed_limit,
+        });
+        self.update_route_index(from_road,to_road);
+    }
+    // Change the turn penalty of an existing movement.
+    pub fn set_turn_penalty(&mut self,from_road:usize,to_road:usize,turn_penalty:f32){
+        if let Some(connection) = self.connections.get_mut(&(from_road,to_road)){
+            connection.turn_penalty = turn_penalty;
+            self.update_route_index(from_road,to_road);
+        }
+    }
+    // Get the movement between two roads, if they are connected.
+    pub fn get_connection(&self,from_road:usize,to_road:usize) -> Option<&Connection>{
+        self.connections.get(&(from_road,to_road))
     }
     pub fn reset(&mut self){
         self.roads.clear();
         self.vehicles.clear();
+        self.vehicle_types.clear();
+        self.connections.clear();
+        self.route_index = None;
+        self.geo_origin = None;
+        self.time = 0.0;
+        self.next_vehicle_id = 0;
+        self.departures.clear();
+        self.trips.clear();
+        self.point_detectors.clear();
+        self.area_detectors.clear();
+        if let Some(statistics) = &self.link_statistics{
+            self.link_statistics = Some(LinkStatistics::new(statistics.interval,0.0));
+        }
+        //Restart the random streams so a reloaded scenario runs the same way
+        let seed = self.rng.seed();
+        self.rng.reseed(seed);
+    }
+    // Load a scenario or network file by its extension.
+    // Snapshots continue a saved run, OpenStreetMap extracts, OpenDRIVE files and SUMO networks are imported,
+    // everything else is read as a JSON scenario.
+    pub fn load_file(&mut self,path:&Path){
+        let file_name = path.to_string_lossy().to_lowercase();
+        if file_name.ends_with(".snapshot"){
+            //A corrupt or outdated snapshot leaves the world unchanged
+            if let Err(error) = self.load_snapshot(path){
+                println!("Failed to restore snapshot: {}", error);
+            }
+        }
+        else if file_name.ends_with(".osm") || file_name.ends_with(".osm.pbf"){
+            if let Err(error) = self.load_osm(path){
+                println!("Failed to read OpenStreetMap data: {}", error);
+            }
+        }
+        else if file_name.ends_with(".xodr"){
+            if let Err(error) = self.load_opendrive(path){
+                println!("Failed to read OpenDRIVE data: {}", error);
+            }
+        }
+        else if file_name.ends_with(".net.xml"){
+            //Routes are read from the .rou.xml file next to the network if there is one
+            let name = path.to_string_lossy();
+            let route_path = PathBuf::from(format!("{}.rou.xml", &name[..name.len()-".net.xml".len()]));
+            if let Err(error) = self.load_sumo(path,Some(route_path.as_path()).filter(|route_path| route_path.exists())){
+                println!("Failed to read SUMO network: {}", error);
+            }
+        }
+        else{
+            let mut file = File::open(path).expect("Unable to open");
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).expect("Unable to read");
+            self.load_json(contents);
+        }
+    }
+    // Write the network, vehicles and detectors as a JSON scenario that load_json reads back.
+    // Vehicles waiting for their departure and signal states are not part of the scenario format.
+    pub fn save_json(&self,path:&Path) -> Result<(),String>{
+        //Connections are written in a stable order
+        let mut connections:Vec<&Connection> = self.connections.values().collect();
+        connections.sort_by_key(|connection| (connection.from_road,connection.to_road));
+        let world_data = WorldData{
+            seed: Some(self.rng.seed()),
+            vehicle_types: self.vehicle_types.clone(),
+            roads: self.roads.iter().map(|road| RoadData{
+                from: [road.from.0, road.from.1, road.from.2],
+                to: [road.to.0, road.to.1, road.to.2],
+                lanes: road.lanes,
+                speed_limit: road.speed_limit,
+                from_road: road.from_road.clone(),
+                to_road: road.to_road.clone(),
+                end_speed_limit: road.end_speed_limit,
+            }).collect(),
+            connections: connections.into_iter().map(|connection| ConnectionData{
+                from_road: connection.from_road,
+                to_road: connection.to_road,
+                allowed_types: connection.allowed_types.clone(),
+                turn_penalty: connection.turn_penalty,
+                speed_limit: connection.speed_limit,
+            }).collect(),
+            detectors: self.detector_data(),
+            vehicles: self.vehicles.iter().map(|vehicle| VehicleData{
+                vehicle_type: vehicle.vehicle_type,
+                position: vehicle.position,
+                velocity: vehicle.velocity,
+                acceleration: Some(vehicle.acceleration),
+                break_deceleration: Some(vehicle.break_decceleration),
+                on_road: vehicle.on_road,
+                watch_distance: Some(vehicle.watch_distance),
+                destination: vehicle.destination,
+                destination_position: vehicle.destination_position,
+            }).collect(),
+        };
+        let contents = serde_json::to_string_pretty(&world_data).map_err(|e| e.to_string())?;
+        std::fs::write(path,contents).map_err(|e| e.to_string())
     }
     pub fn load_json(&mut self,contents:String){
         self.reset();
         let world_data: WorldData = serde_json::from_str(&contents).expect("Failed to deserialize JSON data.");
+        //Use the scenario seed, or draw a new one so the run can still be reproduced
+        let seed = world_data.seed.unwrap_or_else(rand::random::<u64>);
+        self.rng.reseed(seed);
+        println!("Seed: {}", seed);
+        self.vehicle_types = world_data.vehicle_types;
+        for (vehicle_type,distributions) in self.vehicle_types.iter_mut(){
+            distributions.remove_invalid(*vehicle_type);
+        }
         // Add roads from the JSON data
         for road_data in world_data.roads {
             self.add_road(
@@ -112,91 +350,47 @@ impl World{
             );
         }
 
-        // Add vehicles from the JSON data
-        for vehicle_data in world_data.vehicles {
-            self.add_vehicle(
-                vehicle_data.position,
-                vehicle_data.velocity,
-                vehicle_data.acceleration,
-                vehicle_data.break_deceleration,
-                vehicle_data.on_road,
-                vehicle_data.watch_distance,
-                vehicle_data.destination,
-                vehicle_data.destination_position,
+        // Add connections from the JSON data
+        for connection_data in world_data.connections {
+            self.add_connection(
+                connection_data.from_road,
+                connection_data.to_road,
+                connection_data.allowed_types,
+                connection_data.turn_penalty,
+                connection_data.speed_limit,
             );
         }
-    }
-
-    // Helper function to get adjacent roads for a given road index
-    fn get_adjacent_roads(&self, road_index: usize) -> &[usize] {
-        if road_index < self.roads.len() {
-            let road = &self.roads[road_index];
-            &road.to_road
-        } else {
-            &[]
-        }
-    }
-
-    // Helper function to get the distance between two roads
-    fn get_distance_between_roads(&self, road_index_1: usize, road_index_2: usize) -> f32 {
-        if road_index_1 < self.roads.len() && road_index_2 < self.roads.len() {
-            let road_1 = &self.roads[road_index_1];
-            let road_2 = &self.roads[road_index_2];
-            // For simplicity, assume 3D Euclidean distance between roads' end points
-            ((road_1.to.0 - road_2.from.0).powi(2) + (road_1.to.1 - road_2.from.1).powi(2)).sqrt()
-        } else {
-            f32::INFINITY
-        }
-    }
-
-    // Heuristic function to estimate the cost from a given road to the destination road.
-    fn heuristic(&self, road_index: usize, destination_road: usize) -> OrderedFloat<f32> {
-        OrderedFloat(self.get_distance_between_roads(road_index, destination_road))
-    }
-
-    // Function to find the shortest path using A* algorithm
-    fn find_shortest_path(&self, start_road: usize, destination_road: usize) -> Vec<usize> {
-        let mut distances = HashMap::new();
-        let mut previous = HashMap::new();
-        let mut queue = BinaryHeap::new();
-
-        distances.insert(start_road, OrderedFloat(0.0));
-        queue.push(Reverse((OrderedFloat(0.0) + self.heuristic(start_road, destination_road), start_road)));
-
-        while let Some(Reverse((current_distance, current_road))) = queue.pop() {
-            if current_road == destination_road {
-                break;
-            }
 
-            for &next_road in self.get_adjacent_roads(current_road) {
-                let distance_to_next = self.get_distance_between_roads(current_road, next_road);
-                let total_distance = current_distance - self.heuristic(current_road, destination_road) + OrderedFloat(distance_to_next);
+        //Preprocess the network so vehicles can be routed quickly
+        self.build_route_index();
 
-                if !distances.contains_key(&next_road) || total_distance < *distances.get(&next_road).unwrap() {
-                    distances.insert(next_road, total_distance);
-                    previous.insert(next_road, current_road);
-                    queue.push(Reverse((total_distance + self.heuristic(next_road, destination_road), next_road)));
-                }
+        for detector_data in world_data.detectors {
+            if let Err(error) = self.add_detector(detector_data){
+                println!("{}, skipping it", error);
             }
         }
 
-        // Reconstruct the path
-        let mut path = Vec::new();
-        let mut current_road = destination_road;
-        while let Some(&prev) = previous.get(&current_road) {
-            path.push(current_road);
-            current_road = prev;
+        // Add vehicles from the JSON data
+        for vehicle_data in world_data.vehicles {
+            //Parameters missing from the vehicle are sampled from its type
+            let (acceleration,break_decceleration,watch_distance) = self.sample_vehicle_params(vehicle_data.vehicle_type);
+            self.add_vehicle(VehicleParams{
+                vehicle_type: vehicle_data.vehicle_type,
+                position: vehicle_data.position,
+                velocity: vehicle_data.velocity,
+                acceleration: vehicle_data.acceleration.unwrap_or(acceleration),
+                break_decceleration: vehicle_data.break_deceleration.unwrap_or(break_decceleration),
+                on_road: vehicle_data.on_road,
+                watch_distance: vehicle_data.watch_distance.unwrap_or(watch_distance),
+                destination: vehicle_data.destination,
+                destination_position: vehicle_data.destination_position,
+            });
         }
-        path.push(start_road);
-        path.reverse();
-        path
     }
-
-
 }
 
 // Road struct contains the length, number of lanes, and speed limit of a road.}
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Road{
     pub from: (f32,f32,f32),
     pub to: (f32,f32,f32),
@@ -206,7 +400,21 @@ pub struct Road{
     pub from_road: Vec<usize>,
     pub to_road: Vec<usize>,
     pub obstacle_map: HashMap<OrderedFloat<f32>,f32>,
-    pub end_speed_limit: f32
+    pub end_speed_limit: f32,
+    //Road ends at a traffic signal
+    pub signal: bool
+}
+
+impl Road{
+    // Point at a distance along the road.
+    pub fn point_at(&self,position:f32) -> (f32,f32,f32){
+        let fraction = if self.length.into_inner() > 0.0 { position / self.length.into_inner() } else { 0.0 };
+        (
+            self.from.0 + (self.to.0 - self.from.0) * fraction,
+            self.from.1 + (self.to.1 - self.from.1) * fraction,
+            self.from.2 + (self.to.2 - self.from.2) * fraction,
+        )
+    }
 }
 
 // Implement the Display trait for the Road struct.
@@ -217,9 +425,91 @@ impl std::fmt::Display for Road {
 }
 
 
+// Connection struct describes a movement from the end of one road to the start of another.
+// The turn penalty is added to the route cost, in the same units as road length.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection{
+    pub from_road: usize,
+    pub to_road: usize,
+    pub allowed_types: Option<Vec<VehicleType>>,
+    pub turn_penalty: f32,
+    pub speed_limit: Option<f32>,
+}
+
+impl Connection{
+    // Unrestricted movement without penalty.
+    pub fn new(from_road:usize,to_road:usize) -> Connection{
+        Connection{
+            from_road,
+            to_road,
+            allowed_types: None,
+            turn_penalty: 0.0,
+            speed_limit: None,
+        }
+    }
+    // Check if a vehicle type may use this movement.
+    pub fn allows(&self,vehicle_type:VehicleType) -> bool{
+        match &self.allowed_types{
+            Some(types) => types.contains(&vehicle_type),
+            None => true,
+        }
+    }
+}
+
+
+// Departure struct holds a vehicle waiting to enter the network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Departure{
+    pub time: f32,
+    pub vehicle_type: VehicleType,
+    pub position: f32,
+    pub velocity: f32,
+    pub acceleration: f32,
+    pub break_decceleration: f32,
+    pub on_road: usize,
+    pub watch_distance: f32,
+    pub destination: usize,
+    pub destination_position: f32,
+    //Fixed route from on_road to destination, the shortest path is used when empty
+    pub route: Vec<usize>,
+}
+
+impl Departure{
+    // Initial state and driving parameters of the departing vehicle.
+    pub fn params(&self) -> VehicleParams{
+        VehicleParams{
+            vehicle_type: self.vehicle_type,
+            position: self.position,
+            velocity: self.velocity,
+            acceleration: self.acceleration,
+            break_decceleration: self.break_decceleration,
+            on_road: self.on_road,
+            watch_distance: self.watch_distance,
+            destination: self.destination,
+            destination_position: self.destination_position,
+        }
+    }
+}
+
+// VehicleParams struct holds the initial state and driving parameters of a vehicle entering the network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VehicleParams{
+    pub vehicle_type: VehicleType,
+    pub position: f32,
+    pub velocity: f32,
+    pub acceleration: f32,
+    pub break_decceleration: f32,
+    pub on_road: usize,
+    pub watch_distance: f32,
+    pub destination: usize,
+    pub destination_position: f32,
+}
+
 // Vehicle struct contains the position, velocity, and acceleration of a vehicle.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Vehicle{
+    pub id: usize,
+    pub vehicle_type: VehicleType,
     pub position: f32,
     pub velocity: f32,
     pub acceleration: f32,
@@ -228,7 +518,39 @@ pub struct Vehicle{
     pub watch_distance: f32,
     pub destination: usize,
     pub destination_position: f32,
-    pub path: Vec<usize>
+    pub path: Vec<usize>,
+    pub trip: TripStats,
+    // Not saved, it is set again by the next step
+    #[serde(skip)]
+    pub control_law: ControlLaw
+}
+
+// Branch of the driver model a vehicle took in its last step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlLaw{
+    // Speeding up towards the speed limit
+    #[default]
+    Accelerating,
+    // Holding the speed limit
+    Cruising,
+    // Slowing down for a slower vehicle ahead
+    Following,
+    // Slowing down for the end speed limit of the road or of the movement into the next road
+    RoadEnd,
+    // Stopping at the destination
+    Destination,
+}
+
+impl ControlLaw{
+    pub fn description(&self) -> &'static str{
+        match self{
+            ControlLaw::Accelerating => "accelerating",
+            ControlLaw::Cruising => "cruising at the speed limit",
+            ControlLaw::Following => "braking for the vehicle ahead",
+            ControlLaw::RoadEnd => "braking for the end speed limit",
+            ControlLaw::Destination => "braking for the destination",
+        }
+    }
 }
 
 // Implement the Display trait for the Vehicle struct.
@@ -246,7 +568,7 @@ pub fn sample_world() -> World{
     let mut world = World::new();
     world.add_road((0.0,10.0,0.0),(500.0,10.0,0.0),1,100.0,vec![0],vec![1],10.0);
     world.add_road((500.0,-10.0,0.0),(0.0,-10.0,0.0),1,100.0,vec![1],vec![0],10.0);
-    world.add_vehicle(0.0,0.0,5.0,-10.0,0,200.0,1,250.0);
-    world.add_vehicle(0.0,0.0,4.0,-7.0,1,250.0,0,311.0);
+    world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Car,position: 0.0,velocity: 0.0,acceleration: 5.0,break_decceleration: -10.0,on_road: 0,watch_distance: 200.0,destination: 1,destination_position: 250.0});
+    world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Car,position: 0.0,velocity: 0.0,acceleration: 4.0,break_decceleration: -7.0,on_road: 1,watch_distance: 250.0,destination: 0,destination_position: 311.0});
     return world;
 }
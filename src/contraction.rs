@@ -0,0 +1,492 @@
+// Purpose: Customizable contraction hierarchy for fast point-to-point routing on large road networks.
+//
+// Roads are the nodes of the graph and connections are the arcs. The hierarchy is built in two steps:
+// the contraction order and shortcut topology only depend on which roads are connected,
+// the shortcut weights ("metric") are then customized per vehicle type from the link costs.
+// Because the topology does not depend on the weights, a changed link cost only needs the
+// shortcuts above it to be recomputed.
+
+use crate::routing::Route;
+use crate::vehicle_type::VehicleType;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Reverse;
+
+// Shortcut weights for one vehicle type.
+// Every edge {lower, upper} has an upward weight (lower -> upper) and a downward weight (upper -> lower),
+// with the road the shortcut passes through, if it is a shortcut.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Metric{
+    link_costs: HashMap<(usize,usize),f32>,
+    up: Vec<f32>,
+    down: Vec<f32>,
+    up_via: Vec<Option<usize>>,
+    down_via: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractionHierarchy{
+    // Contraction rank of every road
+    rank: Vec<usize>,
+    // Edges to higher ranked roads: (road, edge index)
+    upward: Vec<Vec<(usize,usize)>>,
+    // Lowest ranked upper neighbour of every road, its parent in the elimination tree
+    parent: Vec<Option<usize>>,
+    // Edges to lower ranked roads, sorted by road: (road, edge index)
+    downward: Vec<Vec<(usize,usize)>>,
+    // Lower and upper road of every edge, stored bottom up
+    edges: Vec<(usize,usize)>,
+    edge_index: HashMap<(usize,usize),usize>,
+    metrics: HashMap<VehicleType,Metric>,
+}
+
+// Roads in parts smaller than this are not split any further.
+const DISSECTION_LEAF_SIZE: usize = 16;
+
+impl ContractionHierarchy{
+    // Contract the graph of the given links. The contraction order comes from nested dissection
+    // on the road positions, which keeps the number of shortcuts low on road networks.
+    pub fn new(positions:&[(f32,f32)],links:&[(usize,usize)]) -> ContractionHierarchy{
+        let road_count = positions.len();
+        let mut graph:Vec<Vec<usize>> = vec![Vec::new(); road_count];
+        for &(from,to) in links.iter(){
+            if from != to && from < road_count && to < road_count{
+                graph[from].push(to);
+                graph[to].push(from);
+            }
+        }
+
+        let mut order = Vec::with_capacity(road_count);
+        let mut side = vec![0u8; road_count];
+        dissect((0..road_count).collect(),positions,&graph,&mut side,&mut order);
+        let mut rank = vec![0; road_count];
+        for (i,&road) in order.iter().enumerate(){
+            rank[road] = i;
+        }
+
+        //Contract in order, connecting the remaining neighbours of every road to each other
+        let mut neighbours:Vec<HashSet<usize>> = graph.into_iter().map(|list| list.into_iter().collect()).collect();
+        let mut edges = Vec::new();
+        let mut edge_index = HashMap::new();
+        let mut upward = vec![Vec::new(); road_count];
+        let mut downward = vec![Vec::new(); road_count];
+        for &road in order.iter(){
+            let mut remaining:Vec<usize> = neighbours[road].drain().collect();
+            remaining.sort();
+            for &neighbour in remaining.iter(){
+                neighbours[neighbour].remove(&road);
+                for &other in remaining.iter(){
+                    if other != neighbour{
+                        neighbours[neighbour].insert(other);
+                    }
+                }
+                edge_index.insert((road,neighbour),edges.len());
+                upward[road].push((neighbour,edges.len()));
+                downward[neighbour].push((road,edges.len()));
+                edges.push((road,neighbour));
+            }
+        }
+        for list in downward.iter_mut(){
+            list.sort();
+        }
+        let parent:Vec<Option<usize>> = upward.iter().map(|list| list.iter().map(|&(upper,_)| upper).min_by_key(|&upper| rank[upper])).collect();
+
+        ContractionHierarchy{
+            rank,
+            upward,
+            parent,
+            downward,
+            edges,
+            edge_index,
+            metrics: HashMap::new(),
+        }
+    }
+
+    // Number of roads in the hierarchy.
+    pub fn road_count(&self) -> usize{
+        self.rank.len()
+    }
+
+    // Check if a link between two roads fits in the hierarchy without rebuilding it.
+    pub fn covers_link(&self,from:usize,to:usize) -> bool{
+        from == to || self.find_edge(from,to).is_some()
+    }
+
+    fn find_edge(&self,a:usize,b:usize) -> Option<usize>{
+        if a >= self.rank.len() || b >= self.rank.len(){
+            return None;
+        }
+        if self.rank[a] < self.rank[b]{
+            self.edge_index.get(&(a,b)).copied()
+        }
+        else{
+            self.edge_index.get(&(b,a)).copied()
+        }
+    }
+
+    // Compute the shortcut weights for a vehicle type from its link costs.
+    // Links missing from the map can't be used.
+    pub fn customize(&mut self,vehicle_type:VehicleType,link_costs:HashMap<(usize,usize),f32>){
+        let edge_count = self.edges.len();
+        let mut metric = Metric{
+            link_costs,
+            up: vec![f32::INFINITY; edge_count],
+            down: vec![f32::INFINITY; edge_count],
+            up_via: vec![None; edge_count],
+            down_via: vec![None; edge_count],
+        };
+        //Edges are stored bottom up, so the triangles below an edge are always done first
+        for edge in 0..edge_count{
+            self.customize_edge(&mut metric,edge);
+        }
+        self.metrics.insert(vehicle_type,metric);
+    }
+
+    // Recompute the weights of one edge from its link costs and the triangles below it.
+    // Returns true if any weight changed.
+    fn customize_edge(&self,metric:&mut Metric,edge:usize) -> bool{
+        let (lower,upper) = self.edges[edge];
+        let mut up = metric.link_costs.get(&(lower,upper)).copied().unwrap_or(f32::INFINITY);
+        let mut down = metric.link_costs.get(&(upper,lower)).copied().unwrap_or(f32::INFINITY);
+        let mut up_via = None;
+        let mut down_via = None;
+        for (road,edge_lower,edge_upper) in self.lower_triangles(lower,upper){
+            let through_up = metric.down[edge_lower] + metric.up[edge_upper];
+            if through_up < up{
+                up = through_up;
+                up_via = Some(road);
+            }
+            let through_down = metric.down[edge_upper] + metric.up[edge_lower];
+            if through_down < down{
+                down = through_down;
+                down_via = Some(road);
+            }
+        }
+        let changed = up != metric.up[edge] || down != metric.down[edge];
+        metric.up[edge] = up;
+        metric.down[edge] = down;
+        metric.up_via[edge] = up_via;
+        metric.down_via[edge] = down_via;
+        changed
+    }
+
+    // Roads below both ends of an edge: (lowest road, edge to lower end, edge to upper end)
+    fn lower_triangles(&self,lower:usize,upper:usize) -> Vec<(usize,usize,usize)>{
+        let mut triangles = Vec::new();
+        let (below_lower,below_upper) = (&self.downward[lower],&self.downward[upper]);
+        let (mut i,mut j) = (0,0);
+        while i < below_lower.len() && j < below_upper.len(){
+            if below_lower[i].0 < below_upper[j].0{
+                i += 1;
+            }
+            else if below_lower[i].0 > below_upper[j].0{
+                j += 1;
+            }
+            else{
+                triangles.push((below_lower[i].0,below_lower[i].1,below_upper[j].1));
+                i += 1;
+                j += 1;
+            }
+        }
+        triangles
+    }
+
+    // Change the cost of one link for a vehicle type and update the shortcuts that depend on it.
+    // Use f32::INFINITY to forbid the link.
+    pub fn update_link_cost(&mut self,vehicle_type:VehicleType,from:usize,to:usize,cost:f32){
+        let edge = match self.find_edge(from,to){
+            Some(edge) => edge,
+            None => return,
+        };
+        let mut metric = match self.metrics.remove(&vehicle_type){
+            Some(metric) => metric,
+            None => return,
+        };
+        metric.link_costs.insert((from,to),cost);
+
+        //Update edges bottom up, an edge only changes the edges above its lower road
+        let mut queue = BinaryHeap::new();
+        let mut queued = HashSet::new();
+        queue.push(Reverse((self.rank[self.edges[edge].0],edge)));
+        queued.insert(edge);
+        while let Some(Reverse((_,edge))) = queue.pop(){
+            queued.remove(&edge);
+            if !self.customize_edge(&mut metric,edge){
+                continue;
+            }
+            let (lower,upper) = self.edges[edge];
+            for &(other,_) in self.upward[lower].iter(){
+                if other == upper{
+                    continue;
+                }
+                if let Some(dependent) = self.find_edge(upper,other){
+                    if queued.insert(dependent){
+                        queue.push(Reverse((self.rank[self.edges[dependent].0],dependent)));
+                    }
+                }
+            }
+        }
+        self.metrics.insert(vehicle_type,metric);
+    }
+
+    // Find the cheapest route between two roads for a vehicle type.
+    pub fn query(&self,vehicle_type:VehicleType,start_road:usize,destination_road:usize) -> Option<Route>{
+        let metric = self.metrics.get(&vehicle_type)?;
+        if start_road >= self.rank.len() || destination_road >= self.rank.len(){
+            return None;
+        }
+        if start_road == destination_road{
+            return Some(Route{
+                roads: vec![start_road],
+                cost: 0.0,
+            });
+        }
+
+        //Search upward from both ends, the routes meet at a common ancestor
+        let (ancestors,forward,forward_previous) = self.upward_search(start_road,&metric.up);
+        let (_,backward,backward_previous) = self.upward_search(destination_road,&metric.down);
+        let mut best:Option<(f32,usize)> = None;
+        for &road in ancestors.iter(){
+            let total = forward[road] + backward[road];
+            if total.is_finite() && best.is_none_or(|(cost,_)| total < cost){
+                best = Some((total,road));
+            }
+        }
+        let (cost,meet) = best?;
+
+        //Unpack the forward half from the start to the meeting road
+        let mut forward_roads = vec![meet];
+        let mut road = meet;
+        while road != start_road{
+            road = forward_previous[road];
+            forward_roads.push(road);
+        }
+        forward_roads.reverse();
+        let mut roads = vec![start_road];
+        for link in forward_roads.windows(2){
+            self.unpack(metric,link[0],link[1],&mut roads);
+        }
+
+        //Unpack the backward half from the meeting road to the destination
+        let mut road = meet;
+        while road != destination_road{
+            let next = backward_previous[road];
+            self.unpack(metric,road,next,&mut roads);
+            road = next;
+        }
+
+        Some(Route{
+            roads,
+            cost,
+        })
+    }
+
+    // Search upward from a road, with the weight of each edge taken from the given side of the metric.
+    // Every road reachable upward is an ancestor in the elimination tree, so the ancestors are
+    // scanned in rank order instead of running Dijkstra. Returns the ancestors with the distances
+    // and previous roads indexed by road.
+    fn upward_search(&self,source:usize,weights:&[f32]) -> (Vec<usize>,Vec<f32>,Vec<usize>){
+        let mut ancestors = Vec::new();
+        let mut distances = vec![f32::INFINITY; self.rank.len()];
+        let mut previous = vec![usize::MAX; self.rank.len()];
+        distances[source] = 0.0;
+        let mut road = Some(source);
+        while let Some(current) = road{
+            ancestors.push(current);
+            let distance = distances[current];
+            if distance.is_finite(){
+                for &(upper,edge) in self.upward[current].iter(){
+                    let total = distance + weights[edge];
+                    if total < distances[upper]{
+                        distances[upper] = total;
+                        previous[upper] = current;
+                    }
+                }
+            }
+            road = self.parent[current];
+        }
+        (ancestors,distances,previous)
+    }
+
+    // Expand the link from -> to into the original roads, appending everything after `from`.
+    fn unpack(&self,metric:&Metric,from:usize,to:usize,roads:&mut Vec<usize>){
+        let edge = self.find_edge(from,to).expect("Route uses a link outside the hierarchy.");
+        let via = if self.rank[from] < self.rank[to]{
+            metric.up_via[edge]
+        }
+        else{
+            metric.down_via[edge]
+        };
+        match via{
+            Some(via) => {
+                self.unpack(metric,from,via,roads);
+                self.unpack(metric,via,to,roads);
+            }
+            None => roads.push(to),
+        }
+    }
+}
+
+// Order roads by nested dissection: split the part in two along its longer side,
+// order both halves first and the roads separating them last.
+fn dissect(roads:Vec<usize>,positions:&[(f32,f32)],graph:&[Vec<usize>],side:&mut [u8],order:&mut Vec<usize>){
+    if roads.len() <= DISSECTION_LEAF_SIZE{
+        order.extend(roads);
+        return;
+    }
+    let (mut min_x,mut min_y,mut max_x,mut max_y) = (f32::INFINITY,f32::INFINITY,f32::NEG_INFINITY,f32::NEG_INFINITY);
+    for &road in roads.iter(){
+        let (x,y) = positions[road];
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    let mut sorted = roads;
+    if max_x - min_x >= max_y - min_y{
+        sorted.sort_by_key(|&road| (OrderedFloat(positions[road].0),road));
+    }
+    else{
+        sorted.sort_by_key(|&road| (OrderedFloat(positions[road].1),road));
+    }
+    let mut second = sorted.split_off(sorted.len()/2);
+    let mut first = sorted;
+    for &road in first.iter(){
+        side[road] = 1;
+    }
+    for &road in second.iter(){
+        side[road] = 2;
+    }
+
+    //The separator covers every link between the halves, roads with the most crossing links are taken first
+    let mut crossing:Vec<(usize,usize)> = first.iter().chain(second.iter()).map(|&road| {
+        let other = 3 - side[road];
+        (graph[road].iter().filter(|&&neighbour| side[neighbour] == other).count(),road)
+    }).filter(|&(count,_)| count > 0).collect();
+    crossing.sort_by(|a,b| b.cmp(a));
+    let mut separator = Vec::new();
+    for (_,road) in crossing{
+        let other = 3 - side[road];
+        if graph[road].iter().any(|&neighbour| side[neighbour] == other){
+            //Taking the road out of its half covers all of its links
+            side[road] = 0;
+            separator.push(road);
+        }
+    }
+    first.retain(|&road| side[road] != 0);
+    second.retain(|&road| side[road] != 0);
+    for &road in first.iter().chain(second.iter()){
+        side[road] = 0;
+    }
+
+    dissect(first,positions,graph,side,order);
+    dissect(second,positions,graph,side,order);
+    order.extend(separator);
+}
+
+#[cfg(test)]
+pub(crate) mod tests{
+    use crate::comp::World;
+    use crate::vehicle_type::VehicleType;
+    use std::collections::HashSet;
+
+    // Two way streets between the nodes of a grid, slightly shifted so few routes tie.
+    // Every road turns into the roads leaving its end, except back the way it came.
+    pub(crate) fn grid(size:usize) -> World{
+        let node = |x:usize,y:usize| ((x * 100 + (y * 7 % 5) * 3) as f32,(y * 100 + (x * 3 % 4) * 5) as f32,0.0);
+        let mut ends = Vec::new();
+        for x in 0..size{
+            for y in 0..size{
+                if x + 1 < size{
+                    ends.push(((x,y),(x + 1,y)));
+                    ends.push(((x + 1,y),(x,y)));
+                }
+                if y + 1 < size{
+                    ends.push(((x,y),(x,y + 1)));
+                    ends.push(((x,y + 1),(x,y)));
+                }
+            }
+        }
+        let mut world = World::new();
+        for &(from,to) in ends.iter(){
+            world.add_road(node(from.0,from.1),node(to.0,to.1),1,13.9,Vec::new(),Vec::new(),13.9);
+        }
+        for (road,&(from,to)) in ends.iter().enumerate(){
+            for (next_road,&(next_from,next_to)) in ends.iter().enumerate(){
+                if next_from == to && next_to != from{
+                    world.add_connection(road,next_road,None,0.0,None);
+                }
+            }
+        }
+        world
+    }
+
+    // Every route of the index costs the same as the A* route, and both find the same pairs.
+    fn assert_matches_search(world:&World,vehicle_types:&[VehicleType]){
+        let index = world.route_index.as_ref().expect("The route index is not built");
+        for &vehicle_type in vehicle_types{
+            for start in 0..world.roads.len(){
+                for destination in 0..world.roads.len(){
+                    let fast = index.query(vehicle_type,start,destination);
+                    let searched = world.find_shortest_path_avoiding(start,destination,vehicle_type,&HashSet::new(),&HashSet::new());
+                    match (fast,searched){
+                        (Some(fast),Some(searched)) => {
+                            assert!((fast.cost - searched.cost).abs() < 1e-2,"{:?} from {} to {}: {} against {}",vehicle_type,start,destination,fast.cost,searched.cost);
+                            assert!((world.route_cost(&fast.roads) - fast.cost).abs() < 1e-2,"Route {:?} does not cost {}",fast.roads,fast.cost);
+                            assert_eq!((fast.roads[0],fast.roads[fast.roads.len() - 1]),(start,destination));
+                        }
+                        (None,None) => {}
+                        (fast,searched) => panic!("{:?} from {} to {}: {:?} against {:?}",vehicle_type,start,destination,fast,searched),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn query_matches_search(){
+        let mut world = grid(5);
+        world.build_route_index();
+        assert_matches_search(&world,&[VehicleType::Car,VehicleType::Truck]);
+    }
+
+    #[test]
+    fn turn_penalty_updates_match_search(){
+        let mut world = grid(5);
+        world.build_route_index();
+        let links:Vec<(usize,usize)> = world.connections.keys().copied().filter(|&(from,_)| from % 3 == 0).collect();
+        for (from,to) in links{
+            world.set_turn_penalty(from,to,250.0);
+        }
+        assert!(world.route_index.is_some(),"Penalties on existing links update the index");
+        assert_matches_search(&world,&[VehicleType::Car]);
+    }
+
+    #[test]
+    fn restricted_movements_match_search(){
+        let mut world = grid(5);
+        world.build_route_index();
+        //Only buses may take some turns, cutting off parts of the grid for the other types
+        let links:Vec<(usize,usize)> = world.connections.keys().copied().filter(|&(from,to)| (from + to) % 4 == 0).collect();
+        for (from,to) in links{
+            world.add_connection(from,to,Some(vec![VehicleType::Bus]),0.0,None);
+        }
+        assert!(world.route_index.is_some(),"Restrictions on existing links update the index");
+        assert_matches_search(&world,&VehicleType::ALL);
+    }
+
+    #[test]
+    fn new_connection_drops_index(){
+        let mut world = grid(4);
+        world.build_route_index();
+        //A U-turn is not in the grid, so the index no longer covers the network
+        let (from,to) = (0..world.roads.len()).flat_map(|road| (0..world.roads.len()).map(move |other| (road,other)))
+            .find(|&(road,other)| world.roads[road].to == world.roads[other].from && world.roads[road].from == world.roads[other].to)
+            .expect("The grid has two way streets");
+        world.add_connection(from,to,None,0.0,None);
+        assert!(world.route_index.is_none());
+        world.build_route_index();
+        assert_matches_search(&world,&[VehicleType::Car]);
+    }
+}
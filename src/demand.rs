@@ -0,0 +1,220 @@
+// Purpose: Spawn vehicles and paint origin-destination flows with the mouse, for quick stress tests of a junction.
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+use crate::charts::pointer_over_ui;
+use crate::comp::{Departure, VehicleParams, World, ARRIVAL_MARGIN};
+use crate::gui::TIMELINE_HEIGHT;
+use crate::history::{Edit, History};
+use crate::inspect::pick_road_position;
+use crate::replay::Replay;
+use crate::rng::DEMAND_STREAM;
+use crate::vehicle_type::VehicleType;
+use rand::Rng;
+
+// Cursor movement in pixels after which a right press pans the camera instead of spawning a vehicle.
+const CLICK_PIXELS: f32 = 4.0;
+
+// Flow rates in vehicles per hour, chosen with page up and page down.
+const FLOW_RATES: [f32; 8] = [60.0, 120.0, 300.0, 600.0, 900.0, 1200.0, 1800.0, 3600.0];
+
+// How long a painted flow keeps sending vehicles, in seconds from its creation.
+const FLOW_DURATION: f32 = 900.0;
+
+// Number keys choosing the type of spawned vehicles, in the order of VehicleType::ALL.
+const TYPE_KEYS: [KeyCode; 5] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5];
+
+impl World{
+    // Roads a vehicle type can reach from a road by following the successors it may turn into, in index order.
+    pub fn reachable_roads(&self,start:usize,vehicle_type:VehicleType) -> Vec<usize>{
+        let mut seen = vec![false; self.roads.len()];
+        let mut pending = vec![start];
+        let mut reachable = Vec::new();
+        while let Some(road) = pending.pop(){
+            for next_road in self.get_adjacent_roads(road,vehicle_type){
+                if next_road < seen.len() && !seen[next_road]{
+                    seen[next_road] = true;
+                    reachable.push(next_road);
+                    pending.push(next_road);
+                }
+            }
+        }
+        //The random destination then only depends on the network
+        reachable.sort();
+        reachable
+    }
+
+    fn arrival_position(&self,road:usize) -> f32{
+        (self.roads[road].length.into_inner() - ARRIVAL_MARGIN).max(0.0)
+    }
+
+    // Add a standing vehicle at a position on a road, driving to a random road reachable from there, and return its id.
+    pub fn spawn_vehicle(&mut self,road:usize,position:f32,vehicle_type:VehicleType) -> usize{
+        let reachable = self.reachable_roads(road,vehicle_type);
+        //A road without successors is its own destination
+        let destination = if reachable.is_empty() { road } else { reachable[self.rng.stream(DEMAND_STREAM).gen_range(0..reachable.len())] };
+        let (acceleration,break_decceleration,watch_distance) = self.sample_vehicle_params(vehicle_type);
+        let destination_position = self.arrival_position(destination);
+        self.add_vehicle(VehicleParams{vehicle_type,position,velocity: 0.0,acceleration,break_decceleration,on_road: road,watch_distance,destination,destination_position});
+        self.next_vehicle_id - 1
+    }
+
+    // Departures of a flow between two roads from now on, arriving at random with a mean rate in vehicles per hour.
+    // Fails when the vehicle type can't reach the destination road.
+    pub fn flow_departures(&mut self,from_road:usize,to_road:usize,vehicles_per_hour:f32,duration:f32,vehicle_type:VehicleType) -> Result<Vec<Departure>,String>{
+        if !self.can_reach(from_road,to_road,vehicle_type){
+            return Err(format!("A {} can't drive from road {} to road {}", vehicle_type.name(), from_road, to_road));
+        }
+        let end = self.time + duration;
+        let destination_position = self.arrival_position(to_road);
+        let mut departures = Vec::new();
+        let mut time = self.time;
+        loop{
+            //Exponential headways make the arrivals a Poisson process
+            let draw:f32 = self.rng.stream(DEMAND_STREAM).gen();
+            time += -(1.0 - draw).ln() * 3600.0 / vehicles_per_hour;
+            if time >= end{
+                break;
+            }
+            let (acceleration,break_decceleration,watch_distance) = self.sample_vehicle_params(vehicle_type);
+            departures.push(Departure{
+                time,
+                vehicle_type,
+                position: 0.0,
+                velocity: 0.0,
+                acceleration,
+                break_decceleration,
+                on_road: from_road,
+                watch_distance,
+                destination: to_road,
+                destination_position,
+                route: Vec::new(),
+            });
+        }
+        Ok(departures)
+    }
+}
+
+// Type of the spawned vehicles and rate of the painted flows.
+#[derive(Resource)]
+pub struct Spawner{
+    pub vehicle_type: VehicleType,
+    // Index into the flow rates
+    pub rate: usize,
+    // Cursor position of the right press and the road a flow starts from when control was held
+    press_cursor: Option<Vec2>,
+    flow_origin: Option<usize>,
+}
+
+impl Default for Spawner{
+    fn default() -> Spawner{
+        Spawner{
+            vehicle_type: VehicleType::Car,
+            rate: 3,
+            press_cursor: None,
+            flow_origin: None,
+        }
+    }
+}
+
+// Controls of the spawner with its current settings.
+#[derive(Component)]
+pub struct SpawnerText;
+
+// Spawn the spawner panel in the bottom right corner, above the replay timeline.
+pub fn spawn_spawner_panel(mut commands: Commands, asset_server: Res<AssetServer>){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+            font_size: 16.0,
+            color: Color::WHITE,
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect { right: Val::Px(10.0), bottom: Val::Px(TIMELINE_HEIGHT + 10.0), ..default() },
+        ..default()
+    }),SpawnerText));
+}
+
+// A right click on a road spawns a vehicle there, control with a right drag from one road to another adds a flow between them.
+// Both can be undone.
+#[allow(clippy::too_many_arguments)]
+pub fn spawner_mouse_system(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    replay: Option<Res<Replay>>,
+    mut spawner: ResMut<Spawner>,
+    mut world: ResMut<World>,
+    mut history: ResMut<History>,
+    mut contexts: EguiContexts) {
+    //Presses on the charts window don't spawn
+    if replay.is_some() || (mouse.just_pressed(MouseButton::Right) && pointer_over_ui(&mut contexts)) {
+        return;
+    }
+    let Some(cursor) = windows.get_single().ok().and_then(|window| window.cursor_position()) else { return };
+    let Some(ray) = cameras.get_single().ok().and_then(|(camera,transform)| camera.viewport_to_world(transform,cursor)) else { return };
+    if mouse.just_pressed(MouseButton::Right) {
+        spawner.press_cursor = Some(cursor);
+        spawner.flow_origin = if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+            pick_road_position(&world,ray).map(|(road,_)| road)
+        }
+        else {
+            None
+        };
+    }
+    if !mouse.just_released(MouseButton::Right) {
+        return;
+    }
+    let Some(press_cursor) = spawner.press_cursor.take() else { return };
+    let flow_origin = spawner.flow_origin.take();
+    let Some((road,position)) = pick_road_position(&world,ray) else { return };
+    match flow_origin {
+        Some(from_road) => {
+            match world.flow_departures(from_road,road,FLOW_RATES[spawner.rate],FLOW_DURATION,spawner.vehicle_type) {
+                Ok(departures) => {
+                    println!("Added a flow of {} vehicles from road {} to road {}", departures.len(), from_road, road);
+                    history.apply(&mut world,Edit::AddDepartures(departures));
+                }
+                Err(error) => println!("No flow added: {}", error),
+            }
+        }
+        //Right drags pan and alt with a right drag orbits
+        None if press_cursor.distance(cursor) <= CLICK_PIXELS && !keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) => {
+            let id = world.spawn_vehicle(road,position,spawner.vehicle_type);
+            history.record(Edit::RemoveVehicle(id));
+        }
+        None => {}
+    }
+}
+
+// Number keys 1 to 5 choose the vehicle type, page up and page down the flow rate.
+pub fn spawner_key_system(keys: Res<Input<KeyCode>>,mut spawner: ResMut<Spawner>) {
+    if let Some(index) = TYPE_KEYS.iter().position(|&key| keys.just_pressed(key)) {
+        spawner.vehicle_type = VehicleType::ALL[index];
+    }
+    if keys.just_pressed(KeyCode::PageUp) {
+        spawner.rate = (spawner.rate + 1).min(FLOW_RATES.len() - 1);
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        spawner.rate = spawner.rate.saturating_sub(1);
+    }
+}
+
+pub fn spawner_panel_system(spawner: Res<Spawner>,mut text_query: Query<&mut Text, With<SpawnerText>>) {
+    if !spawner.is_changed() {
+        return;
+    }
+    let mut text = format!(
+        "Right click: spawn a {} (1-5)\nCtrl+right drag: flow of {} veh/h for {} min (PgUp/PgDn)",
+        spawner.vehicle_type.name(), FLOW_RATES[spawner.rate], FLOW_DURATION / 60.0);
+    if let Some(road) = spawner.flow_origin {
+        text += &format!("\nFlow from road {}", road);
+    }
+    for mut panel in &mut text_query {
+        panel.sections[0].value = text.clone();
+    }
+}
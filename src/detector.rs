@@ -0,0 +1,334 @@
+// Purpose: Virtual point (loop) and area detectors aggregating traffic measurements per interval.
+
+use crate::comp::World;
+use crate::rng::csv_writer_with_seed;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Default aggregation interval in seconds.
+const DEFAULT_INTERVAL: f32 = 60.0;
+
+// Speed below which a vehicle counts as queued, in m/s.
+const QUEUE_SPEED: f32 = 2.0;
+
+// Largest gap between queued vehicles, in meters.
+const QUEUE_GAP: f32 = 20.0;
+
+fn default_interval() -> f32{
+    DEFAULT_INTERVAL
+}
+
+// Detector definition in the scenario file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DetectorData{
+    Point{
+        id: String,
+        road: usize,
+        #[serde(default)]
+        lane: u8,
+        position: f32,
+        #[serde(default = "default_interval")]
+        interval: f32,
+    },
+    Area{
+        id: String,
+        road: usize,
+        from: f32,
+        to: f32,
+        #[serde(default = "default_interval")]
+        interval: f32,
+    },
+}
+
+// Point detector measurement over one interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointMeasurement{
+    pub start: f32,
+    pub end: f32,
+    pub count: u32,
+    // Fraction of the interval a vehicle was over the detector.
+    pub occupancy: f32,
+    // Mean speed of the vehicles passing the detector, None without vehicles.
+    pub speed: Option<f32>,
+}
+
+// Area detector measurement over one interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AreaMeasurement{
+    pub start: f32,
+    pub end: f32,
+    // Mean number of vehicles per kilometer.
+    pub density: f32,
+    // Mean speed of the vehicles in the area, None without vehicles.
+    pub mean_speed: Option<f32>,
+    // Longest queue reaching back from the end of the area, in meters.
+    pub queue_length: f32,
+}
+
+// Detector at a point of a road lane, counting vehicles whose front passes it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointDetector{
+    pub id: String,
+    pub road: usize,
+    pub lane: u8,
+    pub position: f32,
+    pub interval: f32,
+    pub measurements: Vec<PointMeasurement>,
+    // Whether a vehicle is over the detector now, for actuated control.
+    pub occupied: bool,
+    period_start: f32,
+    count: u32,
+    occupied_time: f32,
+    speed_sum: f32,
+    last_positions: HashMap<usize,(usize,f32)>,
+}
+
+// Detector covering a span of a road.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AreaDetector{
+    pub id: String,
+    pub road: usize,
+    pub from: f32,
+    pub to: f32,
+    pub interval: f32,
+    pub measurements: Vec<AreaMeasurement>,
+    period_start: f32,
+    sampled_time: f32,
+    vehicle_time: f32,
+    speed_sum: f32,
+    speed_samples: u32,
+    max_queue: f32,
+}
+
+impl World{
+    // Add a detector, rejecting intervals that would never or always end.
+    pub fn add_detector(&mut self,data:DetectorData) -> Result<(),String>{
+        let (id,interval) = match &data{
+            DetectorData::Point{id,interval,..} | DetectorData::Area{id,interval,..} => (id,*interval),
+        };
+        if !(interval > 0.0 && interval.is_finite()){
+            return Err(format!("Detector {} needs an interval greater than 0, got {}", id, interval));
+        }
+        match data{
+            DetectorData::Point{id,road,lane,position,interval} => self.point_detectors.push(PointDetector{
+                id,
+                road,
+                lane,
+                position,
+                interval,
+                measurements: Vec::new(),
+                occupied: false,
+                period_start: self.time,
+                count: 0,
+                occupied_time: 0.0,
+                speed_sum: 0.0,
+                last_positions: HashMap::new(),
+            }),
+            DetectorData::Area{id,road,from,to,interval} => self.area_detectors.push(AreaDetector{
+                id,
+                road,
+                from: from.min(to),
+                to: from.max(to),
+                interval,
+                measurements: Vec::new(),
+                period_start: self.time,
+                sampled_time: 0.0,
+                vehicle_time: 0.0,
+                speed_sum: 0.0,
+                speed_samples: 0,
+                max_queue: 0.0,
+            }),
+        }
+        Ok(())
+    }
+
+    // Definitions of the detectors, as given in the scenario file.
+    pub fn detector_data(&self) -> Vec<DetectorData>{
+        let points = self.point_detectors.iter().map(|detector| DetectorData::Point{
+            id: detector.id.clone(),
+            road: detector.road,
+            lane: detector.lane,
+            position: detector.position,
+            interval: detector.interval,
+        });
+        let areas = self.area_detectors.iter().map(|detector| DetectorData::Area{
+            id: detector.id.clone(),
+            road: detector.road,
+            from: detector.from,
+            to: detector.to,
+            interval: detector.interval,
+        });
+        points.chain(areas).collect()
+    }
+
+    // Observe the vehicles after a simulation step of t seconds.
+    pub fn update_detectors(&mut self,t:f32){
+        for detector in self.point_detectors.iter_mut(){
+            let mut occupied = false;
+            let mut positions = HashMap::new();
+            //Vehicles drive in single file on the rightmost lane, detectors on other lanes see no traffic
+            let lane_vehicles = self.vehicles.iter().filter(|_| detector.lane == 0);
+            for vehicle in lane_vehicles{
+                positions.insert(vehicle.id,(vehicle.on_road,vehicle.position));
+                if vehicle.on_road != detector.road{
+                    continue;
+                }
+                //The front passed the detector during this step
+                let before = match detector.last_positions.get(&vehicle.id){
+                    Some(&(road,position)) if road == detector.road => position < detector.position,
+                    Some(_) => true,
+                    None => false,
+                };
+                if before && vehicle.position >= detector.position{
+                    detector.count += 1;
+                    detector.speed_sum += vehicle.velocity;
+                }
+                let length = vehicle.vehicle_type.params().length;
+                if vehicle.position >= detector.position && vehicle.position - length <= detector.position{
+                    occupied = true;
+                }
+            }
+            detector.last_positions = positions;
+            detector.occupied = occupied;
+            if occupied{
+                detector.occupied_time += t;
+            }
+
+            if self.time >= detector.period_start + detector.interval{
+                let duration = self.time - detector.period_start;
+                detector.measurements.push(PointMeasurement{
+                    start: detector.period_start,
+                    end: self.time,
+                    count: detector.count,
+                    occupancy: if duration > 0.0 { (detector.occupied_time / duration).min(1.0) } else { 0.0 },
+                    speed: if detector.count > 0 { Some(detector.speed_sum / detector.count as f32) } else { None },
+                });
+                detector.period_start = self.time;
+                detector.count = 0;
+                detector.occupied_time = 0.0;
+                detector.speed_sum = 0.0;
+            }
+        }
+
+        for detector in self.area_detectors.iter_mut(){
+            let mut inside:Vec<(f32,f32,f32)> = self.vehicles.iter()
+                .filter(|vehicle| vehicle.on_road == detector.road && vehicle.position >= detector.from && vehicle.position <= detector.to)
+                .map(|vehicle| (vehicle.position,vehicle.velocity,vehicle.vehicle_type.params().length))
+                .collect();
+            detector.sampled_time += t;
+            detector.vehicle_time += inside.len() as f32 * t;
+            for &(_,velocity,_) in inside.iter(){
+                detector.speed_sum += velocity;
+                detector.speed_samples += 1;
+            }
+
+            //The queue starts with a slow vehicle at the end of the area and continues upstream while vehicles are close and slow
+            inside.sort_by(|a,b| b.0.total_cmp(&a.0));
+            let mut queue_end = detector.to;
+            let mut queue_length = 0.0f32;
+            for &(position,velocity,length) in inside.iter(){
+                if velocity >= QUEUE_SPEED || queue_end - position > QUEUE_GAP{
+                    break;
+                }
+                queue_end = (position - length).max(detector.from);
+                queue_length = detector.to - queue_end;
+            }
+            detector.max_queue = detector.max_queue.max(queue_length);
+
+            if self.time >= detector.period_start + detector.interval{
+                let span = (detector.to - detector.from).max(f32::EPSILON);
+                detector.measurements.push(AreaMeasurement{
+                    start: detector.period_start,
+                    end: self.time,
+                    density: if detector.sampled_time > 0.0 { detector.vehicle_time / detector.sampled_time / span * 1000.0 } else { 0.0 },
+                    mean_speed: if detector.speed_samples > 0 { Some(detector.speed_sum / detector.speed_samples as f32) } else { None },
+                    queue_length: detector.max_queue,
+                });
+                detector.period_start = self.time;
+                detector.sampled_time = 0.0;
+                detector.vehicle_time = 0.0;
+                detector.speed_sum = 0.0;
+                detector.speed_samples = 0;
+                detector.max_queue = 0.0;
+            }
+        }
+    }
+
+    // Write the finished intervals of the point and area detectors to two CSV files.
+    pub fn save_detectors(&self,point_path:&Path,area_path:&Path) -> Result<(),String>{
+        let optional = |value:Option<f32>| value.map_or(String::new(),|value| value.to_string());
+
+        let mut writer = csv_writer_with_seed(point_path,self.rng.seed())?;
+        writer.write_record(["id","road","lane","position","start","end","count","occupancy","speed"]).map_err(|e| e.to_string())?;
+        for detector in self.point_detectors.iter(){
+            for measurement in detector.measurements.iter(){
+                writer.write_record(&[
+                    detector.id.clone(),
+                    detector.road.to_string(),
+                    detector.lane.to_string(),
+                    detector.position.to_string(),
+                    measurement.start.to_string(),
+                    measurement.end.to_string(),
+                    measurement.count.to_string(),
+                    measurement.occupancy.to_string(),
+                    optional(measurement.speed),
+                ]).map_err(|e| e.to_string())?;
+            }
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+
+        let mut writer = csv_writer_with_seed(area_path,self.rng.seed())?;
+        writer.write_record(["id","road","from","to","start","end","density","mean_speed","queue_length"]).map_err(|e| e.to_string())?;
+        for detector in self.area_detectors.iter(){
+            for measurement in detector.measurements.iter(){
+                writer.write_record(&[
+                    detector.id.clone(),
+                    detector.road.to_string(),
+                    detector.from.to_string(),
+                    detector.to.to_string(),
+                    measurement.start.to_string(),
+                    measurement.end.to_string(),
+                    measurement.density.to_string(),
+                    optional(measurement.mean_speed),
+                    measurement.queue_length.to_string(),
+                ]).map_err(|e| e.to_string())?;
+            }
+        }
+        writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::contraction::tests::grid;
+    use crate::phy::update_comp;
+    use crate::vehicle_type::VehicleType;
+
+    #[test]
+    fn point_detector_counts_a_passing_vehicle(){
+        let mut world = grid(2);
+        world.add_detector(DetectorData::Point{id: "p".to_string(),road: 0,lane: 0,position: 30.0,interval: 10.0}).unwrap();
+        world.spawn_vehicle(0,0.0,VehicleType::Car);
+        for _ in 0..120{
+            update_comp(0.1,&mut world);
+        }
+        let measurement = &world.point_detectors[0].measurements[0];
+        assert_eq!(measurement.count,1);
+        assert!(measurement.end - measurement.start < 10.5);
+        assert!(measurement.occupancy > 0.0);
+        assert!(measurement.speed.is_some_and(|speed| speed > 0.0));
+    }
+
+    #[test]
+    fn intervals_must_be_positive(){
+        let mut world = grid(2);
+        for interval in [0.0,-5.0,f32::NAN]{
+            assert!(world.add_detector(DetectorData::Point{id: "p".to_string(),road: 0,lane: 0,position: 30.0,interval}).is_err());
+            assert!(world.add_detector(DetectorData::Area{id: "a".to_string(),road: 0,from: 10.0,to: 50.0,interval}).is_err());
+        }
+        assert!(world.point_detectors.is_empty() && world.area_detectors.is_empty());
+    }
+}
@@ -0,0 +1,441 @@
+// Purpose: In-app road network editor: draw roads as polylines, drag their ends, edit their limits and connect them.
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+use crate::charts::pointer_over_ui;
+use crate::comp::{Connection, World, ARRIVAL_MARGIN, JUNCTION_SPEED};
+use crate::history::{Edit, History};
+use crate::inspect::{pick_road, Selection};
+use crate::replay::Replay;
+use ordered_float::OrderedFloat;
+
+// Clicks closer than this to a road end snap to it, in meters.
+const SNAP_DISTANCE: f32 = 10.0;
+
+// Road ends closer than this are the same point, in meters.
+const SAME_POINT_DISTANCE: f32 = 0.01;
+
+// Cursor movement in pixels after which a press on a road end becomes a drag.
+const DRAG_PIXELS: f32 = 4.0;
+
+// Speed limit of new roads in m/s, 50 km/h.
+const DEFAULT_SPEED_LIMIT: f32 = 13.9;
+
+const MAX_LANES: u8 = 8;
+
+// Color of the vertices and segments of the road being drawn.
+const MARKER_COLOR: Color = Color::rgb(1.0, 0.85, 0.2);
+
+pub(crate) type Point = (f32,f32,f32);
+
+fn distance(a:Point,b:Point) -> f32{
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2) + (b.2 - a.2).powi(2)).sqrt()
+}
+
+// Key of the road end in the obstacle map, as add_road inserts it.
+fn end_key(length:f32) -> OrderedFloat<f32>{
+    OrderedFloat((length * 10.0).round() / 10.0)
+}
+
+impl World{
+    // Move the ends of a road, its end speed limit moves with the road end.
+    // Vehicles and departures beyond the end of a shortened road are moved back to it, and arrive before it.
+    pub fn set_road_points(&mut self,index:usize,from:Point,to:Point){
+        let road = &mut self.roads[index];
+        road.obstacle_map.remove(&end_key(road.length.into_inner()));
+        road.from = from;
+        road.to = to;
+        road.length = OrderedFloat(distance(from,to));
+        road.obstacle_map.insert(end_key(road.length.into_inner()),road.end_speed_limit);
+        let length = road.length.into_inner();
+        let arrival = (length - ARRIVAL_MARGIN).max(0.0);
+        for i in 0..self.vehicles.len(){
+            if self.vehicles[i].on_road == index && self.vehicles[i].position > length{
+                let vehicle = self.vehicles[i].clone();
+                self.clear_obstacle(&vehicle);
+                self.vehicles[i].position = length;
+                let vehicle = self.vehicles[i].clone();
+                self.place_obstacle(&vehicle);
+            }
+            if self.vehicles[i].destination == index{
+                self.vehicles[i].destination_position = self.vehicles[i].destination_position.min(arrival);
+            }
+        }
+        for departure in self.departures.iter_mut(){
+            if departure.on_road == index{
+                departure.position = departure.position.min(length);
+            }
+            if departure.destination == index{
+                departure.destination_position = departure.destination_position.min(arrival);
+            }
+        }
+        //Road lengths are route costs
+        self.route_index = None;
+    }
+
+    // Move every road end at a point, so the roads meeting there stay together.
+    pub fn move_road_point(&mut self,point:Point,new_point:Point){
+        for index in 0..self.roads.len(){
+            let (from,to) = (self.roads[index].from,self.roads[index].to);
+            let moved_from = distance(from,point) <= SAME_POINT_DISTANCE;
+            let moved_to = distance(to,point) <= SAME_POINT_DISTANCE;
+            if moved_from || moved_to{
+                self.set_road_points(index,if moved_from { new_point } else { from },if moved_to { new_point } else { to });
+            }
+        }
+    }
+
+    // Change the lanes and speed limits of a road.
+    pub fn set_road_limits(&mut self,index:usize,lanes:u8,speed_limit:f32,end_speed_limit:f32){
+        let road = &mut self.roads[index];
+        road.lanes = lanes;
+        road.speed_limit = speed_limit;
+        road.end_speed_limit = end_speed_limit;
+        road.obstacle_map.insert(end_key(road.length.into_inner()),end_speed_limit);
+    }
+
+    // Closest road end within a distance of a point.
+    pub fn road_end_near(&self,point:Point,radius:f32) -> Option<Point>{
+        self.roads.iter()
+            .flat_map(|road| [road.from,road.to])
+            .map(|end| (end,distance(end,point)))
+            .filter(|&(_,end_distance)| end_distance <= radius)
+            .min_by_key(|&(_,end_distance)| OrderedFloat(end_distance))
+            .map(|(end,_)| end)
+    }
+
+    // Add a road for every segment of a polyline, connected in order, and return their indices.
+    // Roads ending at the first point lead into the polyline and roads starting at the last point continue it.
+    pub fn add_polyline(&mut self,points:&[Point],lanes:u8,speed_limit:f32,end_speed_limit:f32) -> Vec<usize>{
+        let (Some(&first),Some(&last)) = (points.first(),points.last()) else { return Vec::new() };
+        let incoming:Vec<usize> = (0..self.roads.len()).filter(|&road| distance(self.roads[road].to,first) <= SAME_POINT_DISTANCE).collect();
+        let outgoing:Vec<usize> = (0..self.roads.len()).filter(|&road| distance(self.roads[road].from,last) <= SAME_POINT_DISTANCE).collect();
+
+        let mut added:Vec<usize> = Vec::new();
+        for segment in points.windows(2){
+            //Repeated clicks on the same point add no road
+            if distance(segment[0],segment[1]) <= SAME_POINT_DISTANCE{
+                continue;
+            }
+            self.add_road(segment[0],segment[1],lanes,speed_limit,Vec::new(),Vec::new(),speed_limit);
+            let index = self.roads.len() - 1;
+            if let Some(&previous) = added.last(){
+                self.add_connection(previous,index,None,0.0,None);
+            }
+            added.push(index);
+        }
+        if let (Some(&first_road),Some(&last_road)) = (added.first(),added.last()){
+            //Vehicles only slow down at the end of the polyline
+            self.set_road_limits(last_road,lanes,speed_limit,end_speed_limit);
+            for road in incoming{
+                self.add_connection(road,first_road,None,0.0,None);
+            }
+            for road in outgoing{
+                self.add_connection(last_road,road,None,0.0,None);
+            }
+        }
+        added
+    }
+}
+
+// State of the road editor.
+#[derive(Resource)]
+pub struct Editor{
+    pub active: bool,
+    // Vertices of the road being drawn
+    pub points: Vec<Point>,
+    // Lanes and speed limits of new roads
+    pub lanes: u8,
+    pub speed_limit: f32,
+    pub end_speed_limit: f32,
+    // Road end under the pressed button where it was and where it is now, the cursor position of the press and whether it became a drag
+    drag_start: Option<Point>,
+    dragging: Option<Point>,
+    press_cursor: Option<Vec2>,
+    moved: bool,
+}
+
+impl Default for Editor{
+    fn default() -> Editor{
+        Editor{
+            active: false,
+            points: Vec::new(),
+            lanes: 1,
+            speed_limit: DEFAULT_SPEED_LIMIT,
+            end_speed_limit: JUNCTION_SPEED,
+            drag_start: None,
+            dragging: None,
+            press_cursor: None,
+            moved: false,
+        }
+    }
+}
+
+// The vertices and segments of the road being drawn.
+#[derive(Component)]
+pub struct EditorMarker;
+
+// Help and the limits being edited, below the frame rate.
+#[derive(Component)]
+pub struct EditorText;
+
+// Point on the ground plane under the ray.
+fn ground_point(ray:Ray) -> Option<Point>{
+    if ray.direction.z >= 0.0{
+        return None;
+    }
+    let point = ray.origin + ray.direction * (-ray.origin.z / ray.direction.z);
+    Some((point.x,point.y,0.0))
+}
+
+pub fn spawn_editor_panel(mut commands: Commands, asset_server: Res<AssetServer>){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+            font_size: 16.0,
+            color: Color::WHITE,
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect { left: Val::Px(10.0), top: Val::Px(30.0), ..default() },
+        ..default()
+    }),EditorText));
+}
+
+// In edit mode a click adds a vertex to the road being drawn, snapping to nearby road ends.
+// Without a road being drawn, a click on a road selects it and a click elsewhere starts a new road.
+// Dragging a road end moves it and shift with a click connects the selected road to the clicked one.
+#[allow(clippy::too_many_arguments)]
+pub fn editor_mouse_system(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut editor: ResMut<Editor>,
+    mut world: ResMut<World>,
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>,
+    mut contexts: EguiContexts) {
+    if !editor.active {
+        return;
+    }
+    //Presses on the charts window are not edits
+    if mouse.just_pressed(MouseButton::Left) && pointer_over_ui(&mut contexts) {
+        return;
+    }
+    let Some(cursor) = windows.get_single().ok().and_then(|window| window.cursor_position()) else { return };
+    let Some(ray) = cameras.get_single().ok().and_then(|(camera,transform)| camera.viewport_to_world(transform,cursor)) else { return };
+    let ground = ground_point(ray);
+
+    if mouse.just_pressed(MouseButton::Left) {
+        editor.press_cursor = Some(cursor);
+        editor.moved = false;
+        //Road ends are only dragged while no road is being drawn
+        editor.dragging = ground.filter(|_| editor.points.is_empty()).and_then(|point| world.road_end_near(point,SNAP_DISTANCE));
+        editor.drag_start = editor.dragging;
+    }
+    if mouse.pressed(MouseButton::Left) {
+        if let (Some(point),Some(press_cursor),Some(ground)) = (editor.dragging,editor.press_cursor,ground) {
+            if editor.moved || press_cursor.distance(cursor) > DRAG_PIXELS {
+                //The dragged end keeps its height
+                let new_point = (ground.0,ground.1,point.2);
+                world.move_road_point(point,new_point);
+                editor.dragging = Some(new_point);
+                editor.moved = true;
+            }
+        }
+    }
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    let dragged = editor.moved;
+    let (drag_start,drag_end) = (editor.drag_start.take(),editor.dragging.take());
+    let pressed = editor.press_cursor.take().is_some();
+    editor.moved = false;
+    if !pressed {
+        return;
+    }
+    if dragged {
+        //The road ends moved while dragging, undo moves them back
+        if let (Some(from),Some(to)) = (drag_end,drag_start) {
+            history.record(Edit::MovePoint{from,to});
+        }
+        return;
+    }
+
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        if let (Selection::Road(from_road),Some(to_road)) = (*selection,pick_road(&world,ray)) {
+            if from_road != to_road && from_road < world.roads.len() && world.get_connection(from_road,to_road).is_none() {
+                history.apply(&mut world,Edit::SetConnection{from_road,to_road,connection:Some(Connection::new(from_road,to_road))});
+                println!("Connected road {} to road {}", from_road, to_road);
+            }
+        }
+        return;
+    }
+    let Some(ground) = ground else { return };
+    let snapped = world.road_end_near(ground,SNAP_DISTANCE);
+    if !editor.points.is_empty() || snapped.is_some() {
+        editor.points.push(snapped.unwrap_or(ground));
+    }
+    else if let Some(road) = pick_road(&world,ray) {
+        *selection = Selection::Road(road);
+    }
+    else {
+        editor.points.push(ground);
+    }
+}
+
+// N toggles edit mode and delete removes the selected road or vehicle.
+// While editing, enter adds the drawn road, backspace removes its last vertex and escape discards it.
+// [ and ] change the lanes, comma and period the speed limit and semicolon and apostrophe the end speed limit,
+// of the selected road or of new roads when no road is selected.
+pub fn editor_key_system(
+    keys: Res<Input<KeyCode>>,
+    replay: Option<Res<Replay>>,
+    mut editor: ResMut<Editor>,
+    mut world: ResMut<World>,
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>) {
+    if replay.is_some() {
+        return;
+    }
+    if keys.just_pressed(KeyCode::N) {
+        editor.active = !editor.active;
+        editor.points.clear();
+        //Routing falls back to A* while editing, the route index is rebuilt once for the edited network
+        if !editor.active && world.route_index.is_none() && !world.roads.is_empty() {
+            world.build_route_index();
+        }
+    }
+    if keys.just_pressed(KeyCode::Delete) {
+        match *selection {
+            Selection::Road(index) if index < world.roads.len() => history.apply(&mut world,Edit::RemoveRoad(index)),
+            Selection::Vehicle(id) => history.apply(&mut world,Edit::RemoveVehicle(id)),
+            _ => {}
+        }
+        *selection = Selection::None;
+        if !editor.active && world.route_index.is_none() && !world.roads.is_empty() {
+            world.build_route_index();
+        }
+    }
+    if !editor.active {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let points = std::mem::take(&mut editor.points);
+        let roads = world.add_polyline(&points,editor.lanes,editor.speed_limit,editor.end_speed_limit);
+        if !roads.is_empty() {
+            println!("Added roads {:?}", roads);
+            //Removing the new roads last first keeps the indices of the others
+            history.record(Edit::Group(roads.into_iter().rev().map(Edit::RemoveRoad).collect()));
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        editor.points.pop();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        editor.points.clear();
+    }
+
+    let step = |decrease:KeyCode,increase:KeyCode| keys.just_pressed(increase) as i32 - keys.just_pressed(decrease) as i32;
+    let (lanes_step,speed_step,end_speed_step) = (
+        step(KeyCode::LBracket,KeyCode::RBracket),
+        step(KeyCode::Comma,KeyCode::Period),
+        step(KeyCode::Semicolon,KeyCode::Apostrophe),
+    );
+    if lanes_step == 0 && speed_step == 0 && end_speed_step == 0 {
+        return;
+    }
+    let change = |lanes:u8,speed_limit:f32,end_speed_limit:f32| (
+        (lanes as i32 + lanes_step).clamp(1,MAX_LANES as i32) as u8,
+        (speed_limit + speed_step as f32).max(1.0),
+        (end_speed_limit + end_speed_step as f32).max(0.0),
+    );
+    match *selection {
+        Selection::Road(index) if index < world.roads.len() => {
+            let road = &world.roads[index];
+            let (lanes,speed_limit,end_speed_limit) = change(road.lanes,road.speed_limit,road.end_speed_limit);
+            history.apply(&mut world,Edit::SetRoadLimits{road:index,lanes,speed_limit,end_speed_limit});
+        }
+        _ => {
+            (editor.lanes,editor.speed_limit,editor.end_speed_limit) = change(editor.lanes,editor.speed_limit,editor.end_speed_limit);
+        }
+    }
+}
+
+// Draw the vertices and segments of the road being drawn.
+pub fn editor_marker_system(
+    editor: Res<Editor>,
+    markers: Query<Entity, With<EditorMarker>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>) {
+    if !editor.is_changed() {
+        return;
+    }
+    for marker in &markers {
+        commands.entity(marker).despawn();
+    }
+    if !editor.active || editor.points.is_empty() {
+        return;
+    }
+    let material = materials.add(MARKER_COLOR.into());
+    let cube = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
+    for &(x,y,z) in editor.points.iter() {
+        commands.spawn((PbrBundle {
+            mesh: cube.clone(),
+            material: material.clone(),
+            transform: Transform::from_xyz(x, y, z + 1.0).with_scale(Vec3::splat(3.0)),
+            ..Default::default()
+        },EditorMarker));
+    }
+    for segment in editor.points.windows(2) {
+        let from = Vec3::new(segment[0].0, segment[0].1, segment[0].2);
+        let to = Vec3::new(segment[1].0, segment[1].1, segment[1].2);
+        commands.spawn((PbrBundle {
+            mesh: cube.clone(),
+            material: material.clone(),
+            transform: Transform {
+                translation: (from + to) / 2.0 + Vec3::Z,
+                rotation: Quat::from_rotation_z((to - from).y.atan2((to - from).x)),
+                scale: Vec3::new((to - from).length(), 1.0, 0.5),
+            },
+            ..Default::default()
+        },EditorMarker));
+    }
+}
+
+// Show the editor help with the limits that the keys change.
+pub fn editor_panel_system(
+    editor: Res<Editor>,
+    world: Res<World>,
+    selection: Res<Selection>,
+    mut text_query: Query<&mut Text, With<EditorText>>) {
+    let text = if editor.active {
+        let limits = match *selection {
+            Selection::Road(index) if index < world.roads.len() => {
+                let road = &world.roads[index];
+                format!("Road {}: {} lanes, {:.1} m/s, {:.1} m/s at the end", index, road.lanes, road.speed_limit, road.end_speed_limit)
+            }
+            _ => format!("New roads: {} lanes, {:.1} m/s, {:.1} m/s at the end", editor.lanes, editor.speed_limit, editor.end_speed_limit),
+        };
+        [
+            "Edit mode (N to leave)".to_string(),
+            format!("Click to place points ({} placed), Enter adds the road, Backspace removes a point, Escape discards", editor.points.len()),
+            "Drag road ends to move them, Shift+click connects the selected road to another".to_string(),
+            "[ ] lanes, , . speed limit, ; ' end speed limit, Delete removes the selected road".to_string(),
+            "Ctrl+Z undo, Ctrl+Y or Ctrl+Shift+Z redo".to_string(),
+            limits,
+        ].join("\n")
+    }
+    else {
+        String::new()
+    };
+    for mut panel in &mut text_query {
+        if panel.sections[0].value != text {
+            panel.sections[0].value = text.clone();
+        }
+    }
+}
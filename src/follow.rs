@@ -0,0 +1,156 @@
+// Purpose: Chase and cockpit cameras following the selected vehicle, with an overlay of its driver decisions.
+
+use bevy::prelude::*;
+use crate::camera::CameraController;
+use crate::comp::World;
+use crate::gui::BevyVehicle;
+use crate::inspect::Selection;
+use crate::replay::Replay;
+use crate::vehicle_type::VehicleType;
+
+// Distance and tilt of the chase camera when it starts following, the wheel and the orbit keys still change them.
+const CHASE_DISTANCE: f32 = 30.0;
+const CHASE_PITCH: f32 = 1.2;
+
+// The cockpit camera looks this far ahead and this far down, in meters, so the road in front is in view.
+const COCKPIT_LOOK_AHEAD: f32 = 20.0;
+const COCKPIT_LOOK_DOWN: f32 = 2.0;
+
+// Vehicle the camera follows and how.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowCamera{
+    #[default]
+    Off,
+    // Behind and above the vehicle, facing its direction of travel
+    Chase(usize),
+    // On the front of the roof, looking down the road
+    Cockpit(usize),
+}
+
+impl FollowCamera{
+    fn vehicle(&self) -> Option<usize>{
+        match *self{
+            FollowCamera::Off => None,
+            FollowCamera::Chase(id) | FollowCamera::Cockpit(id) => Some(id),
+        }
+    }
+}
+
+// Speed, leader gap and control law of the followed vehicle.
+#[derive(Component)]
+pub struct FollowText;
+
+// Spawn the overlay at the top of the window, between the FPS counter and the inspector.
+pub fn spawn_follow_panel(mut commands: Commands, asset_server: Res<AssetServer>){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+            font_size: 20.0,
+            color: Color::WHITE,
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect { left: Val::Percent(35.0), top: Val::Px(10.0), ..default() },
+        ..default()
+    }),FollowText));
+}
+
+// K follows the selected vehicle with the chase camera, then switches to the cockpit camera and then stops following.
+// Selecting another vehicle follows it instead.
+pub fn follow_key_system(
+    keys: Res<Input<KeyCode>>,
+    selection: Res<Selection>,
+    mut follow: ResMut<FollowCamera>,
+    mut cameras: Query<&mut CameraController>) {
+    if keys.just_pressed(KeyCode::K) {
+        *follow = match (*follow,*selection) {
+            (FollowCamera::Off,Selection::Vehicle(id)) => {
+                for mut controller in &mut cameras {
+                    controller.distance = CHASE_DISTANCE;
+                    controller.pitch = CHASE_PITCH;
+                }
+                FollowCamera::Chase(id)
+            }
+            (FollowCamera::Chase(id),_) => FollowCamera::Cockpit(id),
+            _ => FollowCamera::Off,
+        };
+    }
+    if let (Some(followed),Selection::Vehicle(id)) = (follow.vehicle(),*selection) {
+        if followed != id {
+            *follow = match *follow {
+                FollowCamera::Cockpit(_) => FollowCamera::Cockpit(id),
+                _ => FollowCamera::Chase(id),
+            };
+        }
+    }
+}
+
+// Keep the camera on the followed vehicle after the other camera controls, stopping when the vehicle leaves the network.
+pub fn follow_camera_system(
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    mut follow: ResMut<FollowCamera>,
+    vehicles: Query<(&BevyVehicle, &Transform), Without<CameraController>>,
+    mut cameras: Query<(&mut CameraController, &mut Transform, &mut Projection)>) {
+    let Some(id) = follow.vehicle() else { return };
+    let Some((_,vehicle)) = vehicles.iter().find(|(bevy_vehicle,_)| bevy_vehicle.0 == id) else {
+        println!("Vehicle {} left the network, the camera stops following it", id);
+        *follow = FollowCamera::Off;
+        return;
+    };
+    let vehicle_type = match &replay {
+        Some(replay) => replay.vehicles().iter().find(|record| record.id == id).map(|record| record.vehicle_type),
+        None => world.vehicles.iter().find(|vehicle| vehicle.id == id).map(|vehicle| vehicle.vehicle_type),
+    }.unwrap_or(VehicleType::Car);
+    let forward = vehicle.rotation * Vec3::X;
+    for (mut controller,mut transform,mut projection) in &mut cameras {
+        //The controller stays centered behind the vehicle, so the view does not jump when following stops
+        controller.focus = vehicle.translation;
+        controller.yaw = forward.y.atan2(forward.x) - std::f32::consts::FRAC_PI_2;
+        controller.orthographic = false;
+        *transform = match *follow {
+            FollowCamera::Cockpit(_) => {
+                //Vehicles are boxes centered on the road
+                let params = vehicle_type.params();
+                let eye = vehicle.translation + forward * params.length / 2.0 + Vec3::Z * params.height / 2.0;
+                Transform::from_translation(eye).looking_at(eye + forward * COCKPIT_LOOK_AHEAD - Vec3::Z * COCKPIT_LOOK_DOWN,Vec3::Z)
+            }
+            _ => controller.transform(),
+        };
+        *projection = controller.projection();
+    }
+}
+
+pub fn follow_panel_system(
+    follow: Res<FollowCamera>,
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    mut text_query: Query<&mut Text, With<FollowText>>) {
+    let text = match follow.vehicle() {
+        None => String::new(),
+        Some(id) => {
+            let view = if matches!(*follow, FollowCamera::Cockpit(_)) { "cockpit" } else { "chase" };
+            let state = match &replay {
+                //Replayed vehicles only have their recorded state
+                Some(replay) => replay.vehicles().iter().find(|record| record.id == id).map(|record| {
+                    format!("Speed: {:.1} m/s ({:.0} km/h)\nLeader and control law are not recorded", record.velocity, record.velocity * 3.6)
+                }),
+                None => world.vehicles.iter().find(|vehicle| vehicle.id == id).map(|vehicle| {
+                    let leader = match world.leader(vehicle) {
+                        Some((leader,gap)) => format!("{:.1} m to vehicle {}", gap, leader),
+                        None => "none in its path".to_string(),
+                    };
+                    format!("Speed: {:.1} m/s ({:.0} km/h), limit {:.1} m/s\nLeader: {}\nControl: {}",
+                        vehicle.velocity, vehicle.velocity * 3.6, world.roads[vehicle.on_road].speed_limit, leader, vehicle.control_law.description())
+                }),
+            };
+            format!("Following vehicle {} ({} view, K)\n{}", id, view, state.unwrap_or_default())
+        }
+    };
+    for mut panel in &mut text_query {
+        if panel.sections[0].value != text {
+            panel.sections[0].value = text.clone();
+        }
+    }
+}
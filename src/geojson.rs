@@ -0,0 +1,117 @@
+// Purpose: Export roads and vehicle positions as GeoJSON feature collections.
+
+use crate::comp::World;
+use crate::osm::unproject;
+use serde_json::{json, Value};
+use std::path::Path;
+
+// Radius of the spherical Web Mercator projection in meters.
+const MERCATOR_RADIUS: f64 = 6_378_137.0;
+
+// Coordinate reference system of exported coordinates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Crs{
+    // Simulation coordinates in meters, without a reference system.
+    #[default]
+    Local,
+    // Longitude and latitude in degrees (EPSG:4326), the GeoJSON default.
+    Wgs84,
+    // Web Mercator meters (EPSG:3857) as used by web basemaps.
+    WebMercator,
+}
+
+impl Crs{
+    // Named CRS member for systems other than WGS84, which GeoJSON readers assume by default.
+    fn member(&self) -> Option<Value>{
+        match self{
+            Crs::WebMercator => Some(json!({"type": "name", "properties": {"name": "urn:ogc:def:crs:EPSG::3857"}})),
+            _ => None,
+        }
+    }
+}
+
+impl World{
+    // Position of a local point in the given reference system.
+    // Geographic systems need the origin of the local coordinates, set when a map is imported.
+    fn geojson_position(&self,point:(f32,f32,f32),crs:Crs) -> Result<Value,String>{
+        let origin = || self.geo_origin.ok_or("The world has no geographic origin, import a map or use the local CRS.".to_string());
+        match crs{
+            Crs::Local => Ok(json!([point.0,point.1,point.2])),
+            Crs::Wgs84 => {
+                let (lat,lon) = unproject(point.0,point.1,origin()?);
+                Ok(json!([lon,lat,point.2]))
+            }
+            Crs::WebMercator => {
+                let (lat,lon) = unproject(point.0,point.1,origin()?);
+                let x = MERCATOR_RADIUS * lon.to_radians();
+                let y = MERCATOR_RADIUS * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+                Ok(json!([x,y,point.2]))
+            }
+        }
+    }
+
+    fn feature_collection(&self,features:Vec<Value>,crs:Crs) -> Value{
+        //Foreign member recording the seed of the run
+        let mut collection = json!({"type": "FeatureCollection", "seed": self.rng.seed(), "features": features});
+        if let Some(member) = crs.member(){
+            collection["crs"] = member;
+        }
+        collection
+    }
+
+    // Roads as LineString features with their index, lanes and speed limits.
+    pub fn roads_geojson(&self,crs:Crs) -> Result<Value,String>{
+        let mut features = Vec::new();
+        for (index,road) in self.roads.iter().enumerate(){
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": [self.geojson_position(road.from,crs)?,self.geojson_position(road.to,crs)?],
+                },
+                "properties": {
+                    "road": index,
+                    "length": road.length.into_inner(),
+                    "lanes": road.lanes,
+                    "speed_limit": road.speed_limit,
+                    "end_speed_limit": road.end_speed_limit,
+                    "signal": road.signal,
+                    "to_road": road.to_road,
+                },
+            }));
+        }
+        Ok(self.feature_collection(features,crs))
+    }
+
+    // Snapshot of the vehicles as Point features with their id, speed and road.
+    pub fn vehicles_geojson(&self,crs:Crs) -> Result<Value,String>{
+        let mut features = Vec::new();
+        for vehicle in self.vehicles.iter(){
+            let point = self.roads[vehicle.on_road].point_at(vehicle.position);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": self.geojson_position(point,crs)?,
+                },
+                "properties": {
+                    "id": vehicle.id,
+                    "type": vehicle.vehicle_type.name(),
+                    "speed": vehicle.velocity,
+                    "road": vehicle.on_road,
+                    "position": vehicle.position,
+                    "time": self.time,
+                },
+            }));
+        }
+        Ok(self.feature_collection(features,crs))
+    }
+
+    // Write the roads and the current vehicle positions to two GeoJSON files.
+    pub fn save_geojson(&self,roads_path:&Path,vehicles_path:&Path,crs:Crs) -> Result<(),String>{
+        let roads = self.roads_geojson(crs)?;
+        let vehicles = self.vehicles_geojson(crs)?;
+        std::fs::write(roads_path,roads.to_string()).map_err(|e| e.to_string())?;
+        std::fs::write(vehicles_path,vehicles.to_string()).map_err(|e| e.to_string())
+    }
+}
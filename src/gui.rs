@@ -3,187 +3,541 @@ use bevy::prelude::*;
 pub use crate::comp::*;
 pub use crate::comp::World;
 pub use crate::phy::*;
-use std::fs::File;
-use std::io::Read;
+pub use crate::vehicle_type::VehicleType;
+use crate::camera::{camera_control_system, spawn_camera};
+use crate::charts::{chart_sampling_system, charts_system, Charts};
+use crate::demand::{spawn_spawner_panel, spawner_key_system, spawner_mouse_system, spawner_panel_system, Spawner};
+use crate::editor::{editor_key_system, editor_marker_system, editor_mouse_system, editor_panel_system, spawn_editor_panel, Editor};
+use crate::follow::{follow_camera_system, follow_key_system, follow_panel_system, spawn_follow_panel, FollowCamera};
+use crate::geojson::Crs;
+use crate::heatmap::{legend_system, overlay_control_system, spawn_legend, vehicle_color_system, Overlay};
+use crate::history::{history_system, History};
+use crate::inspect::{inspect_pick_system, inspector_panel_system, route_highlight_system, InspectorText, Selection};
+use crate::markings::road_markings_system;
+use crate::recorder::TrajectoryRecord;
+use crate::replay::Replay;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiPlugin;
+use std::collections::HashMap;
+
+//Aggregation interval of the link statistics in seconds
+const LINK_STATISTICS_INTERVAL: f32 = 60.0;
+
+//Speed factors of the simulation clock, from slow motion to fast forward
+const SPEEDS: [f32; 10] = [0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+//Longest simulation step in seconds, frames are split into steps no longer than this so fast forward stays accurate
+const MAX_STEP: f32 = 0.1;
+
+//Most steps per frame, the clock falls behind instead of freezing the window when the simulation is too slow
+const MAX_STEPS_PER_FRAME: usize = 200;
 
 
 pub fn run(){
     App::new()
-    .insert_resource(World {
-        roads: Vec::new(),
-        vehicles: Vec::new(),
-    })
+    .insert_resource(World::new())
+    .insert_resource(SimulationClock::default())
+    .init_resource::<Selection>()
+    .init_resource::<Overlay>()
+    .init_resource::<Editor>()
+    .init_resource::<History>()
+    .init_resource::<Spawner>()
+    .init_resource::<Charts>()
+    .init_resource::<FollowCamera>()
     .add_startup_system(create_sample_world)
     .add_startup_system(spawn_camera)
     .add_startup_system(spawn_directional_light)
     .add_startup_system(set_initial_state)
-    .add_system(update_frame)
+    .add_startup_system(spawn_legend)
+    .add_startup_system(spawn_editor_panel)
+    .add_startup_system(spawn_spawner_panel)
+    .add_startup_system(spawn_follow_panel)
+    .add_system(simulation_step_system)
+    .add_system(update_frame.after(simulation_step_system))
     .add_plugins(DefaultPlugins)
+    .add_plugin(EguiPlugin)
     .add_system(file_drag_and_drop_system)
+    .add_system(camera_control_system.after(file_drag_and_drop_system).after(update_frame))
+    .add_system(sumo_export_system)
+    .add_system(geojson_export_system)
+    .add_system(trajectory_recording_system)
+    .add_system(trip_export_system)
+    .add_system(detector_export_system)
+    .add_system(link_statistics_export_system)
+    .add_system(snapshot_system)
+    .add_system(scenario_export_system)
+    .add_system(time_control_system)
+    .add_system(replay_control_system)
+    .add_system(replay_timeline_system)
+    .add_system(inspect_pick_system)
+    .add_system(inspector_panel_system)
+    .add_system(route_highlight_system)
+    .add_system(overlay_control_system)
+    .add_system(vehicle_color_system)
+    .add_system(legend_system)
+    .add_system(editor_key_system)
+    .add_system(editor_mouse_system)
+    .add_system(editor_marker_system.after(editor_key_system).after(editor_mouse_system))
+    .add_system(editor_panel_system)
+    .add_system(history_system)
+    .add_system(spawner_key_system)
+    .add_system(spawner_mouse_system)
+    .add_system(spawner_panel_system)
+    .add_system(road_markings_system.after(update_frame))
+    .add_system(chart_sampling_system.after(update_frame))
+    .add_system(charts_system)
+    .add_system(follow_key_system.after(inspect_pick_system))
+    .add_system(follow_camera_system.after(follow_key_system).after(camera_control_system))
+    .add_system(follow_panel_system.after(follow_camera_system))
     .run(); 
 }
 
-fn file_drag_and_drop_system(mut events: EventReader<FileDragAndDrop>,mut world: ResMut<World>) {
+//Trajectory files start a replay over the current roads, other files are loaded into the simulation
+fn file_drag_and_drop_system(mut events: EventReader<FileDragAndDrop>,mut world: ResMut<World>,mut history: ResMut<History>,mut commands: Commands) {
     for event in events.iter() {
         if let FileDragAndDrop::DroppedFile { window, path_buf } = event {
             println!("Dropped file with path: {:?}, in window id: {:?}", path_buf, window);
-            let mut file = File::open(path_buf).expect("Unable to open");
-            let mut contents = String::new();
-            file.read_to_string(&mut contents);
-            world.load_json(contents);
+            match path_buf.extension().and_then(|extension| extension.to_str()) {
+                Some("csv") | Some("parquet") => match Replay::load(path_buf) {
+                    Ok(replay) => {
+                        println!("Replaying {:.1} s to {:.1} s", replay.start_time(), replay.end_time());
+                        commands.insert_resource(replay);
+                    }
+                    Err(error) => println!("Failed to load trajectories: {}", error),
+                },
+                _ => {
+                    world.load_file(path_buf);
+                    //The edits belong to the previous scenario
+                    history.clear();
+                    commands.remove_resource::<Replay>();
+                }
+            }
+        }
+    }
+}
+
+
+//Exports the world as export.net.xml and export.rou.xml when E is pressed
+fn sumo_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::E) {
+        match world.save_sumo(std::path::Path::new("export.net.xml"),std::path::Path::new("export.rou.xml")) {
+            Ok(()) => println!("Exported SUMO network and routes"),
+            Err(error) => println!("Failed to export SUMO files: {}", error),
+        }
+    }
+}
+
+//Exports roads and vehicle positions as roads.geojson and vehicles.geojson when G is pressed
+//Coordinates are longitude and latitude for imported maps, Web Mercator with shift, and local meters otherwise
+fn geojson_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::G) {
+        let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+        let crs = match (world.geo_origin.is_some(),shift) {
+            (false,_) => Crs::Local,
+            (true,false) => Crs::Wgs84,
+            (true,true) => Crs::WebMercator,
+        };
+        match world.save_geojson(std::path::Path::new("roads.geojson"),std::path::Path::new("vehicles.geojson"),crs) {
+            Ok(()) => println!("Exported GeoJSON in {:?} coordinates", crs),
+            Err(error) => println!("Failed to export GeoJSON: {}", error),
+        }
+    }
+}
+
+//Starts and stops recording trajectories every second when R is pressed, to trajectories.csv or trajectories.parquet with shift
+fn trajectory_recording_system(keys: Res<Input<KeyCode>>,mut world: ResMut<World>) {
+    if keys.just_pressed(KeyCode::R) {
+        if world.recorder.is_some() {
+            match world.stop_recording() {
+                Ok(()) => println!("Stopped recording trajectories"),
+                Err(error) => println!("Failed to finish trajectory file: {}", error),
+            }
+            return;
+        }
+        let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+        let path = if shift { "trajectories.parquet" } else { "trajectories.csv" };
+        match world.start_recording(std::path::Path::new(path),1.0) {
+            Ok(()) => println!("Recording trajectories to {}", path),
+            Err(error) => println!("Failed to start recording: {}", error),
+        }
+    }
+}
+
+//Writes the finished trips to trips.csv and trips.json and prints their statistics when T is pressed
+fn trip_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::T) {
+        for path in ["trips.csv","trips.json"] {
+            if let Err(error) = world.save_trips(std::path::Path::new(path)) {
+                println!("Failed to write {}: {}", path, error);
+            }
+        }
+        println!("Trip statistics: {:?}", world.trip_statistics());
+    }
+}
+
+//Writes the detector measurements to point_detectors.csv and area_detectors.csv when D is pressed
+fn detector_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::D) {
+        match world.save_detectors(std::path::Path::new("point_detectors.csv"),std::path::Path::new("area_detectors.csv")) {
+            Ok(()) => println!("Exported detector measurements"),
+            Err(error) => println!("Failed to export detector measurements: {}", error),
+        }
+    }
+}
+
+//Writes the per-road flow, density and speed of every interval to link_statistics.csv when L is pressed
+fn link_statistics_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::L) {
+        match world.save_link_statistics(std::path::Path::new("link_statistics.csv"),None) {
+            Ok(()) => println!("Exported link statistics"),
+            Err(error) => println!("Failed to export link statistics: {}", error),
+        }
+    }
+}
+
+
+//Saves the simulation state to state.snapshot when S is pressed, dropping the file restores it
+fn snapshot_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::S) {
+        match world.save_snapshot(std::path::Path::new("state.snapshot")) {
+            Ok(()) => println!("Saved snapshot at {:.1} s", world.time),
+            Err(error) => println!("Failed to save snapshot: {}", error),
+        }
+    }
+}
+
+//Saves the road network and its vehicles to scenario.json when J is pressed, for example after editing the roads
+fn scenario_export_system(keys: Res<Input<KeyCode>>,world: Res<World>) {
+    if keys.just_pressed(KeyCode::J) {
+        match world.save_json(std::path::Path::new("scenario.json")) {
+            Ok(()) => println!("Exported scenario"),
+            Err(error) => println!("Failed to export scenario: {}", error),
         }
     }
 }
 
 
+//Controls the replay: space pauses, left and right step one frame, up and down change the speed,
+//clicking or dragging on the timeline at the bottom of the window scrubs and escape returns to the simulation
+fn replay_control_system(
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    replay: Option<ResMut<Replay>>,
+    mut commands: Commands) {
+    let Some(mut replay) = replay else { return };
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<Replay>();
+        return;
+    }
+    if keys.just_pressed(KeyCode::Space) {
+        //Playing again from the end starts over
+        if replay.paused && replay.time >= replay.end_time() {
+            let start = replay.start_time();
+            replay.seek(start);
+        }
+        replay.paused = !replay.paused;
+    }
+    //The arrow keys move the camera while shift or control is held
+    let modifier = keys.any_pressed([KeyCode::LShift, KeyCode::RShift, KeyCode::LControl, KeyCode::RControl]);
+    if !modifier {
+        if keys.just_pressed(KeyCode::Right) {
+            replay.step(1);
+        }
+        if keys.just_pressed(KeyCode::Left) {
+            replay.step(-1);
+        }
+        if keys.just_pressed(KeyCode::Up) {
+            replay.change_speed(true);
+        }
+        if keys.just_pressed(KeyCode::Down) {
+            replay.change_speed(false);
+        }
+    }
+    if mouse.pressed(MouseButton::Left) {
+        if let Some((window,cursor)) = windows.get_single().ok().and_then(|window| window.cursor_position().map(|cursor| (window,cursor))) {
+            //The cursor is measured from the bottom left corner
+            if cursor.y <= TIMELINE_HEIGHT {
+                let fraction = (cursor.x / window.width()).clamp(0.0,1.0);
+                let time = replay.start_time() + fraction * (replay.end_time() - replay.start_time());
+                replay.seek(time);
+            }
+        }
+    }
+}
+
+//Shows the timeline and the replay state while replaying
+fn replay_timeline_system(
+    replay: Option<Res<Replay>>,
+    mut timeline_query: Query<&mut Visibility, With<Timeline>>,
+    mut progress_query: Query<&mut Style, With<TimelineProgress>>,
+    mut text_query: Query<&mut Text, With<TimelineText>>) {
+    for mut visibility in &mut timeline_query {
+        *visibility = if replay.is_some() { Visibility::Visible } else { Visibility::Hidden };
+    }
+    let Some(replay) = replay else { return };
+    let duration = replay.end_time() - replay.start_time();
+    let fraction = if duration > 0.0 { (replay.time - replay.start_time()) / duration } else { 1.0 };
+    for mut style in &mut progress_query {
+        style.size.width = Val::Percent(fraction * 100.0);
+    }
+    for mut text in &mut text_query {
+        text.sections[0].value = format!("Replay {:.1} / {:.1} s  x{}{}", replay.time, replay.end_time(), replay.speed, if replay.paused { "  paused" } else { "" });
+    }
+}
+
 
 //Creates a sample world with 2 roads and 2 vehicles
 fn create_sample_world(mut world: ResMut<World>){
+    world.start_link_statistics(LINK_STATISTICS_INTERVAL);
     world.add_road((0.0,10.0,0.0),(500.0,10.0,0.0),1,100.0,vec![0],vec![1],5.0);
     world.add_road((500.0,-10.0,0.0),(0.0,-10.0,0.0),1,100.0,vec![1],vec![0],7.5);
-    world.add_vehicle(0.0,0.0,5.0,-10.0,0,200.0,1,250.0);
-    world.add_vehicle(50.0,0.0,3.0,-20.0,0,150.0,1,100.0);
-    world.add_vehicle(0.0,0.0,4.0,-7.0,1,250.0,0,250.0);
+    world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Car,position: 0.0,velocity: 0.0,acceleration: 5.0,break_decceleration: -10.0,on_road: 0,watch_distance: 200.0,destination: 1,destination_position: 250.0});
+    world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Van,position: 50.0,velocity: 0.0,acceleration: 3.0,break_decceleration: -20.0,on_road: 0,watch_distance: 150.0,destination: 1,destination_position: 100.0});
+    world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Car,position: 0.0,velocity: 0.0,acceleration: 4.0,break_decceleration: -7.0,on_road: 1,watch_distance: 250.0,destination: 0,destination_position: 250.0});
+}
+
+//Pause state and speed factor of the simulation in the GUI
+#[derive(Resource)]
+struct SimulationClock{
+    paused: bool,
+    speed_index: usize,
+    //Steps requested while paused
+    single_steps: usize,
+}
+
+impl Default for SimulationClock{
+    fn default() -> SimulationClock{
+        SimulationClock{
+            paused: false,
+            //Real time
+            speed_index: 3,
+            single_steps: 0,
+        }
+    }
+}
+
+impl SimulationClock{
+    fn speed(&self) -> f32{
+        SPEEDS[self.speed_index]
+    }
+
+    //Number and length of the simulation steps for a frame of t seconds
+    fn steps(&mut self,t:f32) -> (usize,f32){
+        if self.paused{
+            let steps = self.single_steps;
+            self.single_steps = 0;
+            return (steps,MAX_STEP);
+        }
+        let simulated = t * self.speed();
+        let steps = (simulated / MAX_STEP).ceil() as usize;
+        if steps > MAX_STEPS_PER_FRAME{
+            (MAX_STEPS_PER_FRAME,MAX_STEP)
+        }
+        else{
+            (steps,simulated / steps.max(1) as f32)
+        }
+    }
+}
+
+//Controls the simulation clock when not replaying: space pauses and resumes,
+//right steps once while paused and up and down change the speed factor
+fn time_control_system(keys: Res<Input<KeyCode>>,replay: Option<Res<Replay>>,mut clock: ResMut<SimulationClock>) {
+    //The arrow keys move the camera while shift or control is held
+    let modifier = keys.any_pressed([KeyCode::LShift, KeyCode::RShift, KeyCode::LControl, KeyCode::RControl]);
+    if replay.is_some() || modifier {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Space) {
+        clock.paused = !clock.paused;
+    }
+    if keys.just_pressed(KeyCode::Right) {
+        clock.paused = true;
+        clock.single_steps += 1;
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        clock.speed_index = (clock.speed_index + 1).min(SPEEDS.len() - 1);
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        clock.speed_index = clock.speed_index.saturating_sub(1);
+    }
 }
 
 // A unit struct to help identify the FPS UI component, since there may be many Text components
 #[derive(Component)]
 struct FpsText;
 
-//A struct to help identify the vehicle
+//A struct to help identify the vehicle, holds the vehicle id and the type its mesh was built for
 #[derive(Component)]
-struct BevyVehicle;
+pub(crate) struct BevyVehicle(pub usize, pub VehicleType);
 
-//A struct to help identify the road
+//A struct to help identify the road, holds the road index
 #[derive(Component)]
-struct BevyRoad;
+pub(crate) struct BevyRoad(pub usize);
+
+//Width of a drawn lane in meters and color of the road quads
+pub(crate) const LANE_WIDTH: f32 = 3.5;
+pub(crate) const ROAD_COLOR: Color = Color::rgb(0.1, 0.1, 0.15);
 
+//Height of the replay timeline in pixels
+pub(crate) const TIMELINE_HEIGHT: f32 = 24.0;
+
+//The replay timeline, its filled part and its label
+#[derive(Component)]
+struct Timeline;
+#[derive(Component)]
+struct TimelineProgress;
+#[derive(Component)]
+struct TimelineText;
+
+
+//Moves the replay or the simulation forward by the frame time and updates the FPS counter
+fn simulation_step_system(
+    mut world: ResMut<World>,
+    time: Res<Time>,
+    replay: Option<ResMut<Replay>>,
+    mut clock: ResMut<SimulationClock>,
+    mut text_query: Query<&mut Text, With<FpsText>>) {
+    match replay {
+        Some(mut replay) => replay.advance(time.delta_seconds()),
+        None => {
+            //Update the vehicle position in steps following the clock
+            let (steps,step) = clock.steps(time.delta_seconds());
+            for _ in 0..steps {
+                update_comp(step,&mut world);
+            }
+        }
+    }
+
+    // Update the FPS counter text
+    for mut text in &mut text_query {
+        text.sections[1].value = format!("{:.2}", 1.0/time.delta_seconds());
+        text.sections[3].value = format!("{:.1} s  x{}{}", world.time, clock.speed(), if clock.paused { "  paused" } else { "" });
+    }
+}
 
 //Updates the frame
 fn update_frame(
-    mut world: ResMut<World>, 
-    time: Res<Time>, 
+    world: Res<World>, 
     mut param_set: ParamSet<'_,'_, (
-        Query<(&mut Transform, Entity), With<BevyVehicle>>,
-        Query<(&mut Transform, Entity), With<BevyRoad>>
+        Query<(&mut Transform, Entity, &BevyVehicle)>,
+        Query<(&mut Transform, Entity, &BevyRoad)>
     )>, 
     mut commands: Commands, 
-    mut text_query: Query<&mut Text, With<FpsText>>, 
     mut meshes: ResMut<Assets<Mesh>>, 
-    mut materials: ResMut<Assets<StandardMaterial>>) {
-    
-    // Update the FPS counter text
-    for mut text in &mut text_query {
-        text.sections[1].value = format!("{:.2}", 1.0/time.delta_seconds());
-    }
-
-    
-    //Update the vehicle position
-    update_comp(time.delta_seconds(),&mut world);     
-
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    replay: Option<Res<Replay>>) {
     
-        
-    let mut i = 0;
+    //Map vehicle ids to their type and transform, from the replay or from the simulation
+    let mut vehicle_states:HashMap<usize,(VehicleType,Transform)> = match replay {
+        Some(replay) => replay.vehicles().iter().map(|record| (record.id,(record.vehicle_type,replay_transform(record, &world)))).collect(),
+        None => world.vehicles.iter().map(|veh| (veh.id,(veh.vehicle_type,vehicle_transform(veh, &world)))).collect(),
+    };
 
     //Update the vehicle position in the GUI
-    for (mut t,v) in param_set.p0().iter_mut() {
-        
-
-        //Despawn the vehicle in GUI if it is not in the world
-        if i >= world.vehicles.len() {
-            commands.entity(v).despawn();
-        }
-        else{
-            let veh = &world.vehicles[i];
-            //Calculate the position of the vehicle
-            let road_from = Vec3::new(world.roads[veh.on_road].from.0, world.roads[veh.on_road].from.1, world.roads[veh.on_road].from.2);
-            let road_to = Vec3::new(world.roads[veh.on_road].to.0, world.roads[veh.on_road].to.1, world.roads[veh.on_road].to.2);
-            let road_length = (road_to - road_from).length();
-            let position = road_from + (road_to - road_from) * (veh.position/road_length);
-            t.translation = position;
+    for (mut t,v,bevy_vehicle) in param_set.p0().iter_mut() {
+        //Despawn the vehicle in GUI if it is not in the world, or if the id now belongs to another type
+        //as after loading a scenario or switching between the simulation and a replay
+        match vehicle_states.get(&bevy_vehicle.0) {
+            Some(&(vehicle_type,transform)) if vehicle_type == bevy_vehicle.1 => {
+                *t = transform;
+                vehicle_states.remove(&bevy_vehicle.0);
+            }
+            _ => commands.entity(v).despawn(),
         }
-
-        i+=1;
     }
 
     //Spawn the new vehicles in GUI
-    if param_set.p0().iter_mut().len() < world.vehicles.len(){
-        for i in param_set.p0().iter_mut().len()..world.vehicles.len(){
-            spawn_vehicle(&mut commands, &mut meshes, &mut materials, &world.vehicles[i], &world);
-        }
+    for (id,(vehicle_type,transform)) in vehicle_states{
+        spawn_vehicle(&mut commands, &mut meshes, &mut materials, id, vehicle_type, transform);
     }
 
-    i = 0;
     //Update the road position in the GUI
-    for (mut t,v) in param_set.p1().iter_mut() {
+    for (mut t,v,bevy_road) in param_set.p1().iter_mut() {
         //Despawn the road in GUI if it is not in the world
-        if i >= world.roads.len() {
+        if bevy_road.0 >= world.roads.len() {
             commands.entity(v).despawn();
         }
         else{
-            let road = &world.roads[i];
-            //Calculate the position of the road
-            let from_vec3 = Vec3::new(road.from.0, road.from.1, road.from.2);
-            let to_vec3 = Vec3::new(road.to.0, road.to.1, road.to.2);
-            let center = (from_vec3 + to_vec3) / 2.0;
-            let size = (from_vec3 - to_vec3).length();
-            let width = 10.0;
-            let rotation = Quat::from_rotation_z((to_vec3 - from_vec3).y.atan2((to_vec3 - from_vec3).x));
-            t.translation = center;
-            t.rotation = rotation;
+            *t = road_transform(&world.roads[bevy_road.0]);
         }
-        i+=1;
     }
 
     //Spawn the new roads in GUI
     if param_set.p1().iter_mut().len() < world.roads.len(){
         for i in param_set.p1().iter_mut().len()..world.roads.len(){
-            spawn_road(&mut commands, &mut meshes, &mut materials, &world.roads[i]);
+            spawn_road(&mut commands, &mut meshes, &mut materials, i, &world.roads[i]);
         }
     }
     
     
 }
 
-fn spawn_road( commands: &mut Commands, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<StandardMaterial>>,road: &Road){
-    //Calculate the position of the road
+//Width of a drawn road, its lanes side by side
+pub(crate) fn road_width(road: &Road) -> f32{
+    road.lanes.max(1) as f32 * LANE_WIDTH
+}
+
+//Calculates the transform of a road, a unit quad stretched to the road length and width
+pub(crate) fn road_transform(road: &Road) -> Transform{
     let from_vec3 = Vec3::new(road.from.0, road.from.1, road.from.2);
     let to_vec3 = Vec3::new(road.to.0, road.to.1, road.to.2);
-    let center = (from_vec3 + to_vec3) / 2.0;
     let size = (from_vec3 - to_vec3).length();
-    let width = 10.0;
-    let rotation = Quat::from_rotation_z((to_vec3 - from_vec3).y.atan2((to_vec3 - from_vec3).x));
+    Transform{
+        translation: (from_vec3 + to_vec3) / 2.0,
+        rotation: Quat::from_rotation_z((to_vec3 - from_vec3).y.atan2((to_vec3 - from_vec3).x)),
+        scale: Vec3::new(size.max(0.01), road_width(road), 1.0),
+    }
+}
 
-    //Spawn the road
+fn spawn_road( commands: &mut Commands, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<StandardMaterial>>,index: usize,road: &Road){
+    //Spawn the road, with its own material so it can be highlighted
     commands.spawn((PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Quad { size: Vec2::new(size,width), flip: false})),
-        material: materials.add(Color::rgb(0.1, 0.1, 0.15).into()),
-        transform: Transform{
-            translation: center,
-            rotation: rotation,
-            ..Default::default()
-        },
+        mesh: meshes.add(Mesh::from(shape::Quad { size: Vec2::ONE, flip: false})),
+        material: materials.add(ROAD_COLOR.into()),
+        transform: road_transform(road),
         ..Default::default()
-    },BevyRoad));
+    },BevyRoad(index)));
 }
 
-fn spawn_vehicle(commands: &mut Commands, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<StandardMaterial>>,vehicle: &Vehicle, world: &ResMut<World>){
-    //Calculate the position of the vehicle
+//Calculates the transform of a vehicle, facing along its road
+pub(crate) fn vehicle_transform(vehicle: &Vehicle, world: &World) -> Transform{
     let road_from = Vec3::new(world.roads[vehicle.on_road].from.0, world.roads[vehicle.on_road].from.1, world.roads[vehicle.on_road].from.2);
     let road_to = Vec3::new(world.roads[vehicle.on_road].to.0, world.roads[vehicle.on_road].to.1, world.roads[vehicle.on_road].to.2);
     let road_length = (road_to - road_from).length();
     let position = road_from + (road_to - road_from) * (vehicle.position/road_length);
+    let rotation = Quat::from_rotation_z((road_to - road_from).y.atan2((road_to - road_from).x));
+    Transform {
+        translation: position,
+        rotation,
+        ..Default::default()
+    }
+}
+
+//Calculates the transform of a replayed vehicle at its recorded point, facing along its road if the road exists
+fn replay_transform(record: &TrajectoryRecord, world: &World) -> Transform{
+    let rotation = match world.roads.get(record.road) {
+        Some(road) => Quat::from_rotation_z((road.to.1 - road.from.1).atan2(road.to.0 - road.from.0)),
+        None => Quat::IDENTITY,
+    };
+    Transform {
+        translation: Vec3::new(record.x, record.y, record.z),
+        rotation,
+        ..Default::default()
+    }
+}
+
+fn spawn_vehicle(commands: &mut Commands, meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<StandardMaterial>>,id: usize, vehicle_type: VehicleType, transform: Transform){
+    //Size and color come from the vehicle type
+    let params = vehicle_type.params();
 
     //Spawn the vehicle
     commands.spawn((PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Cube { size: 5.0 })),
-        material: materials.add(Color::rgb(0.8, 0.2, 0.2).into()),
-        transform: Transform {
-            translation: position,
-            ..Default::default()
-        },
+        mesh: meshes.add(Mesh::from(shape::Box::new(params.length, params.width, params.height))),
+        material: materials.add(Color::rgb(params.color[0], params.color[1], params.color[2]).into()),
+        transform,
         ..Default::default()
-    },BevyVehicle));
+    },BevyVehicle(id, vehicle_type)));
 }
 
 //Sets the initial state of the GUI
@@ -207,28 +561,76 @@ fn set_initial_state(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, m
                 font_size: 20.0,
                 color: Color::GOLD,
             }),
+            TextSection::new(
+                "  Time: ",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
+            ),
+            TextSection::from_style(TextStyle {
+                font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                font_size: 20.0,
+                color: Color::GOLD,
+            }),
         ]),
         FpsText,
     ));
-}
 
+    //Inspector panel in the top right corner, empty until a vehicle or road is clicked
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+            font_size: 16.0,
+            color: Color::WHITE,
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect { right: Val::Px(10.0), top: Val::Px(10.0), ..default() },
+        ..default()
+    }),InspectorText));
 
-//Spawn the camera
-#[derive(Component)]
-struct WorldCamera;
-fn spawn_camera(mut commands: Commands){
-    commands.spawn((Camera3dBundle{
-        transform: Transform{
-            translation: Vec3::new(500.0, -500.0, 1000.0),
-            rotation: Quat::from_rotation_x(0.3),
-            ..Default::default()
+    //Replay timeline along the bottom of the window, hidden until a replay starts
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { left: Val::Px(0.0), bottom: Val::Px(0.0), ..default() },
+            size: Size::new(Val::Percent(100.0), Val::Px(TIMELINE_HEIGHT)),
+            ..default()
         },
-        ..Default::default()
-    },
-    WorldCamera));
+        background_color: Color::rgba(0.1, 0.1, 0.1, 0.8).into(),
+        visibility: Visibility::Hidden,
+        ..default()
+    },Timeline)).with_children(|parent| {
+        parent.spawn((NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect { left: Val::Px(0.0), bottom: Val::Px(0.0), ..default() },
+                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.3, 0.5, 0.9, 0.8).into(),
+            ..default()
+        },TimelineProgress));
+        parent.spawn((TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                font_size: 18.0,
+                color: Color::WHITE,
+            },
+        ).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { left: Val::Px(8.0), bottom: Val::Px(2.0), ..default() },
+            ..default()
+        }),TimelineText));
+    });
 }
 
 
+
 //Spawn the directional light
 fn spawn_directional_light(mut commands: Commands) {
     commands.spawn(DirectionalLightBundle {
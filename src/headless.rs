@@ -0,0 +1,134 @@
+// Purpose: Command line tools that run the simulation without the GUI.
+
+use crate::comp::World;
+use crate::phy::update_comp;
+use std::path::Path;
+
+const RUN_USAGE: &str = "Usage: trafast run <scenario> [--duration <s>] [--step <s>] [--trajectories <file.csv|file.parquet>] [--record-interval <s>] [--snapshot <file.snapshot>]";
+
+const FUNDAMENTAL_DIAGRAM_USAGE: &str = "Usage: trafast fundamental-diagram <scenario> --road <index> [--duration <s>] [--step <s>] [--interval <s>] [--output <file.csv|file.svg>]";
+
+// Value following an option, e.g. --road 3.
+fn option<'a>(args:&'a [String],name:&str) -> Option<&'a String>{
+    args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1))
+}
+
+// Number following an option, the default when the option is missing.
+fn number(args:&[String],name:&str,default:f32) -> Result<f32,String>{
+    match option(args,name){
+        Some(value) => value.parse::<f32>().ok().filter(|number| number.is_finite()).ok_or(format!("Invalid number {} for {}.", value, name)),
+        None => Ok(default),
+    }
+}
+
+// Number following an option that must be greater than 0, such as a step or an interval.
+fn positive(args:&[String],name:&str,default:f32) -> Result<f32,String>{
+    let value = number(args,name,default)?;
+    if value > 0.0 { Ok(value) } else { Err(format!("{} must be greater than 0, got {}.", name, value)) }
+}
+
+// Print the problem with the arguments and the usage, and exit with an error code.
+fn usage_error(usage:&str,error:&str) -> !{
+    if !error.is_empty(){
+        println!("{}", error);
+    }
+    println!("{}", usage);
+    std::process::exit(2);
+}
+
+// Run a scenario headless, recording trajectories for replay in the GUI and saving the final state if asked.
+pub fn run(args:&[String]){
+    let scenario = match args.first(){
+        Some(scenario) if !scenario.starts_with("--") => scenario,
+        _ => usage_error(RUN_USAGE,""),
+    };
+    let options = || -> Result<(f32,f32,f32),String>{
+        Ok((number(args,"--duration",3600.0)?,positive(args,"--step",0.1)?,positive(args,"--record-interval",1.0)?))
+    };
+    let (duration,step,record_interval) = options().unwrap_or_else(|error| usage_error(RUN_USAGE,&error));
+
+    let mut world = World::new();
+    world.load_file(Path::new(scenario));
+    if let Some(trajectories) = option(args,"--trajectories"){
+        if let Err(error) = world.start_recording(Path::new(trajectories),record_interval){
+            println!("Failed to start recording {}: {}", trajectories, error);
+            return;
+        }
+    }
+    let steps = (duration / step).ceil() as usize;
+    for _ in 0..steps{
+        update_comp(step,&mut world);
+    }
+    if let Err(error) = world.stop_recording(){
+        println!("Failed to finish trajectory file: {}", error);
+    }
+    if let Some(snapshot) = option(args,"--snapshot"){
+        if let Err(error) = world.save_snapshot(Path::new(snapshot)){
+            println!("Failed to write {}: {}", snapshot, error);
+        }
+    }
+    println!("Simulated {:.1} s, {} vehicles finished their trip and {} are still driving.", world.time, world.trips.len(), world.vehicles.len());
+}
+
+// Run a scenario headless and export the flow-density fundamental diagram of one road.
+// The scenario is any file the GUI accepts, demand comes from its vehicles and SUMO flows.
+pub fn fundamental_diagram(args:&[String]){
+    let scenario = match args.first(){
+        Some(scenario) if !scenario.starts_with("--") => scenario,
+        _ => usage_error(FUNDAMENTAL_DIAGRAM_USAGE,""),
+    };
+    let road = match option(args,"--road").and_then(|value| value.parse::<usize>().ok()){
+        Some(road) => road,
+        None => usage_error(FUNDAMENTAL_DIAGRAM_USAGE,"--road needs a road index."),
+    };
+    let options = || -> Result<(f32,f32,f32),String>{
+        Ok((number(args,"--duration",3600.0)?,positive(args,"--step",0.1)?,positive(args,"--interval",60.0)?))
+    };
+    let (duration,step,interval) = options().unwrap_or_else(|error| usage_error(FUNDAMENTAL_DIAGRAM_USAGE,&error));
+    let output = option(args,"--output").map_or("fundamental_diagram.csv",|output| output.as_str());
+
+    let mut world = World::new();
+    world.load_file(Path::new(scenario));
+    if road >= world.roads.len(){
+        println!("Road {} does not exist, the network has {} roads.", road, world.roads.len());
+        return;
+    }
+    world.start_link_statistics(interval);
+    let steps = (duration / step).ceil() as usize;
+    for _ in 0..steps{
+        update_comp(step,&mut world);
+    }
+
+    let written = if output.ends_with(".svg"){
+        world.save_fundamental_diagram_svg(Path::new(output),road)
+    }
+    else{
+        world.save_link_statistics(Path::new(output),Some(road))
+    };
+    match written{
+        Ok(()) => println!("Wrote the fundamental diagram of road {} to {}", road, output),
+        Err(error) => println!("Failed to write {}: {}", output, error),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn args(text:&str) -> Vec<String>{
+        text.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options_are_validated(){
+        let args = args("scenario.json --duration 60 --step 0 --interval -1 --record-interval abc --output");
+        assert_eq!(number(&args,"--duration",3600.0),Ok(60.0));
+        assert_eq!(number(&args,"--missing",3600.0),Ok(3600.0));
+        assert!(number(&args,"--record-interval",1.0).is_err());
+        assert!(positive(&args,"--step",0.1).is_err());
+        assert!(positive(&args,"--interval",60.0).is_err());
+        assert_eq!(positive(&args,"--duration",1.0),Ok(60.0));
+        assert_eq!(option(&args,"--output"),None);
+        assert!(number(&self::args("--step NaN"),"--step",0.1).is_err());
+    }
+}
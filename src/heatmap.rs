@@ -0,0 +1,239 @@
+// Purpose: Vehicles colored by speed and roads colored by mean speed or density, with a legend.
+
+use bevy::prelude::*;
+use crate::comp::World;
+use crate::gui::{BevyVehicle, ROAD_COLOR, TIMELINE_HEIGHT};
+use crate::replay::Replay;
+use crate::vehicle_type::VehicleType;
+use std::collections::HashMap;
+
+// Density at which a road is drawn fully congested, in vehicles per kilometer of single file traffic.
+const JAM_DENSITY: f32 = 150.0;
+
+// Number of color swatches in the legend.
+const LEGEND_SWATCHES: usize = 10;
+
+// Named color ramp from congested (0) to free flowing (1), as positions and sRGB colors.
+type Ramp = (&'static str, &'static [(f32, [f32; 3])]);
+
+const RAMPS: [Ramp; 3] = [
+    ("traffic", &[(0.0, [0.85, 0.1, 0.1]), (0.5, [0.95, 0.8, 0.1]), (1.0, [0.1, 0.7, 0.2])]),
+    ("viridis", &[(0.0, [0.27, 0.0, 0.33]), (0.25, [0.23, 0.32, 0.55]), (0.5, [0.13, 0.57, 0.55]), (0.75, [0.37, 0.79, 0.38]), (1.0, [0.99, 0.91, 0.14])]),
+    ("grayscale", &[(0.0, [0.15, 0.15, 0.15]), (1.0, [0.9, 0.9, 0.9])]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VehicleColoring{
+    Type,
+    // Speed relative to the speed limit of the road
+    Speed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoadColoring{
+    None,
+    // Mean speed of the vehicles on the road relative to its speed limit
+    MeanSpeed,
+    // Vehicles per kilometer and lane relative to the jam density
+    Density,
+}
+
+// How vehicles and roads are colored and with which ramp.
+#[derive(Resource)]
+pub struct Overlay{
+    pub vehicles: VehicleColoring,
+    pub roads: RoadColoring,
+    pub ramp: usize,
+}
+
+impl Default for Overlay{
+    fn default() -> Overlay{
+        Overlay{
+            vehicles: VehicleColoring::Type,
+            roads: RoadColoring::None,
+            ramp: 0,
+        }
+    }
+}
+
+// Vehicle shown in the view: id, road, velocity and type, from the replay or the simulation.
+pub fn shown_vehicles(world:&World,replay:Option<&Replay>) -> Vec<(usize,usize,f32,VehicleType)>{
+    match replay{
+        Some(replay) => replay.vehicles().iter().map(|record| (record.id,record.road,record.velocity,record.vehicle_type)).collect(),
+        None => world.vehicles.iter().map(|vehicle| (vehicle.id,vehicle.on_road,vehicle.velocity,vehicle.vehicle_type)).collect(),
+    }
+}
+
+impl Overlay{
+    // Color of a value between 0 (congested) and 1 (free flowing) on the current ramp.
+    pub fn ramp_color(&self,value:f32) -> Color{
+        let stops = RAMPS[self.ramp].1;
+        let value = value.clamp(0.0,1.0);
+        let upper = stops.iter().position(|stop| stop.0 >= value).unwrap_or(stops.len() - 1).max(1);
+        let (from,to) = (stops[upper - 1],stops[upper]);
+        let fraction = if to.0 > from.0 { (value - from.0) / (to.0 - from.0) } else { 0.0 };
+        let channel = |i:usize| from.1[i] + (to.1[i] - from.1[i]) * fraction;
+        Color::rgb(channel(0),channel(1),channel(2))
+    }
+
+    pub fn vehicle_color(&self,world:&World,road:usize,velocity:f32,vehicle_type:VehicleType) -> Color{
+        let params = vehicle_type.params();
+        match (self.vehicles,world.roads.get(road)){
+            (VehicleColoring::Speed,Some(road)) if road.speed_limit > 0.0 => self.ramp_color(velocity / road.speed_limit),
+            _ => Color::rgb(params.color[0],params.color[1],params.color[2]),
+        }
+    }
+
+    // Color of every road, roads without vehicles keep the road color when colored by mean speed.
+    pub fn road_colors(&self,world:&World,vehicles:&[(usize,usize,f32,VehicleType)]) -> Vec<Color>{
+        if self.roads == RoadColoring::None{
+            return vec![ROAD_COLOR; world.roads.len()];
+        }
+        let mut counts = vec![0usize; world.roads.len()];
+        let mut speed_sums = vec![0.0f32; world.roads.len()];
+        for &(_,road,velocity,_) in vehicles.iter().filter(|vehicle| vehicle.1 < world.roads.len()){
+            counts[road] += 1;
+            speed_sums[road] += velocity;
+        }
+        world.roads.iter().enumerate().map(|(index,road)| match self.roads{
+            RoadColoring::MeanSpeed if counts[index] > 0 && road.speed_limit > 0.0 => {
+                self.ramp_color(speed_sums[index] / counts[index] as f32 / road.speed_limit)
+            }
+            RoadColoring::Density if road.length.into_inner() > 0.0 => {
+                //Per lane, so the jam density of single file traffic applies to roads with any number of lanes
+                let density = counts[index] as f32 / road.length.into_inner() * 1000.0 / road.lanes.max(1) as f32;
+                self.ramp_color(1.0 - density / JAM_DENSITY)
+            }
+            _ => ROAD_COLOR,
+        }).collect()
+    }
+
+    // Lines of the legend describing the active colorings, empty when nothing is colored by traffic.
+    fn legend(&self) -> String{
+        let mut lines = Vec::new();
+        if self.vehicles == VehicleColoring::Speed{
+            lines.push("Vehicles: speed / limit, 0% to 100%".to_string());
+        }
+        match self.roads{
+            RoadColoring::MeanSpeed => lines.push("Roads: mean speed / limit, 0% to 100%".to_string()),
+            RoadColoring::Density => lines.push(format!("Roads: density, {} to 0 veh/km per lane", JAM_DENSITY)),
+            RoadColoring::None => {}
+        }
+        if !lines.is_empty(){
+            lines.push(format!("Ramp: {}", RAMPS[self.ramp].0));
+        }
+        lines.join("\n")
+    }
+}
+
+// The legend, its text and its color swatches.
+#[derive(Component)]
+pub struct Legend;
+#[derive(Component)]
+pub struct LegendText;
+#[derive(Component)]
+pub struct LegendSwatch(usize);
+
+// Spawn the legend in the bottom left corner, above the replay timeline.
+pub fn spawn_legend(mut commands: Commands, asset_server: Res<AssetServer>){
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect { left: Val::Px(10.0), bottom: Val::Px(TIMELINE_HEIGHT + 10.0), ..default() },
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        visibility: Visibility::Hidden,
+        ..default()
+    },Legend)).with_children(|parent| {
+        parent.spawn((TextBundle::from_section("", text_style.clone()),LegendText));
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        }).with_children(|row| {
+            row.spawn(TextBundle::from_section("congested ", text_style.clone()));
+            for i in 0..LEGEND_SWATCHES {
+                row.spawn((NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(16.0), Val::Px(12.0)),
+                        ..default()
+                    },
+                    ..default()
+                },LegendSwatch(i)));
+            }
+            row.spawn(TextBundle::from_section(" free", text_style.clone()));
+        });
+    });
+}
+
+// V switches vehicles between type and speed colors, H cycles the road coloring between none, mean speed and density,
+// and C cycles the color ramp.
+pub fn overlay_control_system(keys: Res<Input<KeyCode>>,mut overlay: ResMut<Overlay>) {
+    if keys.just_pressed(KeyCode::V) {
+        overlay.vehicles = match overlay.vehicles {
+            VehicleColoring::Type => VehicleColoring::Speed,
+            VehicleColoring::Speed => VehicleColoring::Type,
+        };
+    }
+    if keys.just_pressed(KeyCode::H) {
+        overlay.roads = match overlay.roads {
+            RoadColoring::None => RoadColoring::MeanSpeed,
+            RoadColoring::MeanSpeed => RoadColoring::Density,
+            RoadColoring::Density => RoadColoring::None,
+        };
+    }
+    if keys.just_pressed(KeyCode::C) {
+        overlay.ramp = (overlay.ramp + 1) % RAMPS.len();
+    }
+}
+
+// Color the vehicles by type or by speed.
+pub fn vehicle_color_system(
+    overlay: Res<Overlay>,
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    vehicles: Query<(&BevyVehicle, &Handle<StandardMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>) {
+    let colors:HashMap<usize,Color> = shown_vehicles(&world,replay.as_deref()).into_iter()
+        .map(|(id,road,velocity,vehicle_type)| (id,overlay.vehicle_color(&world,road,velocity,vehicle_type)))
+        .collect();
+    for (vehicle,handle) in &vehicles {
+        let Some(&color) = colors.get(&vehicle.0) else { continue };
+        //Only touch materials that change, a changed material is uploaded again
+        if materials.get(handle).is_some_and(|material| material.base_color != color) {
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color = color;
+            }
+        }
+    }
+}
+
+// Show the legend while vehicles or roads are colored by traffic.
+pub fn legend_system(
+    overlay: Res<Overlay>,
+    mut legend_query: Query<&mut Visibility, With<Legend>>,
+    mut text_query: Query<&mut Text, With<LegendText>>,
+    mut swatch_query: Query<(&LegendSwatch, &mut BackgroundColor)>) {
+    if !overlay.is_changed() {
+        return;
+    }
+    let legend = overlay.legend();
+    for mut visibility in &mut legend_query {
+        *visibility = if legend.is_empty() { Visibility::Hidden } else { Visibility::Visible };
+    }
+    for mut text in &mut text_query {
+        text.sections[0].value = legend.clone();
+    }
+    for (swatch,mut color) in &mut swatch_query {
+        *color = overlay.ramp_color(swatch.0 as f32 / (LEGEND_SWATCHES - 1) as f32).into();
+    }
+}
@@ -0,0 +1,440 @@
+// Purpose: Reversible edits of the world with an undo and redo history.
+
+use bevy::prelude::*;
+use crate::comp::{Connection, Departure, Road, Vehicle, World};
+use crate::detector::{AreaDetector, PointDetector};
+use crate::editor::{Editor, Point};
+use crate::inspect::Selection;
+use crate::replay::Replay;
+use ordered_float::OrderedFloat;
+
+// Edits kept for undo, the oldest are forgotten first.
+const MAX_HISTORY: usize = 200;
+
+// A change of the world. Applying an edit returns the edit that reverts it.
+#[derive(Debug, Clone)]
+pub enum Edit{
+    // Remove a road with its connections and the vehicles, departures and detectors using it
+    RemoveRoad(usize),
+    // Put a removed road back where it was
+    RestoreRoad(Box<RemovedRoad>),
+    // Add, replace or with None remove the movement between two roads
+    SetConnection{from_road:usize,to_road:usize,connection:Option<Connection>},
+    SetRoadLimits{road:usize,lanes:u8,speed_limit:f32,end_speed_limit:f32},
+    // Move every road end at a point
+    MovePoint{from:Point,to:Point},
+    AddVehicle(Box<Vehicle>),
+    // Remove a vehicle by id
+    RemoveVehicle(usize),
+    // Schedule vehicles, or remove those of them still waiting for their departure
+    AddDepartures(Vec<Departure>),
+    RemoveDepartures(Vec<Departure>),
+    // Edits applied in order and reverted in reverse order
+    Group(Vec<Edit>),
+}
+
+// A removed road with everything that referred to it, in the road indices from before the removal.
+#[derive(Debug, Clone)]
+pub struct RemovedRoad{
+    index: usize,
+    road: Road,
+    connections: Vec<Connection>,
+    // Roads listing the removed road as successor or predecessor, with its position in their list
+    predecessors: Vec<(usize,usize)>,
+    successors: Vec<(usize,usize)>,
+    // Removed items with their position in their list
+    vehicles: Vec<(usize,Vehicle)>,
+    departures: Vec<(usize,Departure)>,
+    point_detectors: Vec<(usize,PointDetector)>,
+    area_detectors: Vec<(usize,AreaDetector)>,
+}
+
+// Obstacle map key of a vehicle, as the simulation step inserts it.
+fn obstacle_key(vehicle:&Vehicle) -> OrderedFloat<f32>{
+    OrderedFloat(((vehicle.position * 10.0).round() / 10.0) - 0.01 - 1.5)
+}
+
+// Remove the items matching a condition, with their positions in ascending order.
+fn take_where<T>(items:&mut Vec<T>,condition:impl Fn(&T) -> bool) -> Vec<(usize,T)>{
+    let positions:Vec<usize> = (0..items.len()).filter(|&i| condition(&items[i])).collect();
+    let mut taken:Vec<(usize,T)> = positions.into_iter().rev().map(|i| (i,items.remove(i))).collect();
+    taken.reverse();
+    taken
+}
+
+// Insert items taken by take_where back at their positions.
+fn put_back<T>(items:&mut Vec<T>,taken:Vec<(usize,T)>){
+    for (position,item) in taken{
+        items.insert(position.min(items.len()),item);
+    }
+}
+
+impl World{
+    // Remove a vehicle from the obstacle map of its road so it no longer slows others down.
+    pub(crate) fn clear_obstacle(&mut self,vehicle:&Vehicle){
+        if let Some(road) = self.roads.get_mut(vehicle.on_road){
+            road.obstacle_map.remove(&obstacle_key(vehicle));
+        }
+    }
+
+    pub(crate) fn place_obstacle(&mut self,vehicle:&Vehicle){
+        if let Some(road) = self.roads.get_mut(vehicle.on_road){
+            road.obstacle_map.insert(obstacle_key(vehicle),vehicle.velocity);
+        }
+    }
+
+    // Change every road index stored in the world, after roads were removed or inserted.
+    fn renumber_roads(&mut self,renumber:impl Fn(usize) -> usize){
+        for road in self.roads.iter_mut(){
+            road.from_road.iter_mut().for_each(|index| *index = renumber(*index));
+            road.to_road.iter_mut().for_each(|index| *index = renumber(*index));
+        }
+        self.connections = std::mem::take(&mut self.connections).into_values().map(|mut connection| {
+            connection.from_road = renumber(connection.from_road);
+            connection.to_road = renumber(connection.to_road);
+            ((connection.from_road,connection.to_road),connection)
+        }).collect();
+        for vehicle in self.vehicles.iter_mut(){
+            vehicle.on_road = renumber(vehicle.on_road);
+            vehicle.destination = renumber(vehicle.destination);
+            vehicle.path.iter_mut().for_each(|index| *index = renumber(*index));
+            vehicle.trip.route.iter_mut().for_each(|index| *index = renumber(*index));
+        }
+        for departure in self.departures.iter_mut(){
+            departure.on_road = renumber(departure.on_road);
+            departure.destination = renumber(departure.destination);
+            departure.route.iter_mut().for_each(|index| *index = renumber(*index));
+        }
+        self.point_detectors.iter_mut().for_each(|detector| detector.road = renumber(detector.road));
+        self.area_detectors.iter_mut().for_each(|detector| detector.road = renumber(detector.road));
+        self.route_index = None;
+    }
+
+    // Remove a road, the roads after it move down one index.
+    pub fn remove_road(&mut self,index:usize) -> RemovedRoad{
+        let uses = |road:usize,route:&[usize]| road == index || route.contains(&index);
+        let vehicles = take_where(&mut self.vehicles,|vehicle| uses(vehicle.on_road,&vehicle.path) || vehicle.destination == index || vehicle.trip.route.contains(&index));
+        let departures = take_where(&mut self.departures,|departure| uses(departure.on_road,&departure.route) || departure.destination == index);
+        let point_detectors = take_where(&mut self.point_detectors,|detector| detector.road == index);
+        let area_detectors = take_where(&mut self.area_detectors,|detector| detector.road == index);
+        for (_,vehicle) in vehicles.iter(){
+            self.clear_obstacle(vehicle);
+        }
+        let mut connections:Vec<Connection> = self.connections.keys()
+            .filter(|&&(from_road,to_road)| from_road == index || to_road == index)
+            .copied().collect::<Vec<(usize,usize)>>().into_iter()
+            .filter_map(|key| self.connections.remove(&key))
+            .collect();
+        //Stable order, the connections map has none
+        connections.sort_by_key(|connection| (connection.from_road,connection.to_road));
+
+        let mut predecessors = Vec::new();
+        let mut successors = Vec::new();
+        for (other,road) in self.roads.iter_mut().enumerate().filter(|&(other,_)| other != index){
+            if let Some(position) = road.to_road.iter().position(|&next_road| next_road == index){
+                road.to_road.remove(position);
+                predecessors.push((other,position));
+            }
+            if let Some(position) = road.from_road.iter().position(|&previous_road| previous_road == index){
+                road.from_road.remove(position);
+                successors.push((other,position));
+            }
+        }
+        let road = self.roads.remove(index);
+        self.renumber_roads(|road| if road > index { road - 1 } else { road });
+        if let Some(statistics) = &mut self.link_statistics{
+            statistics.remove_road(index);
+        }
+        RemovedRoad{index,road,connections,predecessors,successors,vehicles,departures,point_detectors,area_detectors}
+    }
+
+    // Put a removed road back at its index, the roads from there on move up one index.
+    pub fn restore_road(&mut self,removed:RemovedRoad){
+        let index = removed.index;
+        self.renumber_roads(|road| if road >= index { road + 1 } else { road });
+        if let Some(statistics) = &mut self.link_statistics{
+            statistics.insert_road(index);
+        }
+        self.roads.insert(index,removed.road);
+        for (other,position) in removed.predecessors{
+            let list = &mut self.roads[other].to_road;
+            list.insert(position.min(list.len()),index);
+        }
+        for (other,position) in removed.successors{
+            let list = &mut self.roads[other].from_road;
+            list.insert(position.min(list.len()),index);
+        }
+        for connection in removed.connections{
+            self.connections.insert((connection.from_road,connection.to_road),connection);
+        }
+        for (_,vehicle) in removed.vehicles.iter(){
+            self.place_obstacle(vehicle);
+        }
+        put_back(&mut self.vehicles,removed.vehicles);
+        put_back(&mut self.departures,removed.departures);
+        put_back(&mut self.point_detectors,removed.point_detectors);
+        put_back(&mut self.area_detectors,removed.area_detectors);
+    }
+
+    // Remove the movement between two roads.
+    pub fn remove_connection(&mut self,from_road:usize,to_road:usize) -> Option<Connection>{
+        if let Some(road) = self.roads.get_mut(from_road){
+            road.to_road.retain(|&next_road| next_road != to_road);
+        }
+        if let Some(road) = self.roads.get_mut(to_road){
+            road.from_road.retain(|&previous_road| previous_road != from_road);
+        }
+        //Removing a link cannot be patched into the route index
+        self.route_index = None;
+        self.connections.remove(&(from_road,to_road))
+    }
+
+    // Apply an edit and return the edit that reverts it.
+    pub fn apply(&mut self,edit:Edit) -> Edit{
+        match edit{
+            Edit::RemoveRoad(index) => Edit::RestoreRoad(Box::new(self.remove_road(index))),
+            Edit::RestoreRoad(removed) => {
+                let index = removed.index;
+                self.restore_road(*removed);
+                Edit::RemoveRoad(index)
+            }
+            Edit::SetConnection{from_road,to_road,connection} => {
+                let previous = match connection{
+                    Some(connection) => {
+                        let previous = self.get_connection(from_road,to_road).cloned();
+                        self.add_connection(from_road,to_road,connection.allowed_types,connection.turn_penalty,connection.speed_limit);
+                        previous
+                    }
+                    None => self.remove_connection(from_road,to_road),
+                };
+                Edit::SetConnection{from_road,to_road,connection:previous}
+            }
+            Edit::SetRoadLimits{road,lanes,speed_limit,end_speed_limit} => {
+                let previous = &self.roads[road];
+                let revert = Edit::SetRoadLimits{road,lanes:previous.lanes,speed_limit:previous.speed_limit,end_speed_limit:previous.end_speed_limit};
+                self.set_road_limits(road,lanes,speed_limit,end_speed_limit);
+                revert
+            }
+            Edit::MovePoint{from,to} => {
+                self.move_road_point(from,to);
+                Edit::MovePoint{from:to,to:from}
+            }
+            Edit::AddVehicle(vehicle) => {
+                let id = vehicle.id;
+                self.place_obstacle(&vehicle);
+                //Vehicles are kept in the order of their ids
+                let position = self.vehicles.partition_point(|other| other.id < id);
+                self.vehicles.insert(position,*vehicle);
+                Edit::RemoveVehicle(id)
+            }
+            Edit::RemoveVehicle(id) => match self.vehicles.iter().position(|vehicle| vehicle.id == id){
+                Some(position) => {
+                    let vehicle = self.vehicles.remove(position);
+                    self.clear_obstacle(&vehicle);
+                    Edit::AddVehicle(Box::new(vehicle))
+                }
+                //The vehicle already left the network
+                None => Edit::Group(Vec::new()),
+            },
+            Edit::AddDepartures(departures) => {
+                for departure in departures.iter(){
+                    self.add_departure(departure.clone());
+                }
+                Edit::RemoveDepartures(departures)
+            }
+            Edit::RemoveDepartures(departures) => {
+                //Departed vehicles stay in the network
+                let removed = departures.into_iter().filter(|departure| {
+                    let position = self.departures.iter().position(|waiting| waiting == departure);
+                    position.map(|position| self.departures.remove(position)).is_some()
+                }).collect();
+                Edit::AddDepartures(removed)
+            }
+            Edit::Group(edits) => {
+                let mut reverts:Vec<Edit> = edits.into_iter().map(|edit| self.apply(edit)).collect();
+                reverts.reverse();
+                Edit::Group(reverts)
+            }
+        }
+    }
+}
+
+// Edits that can be undone and redone, stored as the edits reverting them.
+#[derive(Resource, Default)]
+pub struct History{
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History{
+    // Remember the edit reverting a change already made to the world.
+    pub fn record(&mut self,revert:Edit){
+        if self.undo.len() == MAX_HISTORY{
+            self.undo.remove(0);
+        }
+        self.undo.push(revert);
+        self.redo.clear();
+    }
+
+    pub fn apply(&mut self,world:&mut World,edit:Edit){
+        let revert = world.apply(edit);
+        self.record(revert);
+    }
+
+    // Revert the last edit, false when there is nothing to undo.
+    pub fn undo(&mut self,world:&mut World) -> bool{
+        let Some(edit) = self.undo.pop() else { return false };
+        self.redo.push(world.apply(edit));
+        true
+    }
+
+    // Apply the last undone edit again, false when there is nothing to redo.
+    pub fn redo(&mut self,world:&mut World) -> bool{
+        let Some(edit) = self.redo.pop() else { return false };
+        self.undo.push(world.apply(edit));
+        true
+    }
+
+    // Forget all edits, when another scenario is loaded.
+    pub fn clear(&mut self){
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+// Control with Z undoes the last edit, control with Y or control with shift and Z redoes it.
+pub fn history_system(
+    keys: Res<Input<KeyCode>>,
+    replay: Option<Res<Replay>>,
+    editor: Res<Editor>,
+    mut history: ResMut<History>,
+    mut world: ResMut<World>,
+    mut selection: ResMut<Selection>) {
+    let control = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if !control || replay.is_some() {
+        return;
+    }
+    let changed = if keys.just_pressed(KeyCode::Z) && !shift {
+        history.undo(&mut world)
+    }
+    else if keys.just_pressed(KeyCode::Y) || (keys.just_pressed(KeyCode::Z) && shift) {
+        history.redo(&mut world)
+    }
+    else {
+        return;
+    };
+    if !changed {
+        return;
+    }
+    //Road indices may have moved
+    *selection = Selection::None;
+    //The editor rebuilds the route index when editing ends
+    if !editor.active && world.route_index.is_none() && !world.roads.is_empty() {
+        world.build_route_index();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::comp::VehicleParams;
+    use crate::contraction::tests::grid;
+    use crate::detector::DetectorData;
+    use crate::vehicle_type::VehicleType;
+
+    // A grid with vehicles, departures and detectors, all using road 1.
+    fn scenario() -> World{
+        let mut world = grid(3);
+        let destination = world.roads.len() - 1;
+        for (road,position) in [(0,20.0),(1,50.0),(4,10.0)]{
+            world.add_vehicle(VehicleParams{vehicle_type: VehicleType::Car,position,velocity: 5.0,acceleration: 2.0,break_decceleration: -4.0,on_road: road,watch_distance: 50.0,destination,destination_position: 60.0});
+            //The simulation step puts every vehicle in the obstacle map of its road
+            let vehicle = world.vehicles[world.vehicles.len() - 1].clone();
+            world.place_obstacle(&vehicle);
+        }
+        for (time,road) in [(10.0,1),(20.0,2)]{
+            world.add_departure(Departure{time,vehicle_type: VehicleType::Bus,position: 0.0,velocity: 0.0,acceleration: 1.5,break_decceleration: -4.0,
+                on_road: road,watch_distance: 50.0,destination,destination_position: 60.0,route: Vec::new()});
+        }
+        world.add_detector(DetectorData::Point{id: "p1".to_string(),road: 1,lane: 0,position: 40.0,interval: 60.0}).unwrap();
+        world.add_detector(DetectorData::Point{id: "p3".to_string(),road: 3,lane: 0,position: 40.0,interval: 60.0}).unwrap();
+        world.add_detector(DetectorData::Area{id: "a1".to_string(),road: 1,from: 10.0,to: 90.0,interval: 60.0}).unwrap();
+        world
+    }
+
+    // Roads, connections, vehicles, departures and detectors in a comparable form.
+    fn state(world:&World) -> Vec<String>{
+        let mut state:Vec<String> = world.roads.iter().map(|road| {
+            let mut obstacles:Vec<_> = road.obstacle_map.iter().collect();
+            obstacles.sort_by_key(|(key,_)| **key);
+            format!("{:?} {:?} {} {} {} {:?} {:?} {} {} {:?}",road.from,road.to,road.length,road.lanes,road.speed_limit,road.from_road,road.to_road,road.end_speed_limit,road.signal,obstacles)
+        }).collect();
+        let mut connections:Vec<&Connection> = world.connections.values().collect();
+        connections.sort_by_key(|connection| (connection.from_road,connection.to_road));
+        state.extend(connections.iter().map(|connection| format!("{:?}",connection)));
+        state.extend(world.vehicles.iter().map(|vehicle| format!("{:?}",vehicle)));
+        state.extend(world.departures.iter().map(|departure| format!("{:?}",departure)));
+        state.extend(world.point_detectors.iter().map(|detector| format!("{:?}",detector)));
+        state.extend(world.area_detectors.iter().map(|detector| format!("{:?}",detector)));
+        state
+    }
+
+    #[test]
+    fn remove_road_undo_restores_everything(){
+        let mut world = scenario();
+        let (original,roads) = (state(&world),world.roads.len());
+        let mut history = History::default();
+        history.apply(&mut world,Edit::RemoveRoad(1));
+        assert_eq!(world.roads.len(),roads - 1);
+        assert!(world.point_detectors.iter().all(|detector| detector.id != "p1") && world.area_detectors.is_empty());
+        assert_eq!(world.departures.len(),1);
+        //The vehicle on the road is removed with it
+        assert!(world.vehicles.iter().all(|vehicle| vehicle.id != 1));
+
+        assert!(history.undo(&mut world));
+        assert_eq!(state(&world),original);
+        assert!(!history.undo(&mut world));
+    }
+
+    #[test]
+    fn polyline_undo_and_redo(){
+        let mut world = scenario();
+        let original = state(&world);
+        let mut history = History::default();
+
+        //A detour from the end of road 0 to the start of road 5, recorded as the editor does
+        let points = [world.roads[0].to,(250.0,-80.0,0.0),world.roads[5].from];
+        let roads = world.add_polyline(&points,2,10.0,5.0);
+        assert_eq!(roads.len(),2);
+        assert!(world.get_connection(0,roads[0]).is_some() && world.get_connection(roads[1],5).is_some());
+        let added = state(&world);
+        history.record(Edit::Group(roads.into_iter().rev().map(Edit::RemoveRoad).collect()));
+
+        assert!(history.undo(&mut world));
+        assert_eq!(state(&world),original);
+        assert!(history.redo(&mut world));
+        assert_eq!(state(&world),added);
+        assert!(history.undo(&mut world));
+        assert_eq!(state(&world),original);
+    }
+
+    #[test]
+    fn move_point_undo_restores_roads(){
+        let mut world = scenario();
+        let original = state(&world);
+        let mut history = History::default();
+
+        //Dragging a corner of the grid outwards, recorded as the editor does when the drag ends
+        let (point,new_point) = (world.roads[0].from,(-30.0,-40.0,0.0));
+        world.move_road_point(point,new_point);
+        assert_eq!(world.roads[0].from,new_point);
+        let moved = state(&world);
+        history.record(Edit::MovePoint{from: new_point,to: point});
+
+        assert!(history.undo(&mut world));
+        assert_eq!(state(&world),original);
+        assert!(history.redo(&mut world));
+        assert_eq!(state(&world),moved);
+    }
+}
@@ -0,0 +1,260 @@
+// Purpose: Click-to-inspect vehicles and roads in the viewer, with the route of the selected vehicle highlighted.
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+use crate::charts::pointer_over_ui;
+use crate::comp::{Vehicle, World};
+use crate::editor::Editor;
+use crate::gui::{vehicle_transform, road_width, BevyRoad, TIMELINE_HEIGHT};
+use crate::heatmap::{shown_vehicles, Overlay};
+use crate::replay::Replay;
+use ordered_float::OrderedFloat;
+
+// Obstacle map entries listed for a road, the rest are counted.
+const MAX_OBSTACLES_SHOWN: usize = 20;
+
+// Vehicles closer to the click ray than this, or half their length if longer, are picked, in meters.
+const VEHICLE_PICK_RADIUS: f32 = 2.5;
+
+// Color of the selected road and of the remaining route of the selected vehicle.
+const HIGHLIGHT_COLOR: Color = Color::rgb(0.9, 0.7, 0.1);
+
+// Vehicle or road shown in the inspector panel.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection{
+    #[default]
+    None,
+    // Vehicle id
+    Vehicle(usize),
+    // Road index
+    Road(usize),
+}
+
+// Text of the inspector panel.
+#[derive(Component)]
+pub struct InspectorText;
+
+impl World{
+    // Nearest vehicle ahead on the same road or on the next roads of the path, with the gap to its rear in meters.
+    pub fn leader(&self,vehicle:&Vehicle) -> Option<(usize,f32)>{
+        //Distance from the vehicle front to the start of the road being searched
+        let mut offset = -vehicle.position;
+        for (index,&road) in std::iter::once(&vehicle.on_road).chain(vehicle.path.iter()).enumerate(){
+            let leader = self.vehicles.iter()
+                .filter(|other| other.id != vehicle.id && other.on_road == road && (index > 0 || other.position > vehicle.position))
+                .min_by_key(|other| OrderedFloat(other.position));
+            if let Some(leader) = leader{
+                return Some((leader.id,offset + leader.position - leader.vehicle_type.params().length));
+            }
+            offset += self.roads.get(road)?.length.into_inner();
+        }
+        None
+    }
+
+    // Inspector text of a vehicle, None if it left the network.
+    pub fn vehicle_report(&self,id:usize) -> Option<String>{
+        let vehicle = self.vehicles.iter().find(|vehicle| vehicle.id == id)?;
+        let leader = match self.leader(vehicle){
+            Some((leader,gap)) => format!("{} at {:.1} m", leader, gap),
+            None => "none".to_string(),
+        };
+        Some([
+            format!("Vehicle {} ({})", vehicle.id, vehicle.vehicle_type.name()),
+            format!("Road: {}", vehicle.on_road),
+            format!("Position: {:.1} m", vehicle.position),
+            format!("Velocity: {:.1} m/s", vehicle.velocity),
+            format!("Acceleration: {:.1} m/s2", vehicle.acceleration),
+            format!("Break deceleration: {:.1} m/s2", vehicle.break_decceleration),
+            format!("Watch distance: {:.0} m", vehicle.watch_distance),
+            format!("Destination: road {} at {:.1} m", vehicle.destination, vehicle.destination_position),
+            format!("Remaining path: {:?}", vehicle.path),
+            format!("Leader: {}", leader),
+            format!("Control: {}", vehicle.control_law.description()),
+            format!("Departed: {:.1} s, {:.0} m driven, {} stops", vehicle.trip.departure_time, vehicle.trip.distance, vehicle.trip.stops),
+        ].join("\n"))
+    }
+
+    // Inspector text of a road with its connections and obstacle map.
+    pub fn road_report(&self,index:usize) -> Option<String>{
+        let road = self.roads.get(index)?;
+        let mut lines = vec![
+            format!("Road {}", index),
+            format!("Length: {:.1} m", road.length),
+            format!("Lanes: {}", road.lanes),
+            format!("Speed limit: {:.1} m/s, {:.1} m/s at the end{}", road.speed_limit, road.end_speed_limit, if road.signal { " (signal)" } else { "" }),
+            format!("From roads: {:?}", road.from_road),
+            "To roads:".to_string(),
+        ];
+        for &next_road in road.to_road.iter(){
+            let mut line = format!("  {}", next_road);
+            if let Some(connection) = self.get_connection(index,next_road){
+                if let Some(types) = &connection.allowed_types{
+                    line += &format!(" only {:?}", types.iter().map(|vehicle_type| vehicle_type.name()).collect::<Vec<&str>>());
+                }
+                if connection.turn_penalty != 0.0{
+                    line += &format!(" penalty {}", connection.turn_penalty);
+                }
+                if let Some(speed_limit) = connection.speed_limit{
+                    line += &format!(" at {:.1} m/s", speed_limit);
+                }
+            }
+            lines.push(line);
+        }
+        //Obstacles are listed from the start of the road with the speed expected there
+        let mut obstacles:Vec<(OrderedFloat<f32>,f32)> = road.obstacle_map.iter().map(|(&position,&speed)| (position,speed)).collect();
+        obstacles.sort_by_key(|obstacle| obstacle.0);
+        lines.push(format!("Obstacles: {}", obstacles.len()));
+        for (position,speed) in obstacles.iter().take(MAX_OBSTACLES_SHOWN){
+            lines.push(format!("  {:.2} m: {:.1} m/s", position, speed));
+        }
+        if obstacles.len() > MAX_OBSTACLES_SHOWN{
+            lines.push(format!("  ... {} more", obstacles.len() - MAX_OBSTACLES_SHOWN));
+        }
+        Some(lines.join("\n"))
+    }
+}
+
+// Distance between a ray and a line segment, with the distance along the ray to the closest point.
+fn ray_segment_distance(origin:Vec3,direction:Vec3,from:Vec3,to:Vec3) -> (f32,f32){
+    let segment = to - from;
+    let offset = origin - from;
+    let (a,b,c) = (direction.dot(direction),direction.dot(segment),segment.dot(segment));
+    let (d,e) = (direction.dot(offset),segment.dot(offset));
+    let denominator = a * c - b * b;
+    //Parallel lines take the segment start
+    let mut s = if denominator > f32::EPSILON { ((a * e - b * d) / denominator).clamp(0.0,1.0) } else { 0.0 };
+    let t = ((b * s - d) / a).max(0.0);
+    //Clamping the ray parameter can move the closest point on the segment
+    if c > f32::EPSILON{
+        s = ((t * b + e) / c).clamp(0.0,1.0);
+    }
+    ((origin + direction * t).distance(from + segment * s),t)
+}
+
+// Road whose middle line passes closest to the ray, within half the road width.
+pub(crate) fn pick_road(world:&World,ray:Ray) -> Option<usize>{
+    world.roads.iter().enumerate()
+        .map(|(index,road)| {
+            let (distance,_) = ray_segment_distance(ray.origin,ray.direction,Vec3::new(road.from.0,road.from.1,road.from.2),Vec3::new(road.to.0,road.to.1,road.to.2));
+            (index,distance,road_width(road))
+        })
+        .filter(|&(_,distance,width)| distance <= width / 2.0)
+        .map(|(index,distance,_)| (index,distance))
+        .min_by_key(|&(_,distance)| OrderedFloat(distance))
+        .map(|(index,_)| index)
+}
+
+// Road under the cursor with the distance along it from its start, in meters.
+pub(crate) fn pick_road_position(world:&World,ray:Ray) -> Option<(usize,f32)>{
+    let index = pick_road(world,ray)?;
+    let road = &world.roads[index];
+    let (from,to) = (Vec3::new(road.from.0,road.from.1,road.from.2),Vec3::new(road.to.0,road.to.1,road.to.2));
+    let (_,along) = ray_segment_distance(ray.origin,ray.direction,from,to);
+    let segment = to - from;
+    let fraction = if segment.length_squared() > f32::EPSILON { ((ray.origin + ray.direction * along - from).dot(segment) / segment.length_squared()).clamp(0.0,1.0) } else { 0.0 };
+    Some((index,fraction * road.length.into_inner()))
+}
+
+// Select the vehicle or road under the cursor on a left click, or clear the selection when nothing is hit.
+#[allow(clippy::too_many_arguments)]
+pub fn inspect_pick_system(
+    mouse: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    editor: Res<Editor>,
+    mut selection: ResMut<Selection>,
+    mut contexts: EguiContexts) {
+    //The editor handles clicks in edit mode, and the charts window its own clicks
+    if !mouse.just_pressed(MouseButton::Left) || editor.active || pointer_over_ui(&mut contexts) {
+        return;
+    }
+    let Some(cursor) = windows.get_single().ok().and_then(|window| window.cursor_position()) else { return };
+    //Clicks on the replay timeline scrub instead
+    if replay.is_some() && cursor.y <= TIMELINE_HEIGHT {
+        return;
+    }
+    let Some(ray) = cameras.get_single().ok().and_then(|(camera,transform)| camera.viewport_to_world(transform,cursor)) else { return };
+
+    //The vehicle closest to the camera along the ray wins
+    let vehicles:Vec<(usize,Vec3,f32)> = match &replay {
+        Some(replay) => replay.vehicles().iter().map(|record| (record.id,Vec3::new(record.x,record.y,record.z),record.vehicle_type.params().length)).collect(),
+        None => world.vehicles.iter().map(|vehicle| (vehicle.id,vehicle_transform(vehicle,&world).translation,vehicle.vehicle_type.params().length)).collect(),
+    };
+    let vehicle = vehicles.iter()
+        .filter_map(|&(id,center,length)| {
+            let along = (center - ray.origin).dot(ray.direction);
+            let distance = (ray.origin + ray.direction * along.max(0.0)).distance(center);
+            (distance <= VEHICLE_PICK_RADIUS.max(length / 2.0)).then_some((id,along))
+        })
+        .min_by_key(|&(_,along)| OrderedFloat(along));
+    if let Some((id,_)) = vehicle {
+        *selection = Selection::Vehicle(id);
+        return;
+    }
+
+    //Otherwise the road under the cursor
+    *selection = match pick_road(&world,ray) {
+        Some(index) => Selection::Road(index),
+        None => Selection::None,
+    };
+}
+
+// Show the live state of the selection in the inspector panel.
+pub fn inspector_panel_system(
+    selection: Res<Selection>,
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    mut text_query: Query<&mut Text, With<InspectorText>>) {
+    let report = match *selection {
+        Selection::None => String::new(),
+        //Replayed vehicles only have their recorded state
+        Selection::Vehicle(id) => match &replay {
+            Some(replay) => match replay.vehicles().iter().find(|record| record.id == id) {
+                Some(record) => format!("Vehicle {} ({})\nRoad: {}\nPosition: {:.1} m\nVelocity: {:.1} m/s\nAcceleration: {:.1} m/s2", record.id, record.vehicle_type.name(), record.road, record.position, record.velocity, record.acceleration),
+                None => format!("Vehicle {} is not in the recording at {:.1} s", id, replay.time),
+            },
+            None => world.vehicle_report(id).unwrap_or_else(|| format!("Vehicle {} left the network", id)),
+        },
+        Selection::Road(index) => world.road_report(index).unwrap_or_default(),
+    };
+    for mut text in &mut text_query {
+        if text.sections[0].value != report {
+            text.sections[0].value = report.clone();
+        }
+    }
+}
+
+// Color the selected road, or the current road and remaining path of the selected vehicle,
+// over the road colors of the overlay.
+pub fn route_highlight_system(
+    selection: Res<Selection>,
+    overlay: Res<Overlay>,
+    world: Res<World>,
+    replay: Option<Res<Replay>>,
+    roads: Query<(&BevyRoad, &Handle<StandardMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>) {
+    let highlighted:Vec<usize> = match *selection {
+        Selection::None => Vec::new(),
+        Selection::Road(index) => vec![index],
+        Selection::Vehicle(id) => match &replay {
+            Some(replay) => replay.vehicles().iter().filter(|record| record.id == id).map(|record| record.road).collect(),
+            None => world.vehicles.iter().find(|vehicle| vehicle.id == id)
+                .map(|vehicle| std::iter::once(vehicle.on_road).chain(vehicle.path.iter().copied()).collect())
+                .unwrap_or_default(),
+        },
+    };
+    let road_colors = overlay.road_colors(&world,&shown_vehicles(&world,replay.as_deref()));
+    for (road,handle) in &roads {
+        let Some(&road_color) = road_colors.get(road.0) else { continue };
+        let color = if highlighted.contains(&road.0) { HIGHLIGHT_COLOR } else { road_color };
+        //Only touch materials that change, a changed material is uploaded again
+        if materials.get(handle).is_some_and(|material| material.base_color != color) {
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color = color;
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Purpose: Periodic per-road flow, density and space-mean speed (Edie's definitions).
+
+use crate::comp::World;
+use crate::rng::csv_writer_with_seed;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Aggregated state of one road over one interval.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LinkMeasurement{
+    pub road: usize,
+    pub start: f32,
+    pub end: f32,
+    // Vehicles per hour.
+    pub flow: f32,
+    // Vehicles per kilometer.
+    pub density: f32,
+    // Meters per second, None when no vehicle was on the road.
+    pub speed: Option<f32>,
+}
+
+// Distance travelled and time spent by all vehicles on every road, turned into measurements every interval.
+// Flow is the distance travelled over road length and interval, density the time spent over road length and interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkStatistics{
+    pub interval: f32,
+    pub measurements: Vec<LinkMeasurement>,
+    period_start: f32,
+    distance: Vec<f32>,
+    time: Vec<f32>,
+    last_positions: HashMap<usize,(usize,f32)>,
+}
+
+impl LinkStatistics{
+    pub fn new(interval:f32,start:f32) -> LinkStatistics{
+        LinkStatistics{
+            interval,
+            measurements: Vec::new(),
+            period_start: start,
+            distance: Vec::new(),
+            time: Vec::new(),
+            last_positions: HashMap::new(),
+        }
+    }
+
+    // Measurements of one road, oldest first.
+    pub fn road_measurements(&self,road:usize) -> impl Iterator<Item = &LinkMeasurement>{
+        self.measurements.iter().filter(move |measurement| measurement.road == road)
+    }
+
+    // Drop the running totals of a removed road, the roads after it move down one index.
+    // Finished measurements keep the road indices they were taken with.
+    pub(crate) fn remove_road(&mut self,index:usize){
+        for totals in [&mut self.distance,&mut self.time]{
+            if index < totals.len(){
+                totals.remove(index);
+            }
+        }
+        self.last_positions.retain(|_,(road,_)| *road != index);
+        self.last_positions.values_mut().filter(|(road,_)| *road > index).for_each(|(road,_)| *road -= 1);
+    }
+
+    // Start empty running totals for a road inserted at an index, the roads from it on move up one index.
+    pub(crate) fn insert_road(&mut self,index:usize){
+        for totals in [&mut self.distance,&mut self.time]{
+            if index <= totals.len(){
+                totals.insert(index,0.0);
+            }
+        }
+        self.last_positions.values_mut().filter(|(road,_)| *road >= index).for_each(|(road,_)| *road += 1);
+    }
+}
+
+impl World{
+    // Start aggregating link statistics every interval seconds of simulation time.
+    pub fn start_link_statistics(&mut self,interval:f32){
+        self.link_statistics = Some(LinkStatistics::new(interval,self.time));
+    }
+
+    // Observe the vehicles after a simulation step of t seconds.
+    pub fn update_link_statistics(&mut self,t:f32){
+        let statistics = match &mut self.link_statistics{
+            Some(statistics) => statistics,
+            None => return,
+        };
+        statistics.distance.resize(self.roads.len(),0.0);
+        statistics.time.resize(self.roads.len(),0.0);
+
+        let mut positions = HashMap::new();
+        for vehicle in self.vehicles.iter(){
+            positions.insert(vehicle.id,(vehicle.on_road,vehicle.position));
+            statistics.time[vehicle.on_road] += t;
+            //Split the distance at the road end when the vehicle changed roads
+            match statistics.last_positions.get(&vehicle.id){
+                Some(&(road,position)) if road == vehicle.on_road => {
+                    statistics.distance[road] += (vehicle.position - position).max(0.0);
+                }
+                Some(&(road,position)) if road < self.roads.len() => {
+                    statistics.distance[road] += (self.roads[road].length.into_inner() - position).max(0.0);
+                    statistics.distance[vehicle.on_road] += vehicle.position;
+                }
+                _ => {}
+            }
+        }
+        statistics.last_positions = positions;
+
+        if self.time >= statistics.period_start + statistics.interval{
+            let duration = self.time - statistics.period_start;
+            for (road_index,road) in self.roads.iter().enumerate(){
+                let area = road.length.into_inner() * duration;
+                if area <= 0.0{
+                    continue;
+                }
+                let distance = statistics.distance[road_index];
+                let time = statistics.time[road_index];
+                statistics.measurements.push(LinkMeasurement{
+                    road: road_index,
+                    start: statistics.period_start,
+                    end: self.time,
+                    flow: distance / area * 3600.0,
+                    density: time / area * 1000.0,
+                    speed: if time > 0.0 { Some(distance / time) } else { None },
+                });
+            }
+            statistics.period_start = self.time;
+            statistics.distance.iter_mut().for_each(|distance| *distance = 0.0);
+            statistics.time.iter_mut().for_each(|time| *time = 0.0);
+        }
+    }
+
+    // Write the link measurements to CSV, only for one road if given.
+    pub fn save_link_statistics(&self,path:&Path,road:Option<usize>) -> Result<(),String>{
+        let statistics = self.link_statistics.as_ref().ok_or("Link statistics are not being collected.")?;
+        let mut writer = csv_writer_with_seed(path,self.rng.seed())?;
+        writer.write_record(["road","start","end","flow","density","speed"]).map_err(|e| e.to_string())?;
+        for measurement in statistics.measurements.iter().filter(|measurement| road.is_none_or(|road| measurement.road == road)){
+            writer.write_record(&[
+                measurement.road.to_string(),
+                measurement.start.to_string(),
+                measurement.end.to_string(),
+                measurement.flow.to_string(),
+                measurement.density.to_string(),
+                measurement.speed.map_or(String::new(),|speed| speed.to_string()),
+            ]).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())
+    }
+
+    // Plot the flow-density fundamental diagram of a road as an SVG scatter plot.
+    pub fn save_fundamental_diagram_svg(&self,path:&Path,road:usize) -> Result<(),String>{
+        let statistics = self.link_statistics.as_ref().ok_or("Link statistics are not being collected.")?;
+        let points:Vec<(f32,f32)> = statistics.road_measurements(road).map(|measurement| (measurement.density,measurement.flow)).collect();
+        let (width,height,margin) = (640.0,480.0,60.0);
+        //Round the axes up to a multiple of ten
+        let max_density = (points.iter().map(|point| point.0).fold(10.0,f32::max) / 10.0).ceil() * 10.0;
+        let max_flow = (points.iter().map(|point| point.1).fold(100.0,f32::max) / 100.0).ceil() * 100.0;
+        let x = |density:f32| margin + density / max_density * (width - 2.0 * margin);
+        let y = |flow:f32| height - margin - flow / max_flow * (height - 2.0 * margin);
+
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">\n", width, height);
+        svg += &format!("<!-- seed: {} -->\n", self.rng.seed());
+        svg += &format!("<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n", width, height);
+        svg += &format!("<text x=\"{}\" y=\"24\" text-anchor=\"middle\" font-size=\"16\">Fundamental diagram of road {}</text>\n", width / 2.0, road);
+        svg += &format!("<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"black\"/>\n", margin, height - margin, width - margin);
+        svg += &format!("<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\"/>\n", margin, height - margin, margin);
+        for i in 0..=5{
+            let density = max_density * i as f32 / 5.0;
+            let flow = max_flow * i as f32 / 5.0;
+            svg += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n", x(density), height - margin + 16.0, density);
+            svg += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n", margin - 6.0, y(flow) + 4.0, flow);
+        }
+        svg += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Density (veh/km)</text>\n", width / 2.0, height - 16.0);
+        svg += &format!("<text x=\"16\" y=\"{}\" text-anchor=\"middle\" transform=\"rotate(-90 16 {})\">Flow (veh/h)</text>\n", height / 2.0, height / 2.0);
+        for (density,flow) in points{
+            svg += &format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"steelblue\"/>\n", x(density), y(flow));
+        }
+        svg += "</svg>\n";
+        std::fs::write(path,svg).map_err(|e| e.to_string())
+    }
+}
@@ -1,8 +1,39 @@
+mod camera;
+mod charts;
 mod comp;
+mod contraction;
+mod demand;
+mod detector;
+mod editor;
+mod follow;
 mod phy;
+mod recorder;
+mod replay;
+mod geojson;
 mod gui;
+mod headless;
+mod heatmap;
+mod history;
+mod inspect;
+mod link_stats;
+mod markings;
+mod opendrive;
+mod osm;
+mod rng;
+mod routing;
+mod snapshot;
+mod sumo;
+mod trip;
+mod vehicle_type;
 
 fn main() {
+    //Command line tools run without the GUI
+    let args:Vec<String> = std::env::args().collect();
+    match args.get(1).map(|arg| arg.as_str()){
+        Some("run") => return headless::run(&args[2..]),
+        Some("fundamental-diagram") => return headless::fundamental_diagram(&args[2..]),
+        _ => {}
+    }
     gui::run();
     // for i in 0..30{
     //     phy::update(1.0,&mut world);
@@ -0,0 +1,172 @@
+// Purpose: Road markings: edge lines, dashed lane separators, direction arrows and stop lines.
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use crate::comp::{Road, World};
+use crate::gui::{road_transform, road_width, LANE_WIDTH};
+use std::collections::HashMap;
+
+const LINE_WIDTH: f32 = 0.15;
+
+// Lane separators are dashed, lengths in meters.
+const DASH_LENGTH: f32 = 3.0;
+const DASH_GAP: f32 = 6.0;
+
+// Arrows repeat along every lane, the last one ends this far before the road end, in meters.
+const ARROW_SPACING: f32 = 50.0;
+const ARROW_END_OFFSET: f32 = 8.0;
+const ARROW_LENGTH: f32 = 5.0;
+const ARROW_HEAD_LENGTH: f32 = 1.5;
+const ARROW_HEAD_WIDTH: f32 = 1.2;
+const ARROW_SHAFT_WIDTH: f32 = 0.3;
+
+// Stop line across the road end, in meters.
+const STOP_LINE_WIDTH: f32 = 0.4;
+const STOP_LINE_OFFSET: f32 = 1.0;
+
+// Height of the markings above the road surface so they are not hidden by it.
+const MARKING_HEIGHT: f32 = 0.05;
+
+const MARKING_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+
+// Markings of a road, with the geometry they were built for.
+#[derive(Component)]
+pub struct RoadMarkings{
+    road: usize,
+    geometry: Geometry,
+}
+
+// Road ends, lanes and whether the road has a stop line.
+type Geometry = ((f32,f32,f32),(f32,f32,f32),u8,bool);
+
+fn geometry(road:&Road) -> Geometry{
+    (road.from,road.to,road.lanes,has_stop_line(road))
+}
+
+// Roads where vehicles have to slow down at the end get a stop line.
+fn has_stop_line(road:&Road) -> bool{
+    road.signal || road.end_speed_limit < road.speed_limit
+}
+
+// Flat triangles in the road frame, x along the road from its middle and y to its left.
+#[derive(Default)]
+struct MarkingMesh{
+    positions: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+}
+
+impl MarkingMesh{
+    fn triangle(&mut self,a:Vec2,b:Vec2,c:Vec2){
+        let first = self.positions.len() as u32;
+        self.positions.extend([a,b,c].map(|point| [point.x,point.y,MARKING_HEIGHT]));
+        self.indices.extend([first,first + 1,first + 2]);
+    }
+
+    fn rectangle(&mut self,x:(f32,f32),y:(f32,f32)){
+        let corners = [Vec2::new(x.0,y.0),Vec2::new(x.1,y.0),Vec2::new(x.1,y.1),Vec2::new(x.0,y.1)];
+        self.triangle(corners[0],corners[1],corners[2]);
+        self.triangle(corners[0],corners[2],corners[3]);
+    }
+
+    // Arrow along the road with its tip at x.
+    fn arrow(&mut self,tip:f32,y:f32){
+        let head = tip - ARROW_HEAD_LENGTH;
+        self.rectangle((tip - ARROW_LENGTH,head),(y - ARROW_SHAFT_WIDTH / 2.0,y + ARROW_SHAFT_WIDTH / 2.0));
+        self.triangle(Vec2::new(head,y - ARROW_HEAD_WIDTH / 2.0),Vec2::new(tip,y),Vec2::new(head,y + ARROW_HEAD_WIDTH / 2.0));
+    }
+
+    fn into_mesh(self) -> Mesh{
+        let count = self.positions.len();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION,self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL,vec![[0.0,0.0,1.0]; count]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0,vec![[0.0,0.0]; count]);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+// Markings of a road in its frame, as drawn by road_transform without the scale.
+fn marking_mesh(road:&Road) -> Mesh{
+    let from = Vec3::new(road.from.0,road.from.1,road.from.2);
+    let to = Vec3::new(road.to.0,road.to.1,road.to.2);
+    let (start,end) = (-(to - from).length() / 2.0,(to - from).length() / 2.0);
+    let half_width = road_width(road) / 2.0;
+    let lanes = road.lanes.max(1);
+    let stop_line = has_stop_line(road) && end - start > STOP_LINE_OFFSET + STOP_LINE_WIDTH;
+    let mut markings = MarkingMesh::default();
+
+    //Edge lines
+    markings.rectangle((start,end),(-half_width,-half_width + LINE_WIDTH));
+    markings.rectangle((start,end),(half_width - LINE_WIDTH,half_width));
+
+    //Lane separators stop at the stop line
+    let dashes_end = if stop_line { end - STOP_LINE_OFFSET - STOP_LINE_WIDTH } else { end };
+    for lane in 1..lanes{
+        let y = -half_width + lane as f32 * LANE_WIDTH;
+        let mut x = start;
+        while x < dashes_end{
+            markings.rectangle((x,(x + DASH_LENGTH).min(dashes_end)),(y - LINE_WIDTH / 2.0,y + LINE_WIDTH / 2.0));
+            x += DASH_LENGTH + DASH_GAP;
+        }
+    }
+
+    //Arrows in every lane, back from the road end, or one in the middle of a short road
+    let mut tips:Vec<f32> = Vec::new();
+    let mut tip = end - ARROW_END_OFFSET;
+    while tip - ARROW_LENGTH >= start{
+        tips.push(tip);
+        tip -= ARROW_SPACING;
+    }
+    if tips.is_empty() && end - start > ARROW_LENGTH{
+        tips.push(ARROW_LENGTH / 2.0);
+    }
+    for lane in 0..lanes{
+        let y = -half_width + (lane as f32 + 0.5) * LANE_WIDTH;
+        for &tip in tips.iter(){
+            markings.arrow(tip,y);
+        }
+    }
+
+    if stop_line{
+        markings.rectangle((end - STOP_LINE_OFFSET - STOP_LINE_WIDTH,end - STOP_LINE_OFFSET),(-half_width,half_width));
+    }
+    markings.into_mesh()
+}
+
+// Build the markings of new roads and rebuild them when a road is moved, changes lanes or is removed.
+pub fn road_markings_system(
+    world: Res<World>,
+    markings: Query<(Entity, &RoadMarkings)>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut material: Local<Option<Handle<StandardMaterial>>>) {
+    let mut current:HashMap<usize,Entity> = HashMap::new();
+    for (entity,marking) in &markings {
+        let up_to_date = world.roads.get(marking.road).is_some_and(|road| geometry(road) == marking.geometry);
+        if up_to_date && !current.contains_key(&marking.road) {
+            current.insert(marking.road,entity);
+        }
+        else {
+            commands.entity(entity).despawn();
+        }
+    }
+    if current.len() == world.roads.len() {
+        return;
+    }
+    //Markings are the same color everywhere and not lit, so they read like paint
+    let material = material.get_or_insert_with(|| materials.add(StandardMaterial {
+        base_color: MARKING_COLOR,
+        unlit: true,
+        ..default()
+    })).clone();
+    for (index,road) in world.roads.iter().enumerate().filter(|(index,_)| !current.contains_key(index)) {
+        commands.spawn((PbrBundle {
+            mesh: meshes.add(marking_mesh(road)),
+            material: material.clone(),
+            transform: Transform { scale: Vec3::ONE, ..road_transform(road) },
+            ..default()
+        },RoadMarkings { road: index, geometry: geometry(road) }));
+    }
+}
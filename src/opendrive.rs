@@ -0,0 +1,685 @@
+// Purpose: Import ASAM OpenDRIVE (.xodr) road networks into the road graph.
+
+use crate::comp::{World, JUNCTION_SPEED, SIGNAL_SPEED};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Longest road sampled from a curved reference line, in meters.
+const CURVE_STEP: f64 = 5.0;
+
+// Longest road sampled from a straight reference line, in meters.
+const LINE_STEP: f64 = 25.0;
+
+// Speed limit of roads without a speed record, 50 km/h.
+const DEFAULT_SPEED: f32 = 13.89;
+
+// Lane types vehicles drive on.
+const DRIVING_LANE_TYPES: [&str; 6] = ["driving", "entry", "exit", "onRamp", "offRamp", "connectingRamp"];
+
+// Cubic polynomial a + b*ds + c*ds² + d*ds³ starting at s.
+#[derive(Debug, Clone, Copy)]
+struct Polynomial{
+    s: f64,
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+}
+
+impl Polynomial{
+    fn parse(node:&roxmltree::Node,s:&str) -> Polynomial{
+        Polynomial{
+            s: parse_attribute(node,s).unwrap_or(0.0),
+            a: parse_attribute(node,"a").unwrap_or(0.0),
+            b: parse_attribute(node,"b").unwrap_or(0.0),
+            c: parse_attribute(node,"c").unwrap_or(0.0),
+            d: parse_attribute(node,"d").unwrap_or(0.0),
+        }
+    }
+
+    fn value(&self,s:f64) -> f64{
+        let ds = s - self.s;
+        self.a + ds * (self.b + ds * (self.c + ds * self.d))
+    }
+}
+
+// Value of the last polynomial starting at or before s, 0 without polynomials.
+fn evaluate(polynomials:&[Polynomial],s:f64) -> f64{
+    match polynomials.iter().rev().find(|polynomial| polynomial.s <= s).or(polynomials.first()){
+        Some(polynomial) => polynomial.value(s),
+        None => 0.0,
+    }
+}
+
+fn parse_attribute(node:&roxmltree::Node,name:&str) -> Option<f64>{
+    node.attribute(name)?.trim().parse().ok()
+}
+
+// Convert a speed record to m/s, OpenDRIVE speeds are in m/s unless a unit is given.
+fn parse_speed(node:&roxmltree::Node) -> Option<f32>{
+    let max = parse_attribute(node,"max")? as f32;
+    match node.attribute("unit"){
+        Some("km/h") => Some(max / 3.6),
+        Some("mph") => Some(max * 0.44704),
+        _ => Some(max),
+    }
+}
+
+// Default speed of a road type in m/s.
+fn road_type_speed(road_type:&str) -> f32{
+    match road_type{
+        "motorway" => 130.0 / 3.6,
+        "rural" => 100.0 / 3.6,
+        "lowSpeed" => 30.0 / 3.6,
+        "pedestrian" => 5.0 / 3.6,
+        "bicycle" => 25.0 / 3.6,
+        _ => DEFAULT_SPEED,
+    }
+}
+
+// Shape of a piece of the reference line.
+#[derive(Debug, Clone, Copy)]
+enum Shape{
+    Line,
+    Arc{curvature: f64},
+    Spiral{curv_start: f64, curv_end: f64},
+    Poly3{a: f64, b: f64, c: f64, d: f64},
+    ParamPoly3{u: [f64; 4], v: [f64; 4], normalized: bool},
+}
+
+// A piece of the reference line starting at s.
+#[derive(Debug, Clone, Copy)]
+struct Geometry{
+    s: f64,
+    x: f64,
+    y: f64,
+    hdg: f64,
+    length: f64,
+    shape: Shape,
+}
+
+impl Geometry{
+    // Position and heading of the reference line at s.
+    fn evaluate(&self,s:f64) -> (f64,f64,f64){
+        let ds = (s - self.s).clamp(0.0,self.length);
+        let (cos,sin) = (self.hdg.cos(),self.hdg.sin());
+        //Local coordinates (u along the start heading, v to its left) and local heading
+        let (u,v,heading) = match self.shape{
+            Shape::Line => (ds,0.0,0.0),
+            Shape::Arc{curvature} if curvature.abs() < 1e-12 => (ds,0.0,0.0),
+            Shape::Arc{curvature} => {
+                let angle = curvature * ds;
+                (angle.sin() / curvature,(1.0 - angle.cos()) / curvature,angle)
+            }
+            Shape::Spiral{curv_start,curv_end} => {
+                //Integrate the heading of the clothoid with Simpson's rule
+                let rate = if self.length > 0.0 { (curv_end - curv_start) / self.length } else { 0.0 };
+                let angle = |t:f64| curv_start * t + rate * t * t / 2.0;
+                let steps = ((ds / 0.5).ceil() as usize).max(1) * 2;
+                let h = ds / steps as f64;
+                let (mut u,mut v) = (0.0,0.0);
+                for i in 0..=steps{
+                    let weight = if i == 0 || i == steps { 1.0 } else if i % 2 == 1 { 4.0 } else { 2.0 };
+                    let t = angle(i as f64 * h);
+                    u += weight * t.cos();
+                    v += weight * t.sin();
+                }
+                (u * h / 3.0,v * h / 3.0,angle(ds))
+            }
+            Shape::Poly3{a,b,c,d} => {
+                //The offset is given along the start heading, approximate u by the distance travelled
+                let v = a + ds * (b + ds * (c + ds * d));
+                let slope = b + ds * (2.0 * c + ds * 3.0 * d);
+                (ds,v,slope.atan())
+            }
+            Shape::ParamPoly3{u,v,normalized} => {
+                let p = if normalized && self.length > 0.0 { ds / self.length } else { ds };
+                let cubic = |k:[f64; 4]| k[0] + p * (k[1] + p * (k[2] + p * k[3]));
+                let derivative = |k:[f64; 4]| k[1] + p * (2.0 * k[2] + p * 3.0 * k[3]);
+                (cubic(u),cubic(v),derivative(v).atan2(derivative(u)))
+            }
+        };
+        (self.x + u * cos - v * sin,self.y + u * sin + v * cos,self.hdg + heading)
+    }
+}
+
+// A lane of a lane section, positive ids are left of the reference line and negative ids right of it.
+#[derive(Debug, Clone)]
+struct Lane{
+    id: i32,
+    driving: bool,
+    widths: Vec<Polynomial>,
+    speed: Option<f32>,
+}
+
+// Lanes valid from s to the start of the next section.
+#[derive(Debug, Clone)]
+struct LaneSection{
+    s: f64,
+    lanes: Vec<Lane>,
+}
+
+impl LaneSection{
+    // Lateral offset of the middle of the driving lanes on one side and their count.
+    // Right hand traffic, lanes right of the reference line run forward.
+    fn driving_lanes(&self,s:f64,offset:f64,forward:bool) -> Option<(f64,u8)>{
+        let mut side:Vec<&Lane> = self.lanes.iter().filter(|lane| if forward { lane.id < 0 } else { lane.id > 0 }).collect();
+        side.sort_by_key(|lane| lane.id.abs());
+        let sign = if forward { -1.0 } else { 1.0 };
+        let mut inner = offset;
+        let (mut first,mut last,mut count) = (None,0.0,0u8);
+        for lane in side{
+            //Widths are relative to the start of the section
+            let outer = inner + sign * evaluate(&lane.widths,s - self.s).max(0.0);
+            if lane.driving{
+                first.get_or_insert(inner);
+                last = outer;
+                count = count.saturating_add(1);
+            }
+            inner = outer;
+        }
+        first.map(|first| ((first + last) / 2.0,count))
+    }
+
+    fn speed(&self,forward:bool) -> Option<f32>{
+        self.lanes.iter()
+            .filter(|lane| lane.driving && (lane.id < 0) == forward)
+            .filter_map(|lane| lane.speed)
+            .reduce(f32::max)
+    }
+}
+
+// Predecessor or successor of a road.
+#[derive(Debug, Clone)]
+struct Link{
+    junction: bool,
+    id: String,
+    // Whether the link touches the start of the linked road.
+    start: bool,
+}
+
+#[derive(Debug, Clone)]
+struct OpenDriveRoad{
+    id: String,
+    length: f64,
+    geometries: Vec<Geometry>,
+    elevation: Vec<Polynomial>,
+    lane_offset: Vec<Polynomial>,
+    sections: Vec<LaneSection>,
+    predecessor: Option<Link>,
+    successor: Option<Link>,
+    speed: f32,
+    // Dynamic signals controlling the forward and backward direction.
+    signals: (bool,bool),
+}
+
+impl OpenDriveRoad{
+    fn geometry(&self,s:f64) -> Option<&Geometry>{
+        self.geometries.iter().rev().find(|geometry| geometry.s <= s).or(self.geometries.first())
+    }
+
+    // Point at s with a lateral offset to the left of the reference line.
+    fn point(&self,s:f64,t:f64) -> Option<(f32,f32,f32)>{
+        let (x,y,heading) = self.geometry(s)?.evaluate(s);
+        Some(((x - t * heading.sin()) as f32,(y + t * heading.cos()) as f32,evaluate(&self.elevation,s) as f32))
+    }
+
+    // Section index covering s.
+    fn section(&self,s:f64) -> usize{
+        self.sections.iter().rposition(|section| section.s <= s).unwrap_or(0)
+    }
+}
+
+// A movement through a junction from an incoming road onto a connecting road.
+#[derive(Debug, Clone)]
+struct JunctionConnection{
+    incoming: String,
+    connecting: String,
+    // Whether the connecting road is entered at its start.
+    start: bool,
+    lane_links: Vec<(i32,i32)>,
+}
+
+// Roads and junctions read from an OpenDRIVE file.
+#[derive(Default)]
+pub struct OpenDriveData{
+    roads: Vec<OpenDriveRoad>,
+    junctions: HashMap<String,Vec<JunctionConnection>>,
+    // Latitude and longitude of the projection origin given in the geo reference.
+    origin: Option<(f64,f64)>,
+}
+
+fn parse_link(node:Option<roxmltree::Node>) -> Option<Link>{
+    let node = node?;
+    Some(Link{
+        junction: node.attribute("elementType") == Some("junction"),
+        id: node.attribute("elementId")?.to_string(),
+        start: node.attribute("contactPoint") != Some("end"),
+    })
+}
+
+// First child element with the given name.
+fn child<'a,'input>(node:roxmltree::Node<'a,'input>,name:&str) -> Option<roxmltree::Node<'a,'input>>{
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+// Read the projection origin from a PROJ string such as "+proj=tmerc +lat_0=48.1 +lon_0=11.5".
+fn parse_geo_reference(text:&str) -> Option<(f64,f64)>{
+    let value = |key:&str| text.split_whitespace().find_map(|part| part.strip_prefix(key)?.parse::<f64>().ok());
+    Some((value("+lat_0=")?,value("+lon_0=")?))
+}
+
+pub fn parse_xodr(contents:&str) -> Result<OpenDriveData,String>{
+    let document = roxmltree::Document::parse(contents).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+    let mut data = OpenDriveData{
+        origin: child(root,"header")
+            .and_then(|header| child(header,"geoReference"))
+            .and_then(|reference| parse_geo_reference(reference.text()?)),
+        ..Default::default()
+    };
+
+    for road in root.children().filter(|node| node.has_tag_name("road")){
+        let id = road.attribute("id").ok_or("Road without id")?.to_string();
+
+        let mut geometries = Vec::new();
+        for geometry in child(road,"planView").iter().flat_map(|plan| plan.children()).filter(|node| node.has_tag_name("geometry")){
+            let attribute = |node:&roxmltree::Node,name:&str| parse_attribute(node,name).unwrap_or(0.0);
+            let shape = match geometry.children().find(|node| node.is_element()){
+                Some(shape) if shape.has_tag_name("arc") => Shape::Arc{curvature: attribute(&shape,"curvature")},
+                Some(shape) if shape.has_tag_name("spiral") => Shape::Spiral{curv_start: attribute(&shape,"curvStart"),curv_end: attribute(&shape,"curvEnd")},
+                Some(shape) if shape.has_tag_name("poly3") => Shape::Poly3{a: attribute(&shape,"a"),b: attribute(&shape,"b"),c: attribute(&shape,"c"),d: attribute(&shape,"d")},
+                Some(shape) if shape.has_tag_name("paramPoly3") => Shape::ParamPoly3{
+                    u: [attribute(&shape,"aU"),attribute(&shape,"bU"),attribute(&shape,"cU"),attribute(&shape,"dU")],
+                    v: [attribute(&shape,"aV"),attribute(&shape,"bV"),attribute(&shape,"cV"),attribute(&shape,"dV")],
+                    normalized: shape.attribute("pRange") != Some("arcLength"),
+                },
+                _ => Shape::Line,
+            };
+            geometries.push(Geometry{
+                s: attribute(&geometry,"s"),
+                x: attribute(&geometry,"x"),
+                y: attribute(&geometry,"y"),
+                hdg: attribute(&geometry,"hdg"),
+                length: attribute(&geometry,"length"),
+                shape,
+            });
+        }
+        if geometries.is_empty(){
+            continue;
+        }
+        geometries.sort_by(|a,b| a.s.total_cmp(&b.s));
+
+        let elevation = child(road,"elevationProfile").iter().flat_map(|profile| profile.children())
+            .filter(|node| node.has_tag_name("elevation"))
+            .map(|node| Polynomial::parse(&node,"s"))
+            .collect();
+
+        let lanes_node = child(road,"lanes");
+        let lane_offset = lanes_node.iter().flat_map(|lanes| lanes.children())
+            .filter(|node| node.has_tag_name("laneOffset"))
+            .map(|node| Polynomial::parse(&node,"s"))
+            .collect();
+        let mut sections = Vec::new();
+        for section in lanes_node.iter().flat_map(|lanes| lanes.children()).filter(|node| node.has_tag_name("laneSection")){
+            let mut lanes = Vec::new();
+            for lane in section.descendants().filter(|node| node.has_tag_name("lane")){
+                let lane_id:i32 = lane.attribute("id").and_then(|lane_id| lane_id.parse().ok()).unwrap_or(0);
+                if lane_id == 0{
+                    continue;
+                }
+                lanes.push(Lane{
+                    id: lane_id,
+                    driving: DRIVING_LANE_TYPES.contains(&lane.attribute("type").unwrap_or("driving")),
+                    widths: lane.children().filter(|node| node.has_tag_name("width")).map(|node| Polynomial::parse(&node,"sOffset")).collect(),
+                    speed: lane.children().find(|node| node.has_tag_name("speed")).and_then(|node| parse_speed(&node)),
+                });
+            }
+            sections.push(LaneSection{s: parse_attribute(&section,"s").unwrap_or(0.0),lanes});
+        }
+        sections.sort_by(|a,b| a.s.total_cmp(&b.s));
+
+        //Speed of the road type, or the default of the type name
+        let speed = child(road,"type").map_or(DEFAULT_SPEED,|road_type| {
+            child(road_type,"speed").and_then(|speed| parse_speed(&speed)).unwrap_or(road_type_speed(road_type.attribute("type").unwrap_or("")))
+        });
+
+        let mut signals = (false,false);
+        for signal in child(road,"signals").iter().flat_map(|signals| signals.children()).filter(|node| node.has_tag_name("signal")){
+            if signal.attribute("dynamic") != Some("yes"){
+                continue;
+            }
+            match signal.attribute("orientation"){
+                Some("+") => signals.0 = true,
+                Some("-") => signals.1 = true,
+                _ => signals = (true,true),
+            }
+        }
+
+        let link = child(road,"link");
+        data.roads.push(OpenDriveRoad{
+            id,
+            length: parse_attribute(&road,"length").unwrap_or(0.0),
+            geometries,
+            elevation,
+            lane_offset,
+            sections,
+            predecessor: parse_link(link.and_then(|link| child(link,"predecessor"))),
+            successor: parse_link(link.and_then(|link| child(link,"successor"))),
+            speed,
+            signals,
+        });
+    }
+
+    for junction in root.children().filter(|node| node.has_tag_name("junction")){
+        let id = junction.attribute("id").ok_or("Junction without id")?.to_string();
+        let connections = junction.children().filter(|node| node.has_tag_name("connection")).filter_map(|connection| Some(JunctionConnection{
+            incoming: connection.attribute("incomingRoad")?.to_string(),
+            connecting: connection.attribute("connectingRoad")?.to_string(),
+            start: connection.attribute("contactPoint") != Some("end"),
+            lane_links: connection.children().filter(|node| node.has_tag_name("laneLink")).filter_map(|lane_link| {
+                Some((lane_link.attribute("from")?.parse().ok()?,lane_link.attribute("to")?.parse().ok()?))
+            }).collect(),
+        })).collect();
+        data.junctions.insert(id,connections);
+    }
+    Ok(data)
+}
+
+// A sampled piece of a road in one direction of travel.
+struct Segment{
+    from: (f32,f32,f32),
+    to: (f32,f32,f32),
+    lanes: u8,
+    speed_limit: f32,
+}
+
+impl World{
+    // Replace the world with the road network of an OpenDRIVE file.
+    // A file that cannot be read leaves the world unchanged.
+    pub fn load_opendrive(&mut self,path:&Path) -> Result<(),String>{
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let data = parse_xodr(&contents)?;
+        self.load_opendrive_data(&data);
+        Ok(())
+    }
+
+    // Build roads and connections from OpenDRIVE roads and junctions.
+    // The reference line of every road is sampled into short roads per direction of travel,
+    // running along the middle of the driving lanes of that direction.
+    pub fn load_opendrive_data(&mut self,data:&OpenDriveData){
+        self.reset();
+
+        //Sample every direction that has driving lanes along the whole road
+        let mut segments:Vec<Segment> = Vec::new();
+        let mut chains:HashMap<(&str,bool),Vec<usize>> = HashMap::new();
+        for road in data.roads.iter(){
+            let length = if road.length > 0.0 { road.length } else { road.geometries.iter().map(|geometry| geometry.s + geometry.length).fold(0.0,f64::max) };
+            if length <= 0.0 || road.sections.is_empty(){
+                continue;
+            }
+            let mut breaks:Vec<f64> = road.geometries.iter().map(|geometry| geometry.s).chain(road.sections.iter().map(|section| section.s)).filter(|&s| s > 0.0 && s < length).collect();
+            breaks.push(0.0);
+            breaks.push(length);
+            breaks.sort_by(|a,b| a.total_cmp(b));
+            breaks.dedup_by(|a,b| (*a - *b).abs() < 1e-6);
+
+            for forward in [true,false]{
+                let mut pieces = Vec::new();
+                let mut complete = true;
+                for interval in breaks.windows(2){
+                    let middle = (interval[0] + interval[1]) / 2.0;
+                    let step = match road.geometry(middle).map(|geometry| geometry.shape){
+                        Some(Shape::Line) => LINE_STEP,
+                        _ => CURVE_STEP,
+                    };
+                    let count = ((interval[1] - interval[0]) / step).ceil().max(1.0) as usize;
+                    let section = &road.sections[road.section(middle)];
+                    let speed_limit = section.speed(forward).unwrap_or(road.speed);
+                    for i in 0..count{
+                        let start = interval[0] + (interval[1] - interval[0]) * i as f64 / count as f64;
+                        let end = interval[0] + (interval[1] - interval[0]) * (i + 1) as f64 / count as f64;
+                        let lane_middle = |s:f64| section.driving_lanes(s,evaluate(&road.lane_offset,s),forward);
+                        match (lane_middle(start),lane_middle(end)){
+                            (Some((start_t,lanes)),Some((end_t,_))) => {
+                                if let (Some(from),Some(to)) = (road.point(start,start_t),road.point(end,end_t)){
+                                    pieces.push(Segment{from,to,lanes,speed_limit});
+                                }
+                            }
+                            _ => complete = false,
+                        }
+                    }
+                }
+                if !complete || pieces.is_empty(){
+                    continue;
+                }
+                //Backward pieces run against the reference line
+                if !forward{
+                    pieces.reverse();
+                    for piece in pieces.iter_mut(){
+                        std::mem::swap(&mut piece.from,&mut piece.to);
+                    }
+                }
+                let first = segments.len();
+                segments.extend(pieces);
+                chains.insert((road.id.as_str(),forward),(first..segments.len()).collect());
+            }
+        }
+        if segments.is_empty(){
+            return;
+        }
+
+        //Chain entered by reaching the start or end of a road
+        let entering = |id:&str,start:bool| chains.get(&(id,start)).map(|chain| chain[0]);
+        let roads:HashMap<&str,&OpenDriveRoad> = data.roads.iter().map(|road| (road.id.as_str(),road)).collect();
+        let mut to_road:Vec<Vec<usize>> = vec![Vec::new(); segments.len()];
+        let mut end_speed_limit:Vec<f32> = segments.iter().map(|segment| segment.speed_limit).collect();
+        let mut signal = vec![false; segments.len()];
+        for (&(id,forward),chain) in chains.iter(){
+            for pair in chain.windows(2){
+                to_road[pair[0]].push(pair[1]);
+                end_speed_limit[pair[0]] = segments[pair[0]].speed_limit.min(segments[pair[1]].speed_limit);
+            }
+            //Forward traffic leaves at the successor, backward traffic at the predecessor
+            let road = roads[id];
+            let last = chain[chain.len()-1];
+            let link = if forward { &road.successor } else { &road.predecessor };
+            let next:Vec<usize> = match link{
+                Some(link) if link.junction => {
+                    data.junctions.get(&link.id).into_iter().flatten()
+                        .filter(|connection| connection.incoming == id)
+                        .filter(|connection| connection.lane_links.is_empty() || connection.lane_links.iter().any(|&(from,_)| (from < 0) == forward))
+                        .filter_map(|connection| entering(&connection.connecting,connection.start))
+                        .collect()
+                }
+                Some(link) => entering(&link.id,link.start).into_iter().collect(),
+                None => Vec::new(),
+            };
+            let speed_limit = segments[last].speed_limit;
+            let has_signal = if forward { road.signals.0 } else { road.signals.1 };
+            end_speed_limit[last] = match link{
+                Some(link) if link.junction && has_signal => SIGNAL_SPEED,
+                Some(link) if link.junction => JUNCTION_SPEED.min(speed_limit),
+                _ if next.is_empty() => speed_limit,
+                _ => next.iter().map(|&next_road| segments[next_road].speed_limit).fold(speed_limit,f32::min),
+            };
+            signal[last] = has_signal && link.as_ref().is_some_and(|link| link.junction);
+            for next_road in next{
+                if !to_road[last].contains(&next_road){
+                    to_road[last].push(next_road);
+                }
+            }
+        }
+
+        let mut from_road:Vec<Vec<usize>> = vec![Vec::new(); segments.len()];
+        for (index,next) in to_road.iter().enumerate(){
+            for &next_road in next.iter(){
+                from_road[next_road].push(index);
+            }
+        }
+
+        for (index,segment) in segments.iter().enumerate(){
+            self.add_road(segment.from,segment.to,segment.lanes,segment.speed_limit,from_road[index].clone(),to_road[index].clone(),end_speed_limit[index]);
+            self.roads[index].signal = signal[index];
+        }
+        self.geo_origin = data.origin;
+
+        //Preprocess the network so vehicles can be routed quickly
+        self.build_route_index();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::vehicle_type::VehicleType;
+
+    // A straight two way road with two forward lanes and a signal, a one way connecting road through a junction,
+    // and a two way quarter circle turning left.
+    const XODR:&str = r#"<?xml version="1.0" standalone="yes"?>
+<OpenDRIVE>
+    <header revMajor="1" revMinor="6" name="test">
+        <geoReference><![CDATA[+proj=tmerc +lat_0=48.1 +lon_0=11.5 +ellps=WGS84]]></geoReference>
+    </header>
+    <road id="1" length="100" junction="-1">
+        <link>
+            <successor elementType="junction" elementId="100"/>
+        </link>
+        <type s="0" type="town">
+            <speed max="50" unit="km/h"/>
+        </type>
+        <planView>
+            <geometry s="0" x="0" y="0" hdg="0" length="100"><line/></geometry>
+        </planView>
+        <lanes>
+            <laneSection s="0">
+                <left>
+                    <lane id="1" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+                </left>
+                <center>
+                    <lane id="0" type="none"/>
+                </center>
+                <right>
+                    <lane id="-1" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+                    <lane id="-2" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+                    <lane id="-3" type="sidewalk"><width sOffset="0" a="2.0" b="0" c="0" d="0"/></lane>
+                </right>
+            </laneSection>
+        </lanes>
+        <signals>
+            <signal s="98" t="-8" id="10" dynamic="yes" orientation="+" type="1000001"/>
+        </signals>
+    </road>
+    <road id="3" length="10" junction="100">
+        <link>
+            <predecessor elementType="road" elementId="1" contactPoint="end"/>
+            <successor elementType="road" elementId="2" contactPoint="start"/>
+        </link>
+        <planView>
+            <geometry s="0" x="100" y="0" hdg="0" length="10"><line/></geometry>
+        </planView>
+        <lanes>
+            <laneSection s="0">
+                <right>
+                    <lane id="-1" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+                </right>
+            </laneSection>
+        </lanes>
+    </road>
+    <road id="2" length="78.5398163" junction="-1">
+        <planView>
+            <geometry s="0" x="110" y="0" hdg="0" length="78.5398163"><arc curvature="0.02"/></geometry>
+        </planView>
+        <lanes>
+            <laneSection s="0">
+                <left>
+                    <lane id="1" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+                </left>
+                <right>
+                    <lane id="-1" type="driving"><width sOffset="0" a="3.5" b="0" c="0" d="0"/><speed sOffset="0" max="10"/></lane>
+                </right>
+            </laneSection>
+        </lanes>
+    </road>
+    <junction id="100">
+        <connection id="0" incomingRoad="1" connectingRoad="3" contactPoint="start">
+            <laneLink from="-1" to="-1"/>
+        </connection>
+    </junction>
+</OpenDRIVE>"#;
+
+    fn assert_near(point:(f32,f32,f32),expected:(f32,f32)){
+        assert!((point.0 - expected.0).abs() < 1e-3 && (point.1 - expected.1).abs() < 1e-3,"{:?} is not at {:?}",point,expected);
+    }
+
+    #[test]
+    fn roads_follow_the_driving_lanes(){
+        let mut world = World::new();
+        world.load_opendrive_data(&parse_xodr(XODR).unwrap());
+
+        //Roads in file order: 4 pieces per direction of the straight road, 1 through the junction, 16 per direction of the arc
+        assert_eq!(world.roads.len(),41);
+        assert_eq!(world.geo_origin,Some((48.1,11.5)));
+
+        //Forward traffic drives in the middle of both right lanes, backward traffic in the left lane against the reference line
+        assert_near(world.roads[0].from,(0.0,-3.5));
+        assert_near(world.roads[3].to,(100.0,-3.5));
+        assert_eq!(world.roads[0].lanes,2);
+        assert!((world.roads[0].speed_limit - 50.0 / 3.6).abs() < 1e-4);
+        assert_near(world.roads[4].from,(100.0,1.75));
+        assert_near(world.roads[7].to,(0.0,1.75));
+        assert_eq!(world.roads[4].lanes,1);
+
+        //The signal stops forward traffic at the junction, which leads through the connecting road onto the arc
+        assert!(world.roads[3].signal && !world.roads[7].signal);
+        assert_eq!(world.roads[3].end_speed_limit,SIGNAL_SPEED);
+        assert!(world.get_connection(3,8).is_some());
+        assert!(world.get_connection(8,9).is_some());
+        assert_near(world.roads[8].from,(100.0,-1.75));
+
+        //The right lane of the arc has its own speed and ends a quarter circle further, its radius grown by half a lane
+        assert_eq!(world.roads[9].speed_limit,10.0);
+        assert_near(world.roads[9].from,(110.0,-1.75));
+        assert_near(world.roads[24].to,(161.75,50.0));
+        let length:f32 = world.roads[9..25].iter().map(|road| road.length.into_inner()).sum();
+        assert!((length - 81.25).abs() < 0.05,"Arc length {}",length);
+        assert_near(world.roads[25].from,(158.25,50.0));
+
+        //Backward traffic on the arc has no way back through the junction
+        assert!(world.roads[40].to_road.is_empty());
+        assert!(world.route_index.is_some());
+        assert!(world.find_shortest_path(0,24,VehicleType::Car).len() > 2);
+    }
+
+    #[test]
+    fn invalid_files_are_errors(){
+        assert!(parse_xodr("<OpenDRIVE><road length=\"10\"/></OpenDRIVE>").is_err());
+        assert!(parse_xodr("<OpenDRIVE>").is_err());
+        let mut world = World::new();
+        world.load_opendrive_data(&parse_xodr("<OpenDRIVE><road id=\"1\" length=\"10\"/></OpenDRIVE>").unwrap());
+        assert!(world.roads.is_empty());
+    }
+
+    #[test]
+    fn malformed_file_keeps_the_world(){
+        let mut world = World::new();
+        world.load_opendrive_data(&parse_xodr(XODR).unwrap());
+        let path = std::env::temp_dir().join(format!("trafast_opendrive_test_{}.xodr",std::process::id()));
+        std::fs::write(&path,&XODR[..XODR.len() / 2]).unwrap();
+        let result = world.load_opendrive(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert_eq!(world.roads.len(),41);
+    }
+
+    #[test]
+    fn geometries(){
+        //A spiral from a straight line to the curvature of a circle turns by half the angle of that arc
+        let spiral = Geometry{s: 0.0,x: 0.0,y: 0.0,hdg: 0.0,length: 50.0,shape: Shape::Spiral{curv_start: 0.0,curv_end: 0.02}};
+        assert!((spiral.evaluate(50.0).2 - 0.5).abs() < 1e-9);
+        let line = Geometry{s: 10.0,x: 1.0,y: 2.0,hdg: std::f64::consts::FRAC_PI_2,length: 5.0,shape: Shape::Line};
+        let (x,y,_) = line.evaluate(20.0);
+        assert!((x - 1.0).abs() < 1e-9 && (y - 7.0).abs() < 1e-9,"Points past the end are clamped");
+        assert_eq!(parse_geo_reference("+proj=utm +zone=32"),None);
+    }
+}
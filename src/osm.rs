@@ -0,0 +1,744 @@
+// Purpose: Import OpenStreetMap extracts (.osm XML and .osm.pbf) into the road graph.
+
+use crate::comp::{World, JUNCTION_SPEED, SIGNAL_SPEED};
+use flate2::read::ZlibDecoder;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+
+// Mean earth radius in meters, used for the local projection.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// A node of the extract with its position and whether it holds a traffic signal.
+struct OsmNode{
+    lat: f64,
+    lon: f64,
+    signal: bool,
+}
+
+// A way of the extract with its node references and tags.
+struct OsmWay{
+    nodes: Vec<i64>,
+    tags: HashMap<String,String>,
+}
+
+// Nodes and ways read from an extract.
+#[derive(Default)]
+pub struct OsmData{
+    nodes: HashMap<i64,OsmNode>,
+    ways: Vec<OsmWay>,
+}
+
+// Project a coordinate to local meters around the origin (latitude, longitude).
+pub fn project(lat:f64,lon:f64,origin:(f64,f64)) -> (f32,f32){
+    let x = EARTH_RADIUS * (lon - origin.1).to_radians() * origin.0.to_radians().cos();
+    let y = EARTH_RADIUS * (lat - origin.0).to_radians();
+    (x as f32,y as f32)
+}
+
+// Inverse of project, returns (latitude, longitude) of a local position.
+pub fn unproject(x:f32,y:f32,origin:(f64,f64)) -> (f64,f64){
+    let lat = origin.0 + (y as f64 / EARTH_RADIUS).to_degrees();
+    let lon = origin.1 + (x as f64 / (EARTH_RADIUS * origin.0.to_radians().cos())).to_degrees();
+    (lat,lon)
+}
+
+// Read an extract, .pbf files are read as PBF and everything else as XML.
+pub fn read_osm_file(path:&Path) -> Result<OsmData,String>{
+    let is_pbf = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pbf"));
+    if is_pbf{
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        parse_pbf(&bytes)
+    }
+    else{
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        parse_xml(&contents)
+    }
+}
+
+// Parse an OSM XML document.
+pub fn parse_xml(contents:&str) -> Result<OsmData,String>{
+    let document = roxmltree::Document::parse(contents).map_err(|e| e.to_string())?;
+    let mut data = OsmData::default();
+    for element in document.root_element().children().filter(|node| node.is_element()){
+        let tags:HashMap<String,String> = element.children()
+            .filter(|child| child.has_tag_name("tag"))
+            .filter_map(|tag| Some((tag.attribute("k")?.to_string(),tag.attribute("v")?.to_string())))
+            .collect();
+        match element.tag_name().name(){
+            "node" => {
+                let attribute = |name:&str| element.attribute(name).ok_or(format!("Node without {}", name));
+                let id:i64 = attribute("id")?.parse().map_err(|_| "Invalid node id".to_string())?;
+                let lat:f64 = attribute("lat")?.parse().map_err(|_| "Invalid node latitude".to_string())?;
+                let lon:f64 = attribute("lon")?.parse().map_err(|_| "Invalid node longitude".to_string())?;
+                let signal = is_signal(tags.get("highway").map(|value| value.as_str()));
+                data.nodes.insert(id,OsmNode{lat,lon,signal});
+            }
+            "way" => {
+                let nodes = element.children()
+                    .filter(|child| child.has_tag_name("nd"))
+                    .filter_map(|nd| nd.attribute("ref")?.parse().ok())
+                    .collect();
+                data.ways.push(OsmWay{nodes,tags});
+            }
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+fn is_signal(highway:Option<&str>) -> bool{
+    highway == Some("traffic_signals")
+}
+
+// Parse an OSM PBF file: a sequence of length prefixed blob headers and zlib compressed blocks.
+pub fn parse_pbf(bytes:&[u8]) -> Result<OsmData,String>{
+    let mut data = OsmData::default();
+    let mut pos = 0;
+    while pos < bytes.len(){
+        let header_length = bytes.get(pos..pos+4).ok_or("Truncated blob header length")?;
+        let header_length = u32::from_be_bytes([header_length[0],header_length[1],header_length[2],header_length[3]]) as usize;
+        pos += 4;
+        let header = bytes.get(pos..pos+header_length).ok_or("Truncated blob header")?;
+        pos += header_length;
+
+        let mut blob_type = String::new();
+        let mut blob_length = 0;
+        let mut reader = ProtoReader::new(header);
+        while let Some((field,value)) = reader.next_field()?{
+            match (field,value){
+                (1,ProtoValue::Bytes(value)) => blob_type = String::from_utf8_lossy(value).to_string(),
+                (3,ProtoValue::Varint(value)) => blob_length = value as usize,
+                _ => {}
+            }
+        }
+        let blob = bytes.get(pos..pos+blob_length).ok_or("Truncated blob")?;
+        pos += blob_length;
+
+        if blob_type == "OSMData"{
+            parse_primitive_block(&decode_blob(blob)?,&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+// Get the uncompressed contents of a blob.
+fn decode_blob(blob:&[u8]) -> Result<Vec<u8>,String>{
+    let mut reader = ProtoReader::new(blob);
+    while let Some((field,value)) = reader.next_field()?{
+        match (field,value){
+            (1,ProtoValue::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3,ProtoValue::Bytes(compressed)) => {
+                let mut contents = Vec::new();
+                ZlibDecoder::new(compressed).read_to_end(&mut contents).map_err(|e| e.to_string())?;
+                return Ok(contents);
+            }
+            (4..=7,ProtoValue::Bytes(_)) => return Err("Unsupported PBF blob compression".to_string()),
+            _ => {}
+        }
+    }
+    Err("Empty PBF blob".to_string())
+}
+
+fn parse_primitive_block(block:&[u8],data:&mut OsmData) -> Result<(),String>{
+    let mut strings:Vec<String> = Vec::new();
+    let mut groups = Vec::new();
+    let mut granularity:i64 = 100;
+    let mut lat_offset:i64 = 0;
+    let mut lon_offset:i64 = 0;
+    let mut reader = ProtoReader::new(block);
+    while let Some((field,value)) = reader.next_field()?{
+        match (field,value){
+            (1,ProtoValue::Bytes(table)) => {
+                let mut table_reader = ProtoReader::new(table);
+                while let Some((field,value)) = table_reader.next_field()?{
+                    if let (1,ProtoValue::Bytes(string)) = (field,value){
+                        strings.push(String::from_utf8_lossy(string).to_string());
+                    }
+                }
+            }
+            (2,ProtoValue::Bytes(group)) => groups.push(group),
+            (17,ProtoValue::Varint(value)) => granularity = value as i64,
+            (19,ProtoValue::Varint(value)) => lat_offset = value as i64,
+            (20,ProtoValue::Varint(value)) => lon_offset = value as i64,
+            _ => {}
+        }
+    }
+    let coordinate = |offset:i64,value:i64| 1e-9 * (offset + granularity * value) as f64;
+    let string = |index:u64| strings.get(index as usize).map(|value| value.as_str()).unwrap_or("");
+
+    for group in groups{
+        let mut reader = ProtoReader::new(group);
+        while let Some((field,value)) = reader.next_field()?{
+            let element = match value{
+                ProtoValue::Bytes(element) => element,
+                _ => continue,
+            };
+            match field{
+                //Node
+                1 => {
+                    let (mut id,mut lat,mut lon) = (0,0,0);
+                    let (mut keys,mut values) = (Vec::new(),Vec::new());
+                    let mut node_reader = ProtoReader::new(element);
+                    while let Some((field,value)) = node_reader.next_field()?{
+                        match field{
+                            1 => id = zigzag(value.varint()?),
+                            2 => value.repeated(&mut keys)?,
+                            3 => value.repeated(&mut values)?,
+                            8 => lat = zigzag(value.varint()?),
+                            9 => lon = zigzag(value.varint()?),
+                            _ => {}
+                        }
+                    }
+                    let signal = keys.iter().zip(values.iter()).any(|(&key,&value)| string(key) == "highway" && is_signal(Some(string(value))));
+                    data.nodes.insert(id,OsmNode{lat: coordinate(lat_offset,lat),lon: coordinate(lon_offset,lon),signal});
+                }
+                //Dense nodes, ids and coordinates are delta coded
+                2 => {
+                    let (mut ids,mut lats,mut lons,mut keys_values) = (Vec::new(),Vec::new(),Vec::new(),Vec::new());
+                    let mut dense_reader = ProtoReader::new(element);
+                    while let Some((field,value)) = dense_reader.next_field()?{
+                        match field{
+                            1 => value.repeated(&mut ids)?,
+                            8 => value.repeated(&mut lats)?,
+                            9 => value.repeated(&mut lons)?,
+                            10 => value.repeated(&mut keys_values)?,
+                            _ => {}
+                        }
+                    }
+                    let (mut id,mut lat,mut lon) = (0,0,0);
+                    let mut tags = keys_values.iter();
+                    for (i,&delta) in ids.iter().enumerate(){
+                        id += zigzag(delta);
+                        lat += zigzag(*lats.get(i).ok_or("Dense node without latitude")?);
+                        lon += zigzag(*lons.get(i).ok_or("Dense node without longitude")?);
+                        //Tags of each node are key/value pairs ending with 0
+                        let mut signal = false;
+                        while let Some(&key) = tags.next(){
+                            if key == 0{
+                                break;
+                            }
+                            let value = *tags.next().ok_or("Dense node tag without value")?;
+                            signal |= string(key) == "highway" && is_signal(Some(string(value)));
+                        }
+                        data.nodes.insert(id,OsmNode{lat: coordinate(lat_offset,lat),lon: coordinate(lon_offset,lon),signal});
+                    }
+                }
+                //Way, node references are delta coded
+                3 => {
+                    let (mut keys,mut values,mut refs) = (Vec::new(),Vec::new(),Vec::new());
+                    let mut way_reader = ProtoReader::new(element);
+                    while let Some((field,value)) = way_reader.next_field()?{
+                        match field{
+                            2 => value.repeated(&mut keys)?,
+                            3 => value.repeated(&mut values)?,
+                            8 => value.repeated(&mut refs)?,
+                            _ => {}
+                        }
+                    }
+                    let mut node = 0;
+                    let nodes = refs.iter().map(|&delta| {
+                        node += zigzag(delta);
+                        node
+                    }).collect();
+                    let tags = keys.iter().zip(values.iter()).map(|(&key,&value)| (string(key).to_string(),string(value).to_string())).collect();
+                    data.ways.push(OsmWay{nodes,tags});
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn zigzag(value:u64) -> i64{
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+// A field value in the protobuf wire format.
+enum ProtoValue<'a>{
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> ProtoValue<'a>{
+    fn varint(&self) -> Result<u64,String>{
+        match self{
+            ProtoValue::Varint(value) => Ok(*value),
+            _ => Err("Expected a varint field".to_string()),
+        }
+    }
+
+    // Append the values of a repeated varint field, packed or not.
+    fn repeated(&self,values:&mut Vec<u64>) -> Result<(),String>{
+        match self{
+            ProtoValue::Varint(value) => values.push(*value),
+            ProtoValue::Bytes(packed) => {
+                let mut reader = ProtoReader::new(packed);
+                while reader.pos < packed.len(){
+                    values.push(reader.varint()?);
+                }
+            }
+            ProtoValue::Fixed => return Err("Expected a varint field".to_string()),
+        }
+        Ok(())
+    }
+}
+
+// Minimal reader for the protobuf wire format used by PBF files.
+struct ProtoReader<'a>{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a>{
+    fn new(data:&'a [u8]) -> ProtoReader<'a>{
+        ProtoReader{data,pos: 0}
+    }
+
+    fn varint(&mut self) -> Result<u64,String>{
+        let mut value:u64 = 0;
+        for shift in (0..64).step_by(7){
+            let byte = *self.data.get(self.pos).ok_or("Truncated varint")?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0{
+                return Ok(value);
+            }
+        }
+        Err("Varint too long".to_string())
+    }
+
+    fn skip(&mut self,length:usize) -> Result<&'a [u8],String>{
+        let bytes = self.data.get(self.pos..self.pos+length).ok_or("Truncated field")?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u32,ProtoValue<'a>)>,String>{
+        if self.pos >= self.data.len(){
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7{
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                ProtoValue::Fixed
+            }
+            2 => {
+                let length = self.varint()? as usize;
+                ProtoValue::Bytes(self.skip(length)?)
+            }
+            5 => {
+                self.skip(4)?;
+                ProtoValue::Fixed
+            }
+            wire_type => return Err(format!("Unsupported protobuf wire type {}", wire_type)),
+        };
+        Ok(Some(((key >> 3) as u32,value)))
+    }
+}
+
+// Default speed limit in km/h and lanes per direction for the drivable highway types.
+fn highway_defaults(highway:&str) -> Option<(f32,u8)>{
+    let base = highway.trim_end_matches("_link");
+    match base{
+        "motorway" => Some((110.0,2)),
+        "trunk" => Some((90.0,2)),
+        "primary" => Some((70.0,1)),
+        "secondary" => Some((60.0,1)),
+        "tertiary" => Some((50.0,1)),
+        "unclassified" | "road" => Some((40.0,1)),
+        "residential" => Some((30.0,1)),
+        "service" => Some((20.0,1)),
+        "living_street" => Some((10.0,1)),
+        _ => None,
+    }
+}
+
+// Parse a maxspeed tag to m/s, supports plain km/h values and mph.
+fn parse_maxspeed(value:&str) -> Option<f32>{
+    let value = value.trim();
+    if value == "none"{
+        return Some(130.0 / 3.6);
+    }
+    if value == "walk"{
+        return Some(7.0 / 3.6);
+    }
+    let number:String = value.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    let speed:f32 = number.parse().ok()?;
+    if value.ends_with("mph"){
+        Some(speed * 1.609_344 / 3.6)
+    }
+    else if value.ends_with("knots"){
+        Some(speed * 1.852 / 3.6)
+    }
+    else{
+        Some(speed / 3.6)
+    }
+}
+
+fn parse_lanes(value:Option<&String>) -> Option<u8>{
+    let value = value?;
+    let first = value.split(';').next()?.trim();
+    first.parse::<u8>().ok().filter(|&lanes| lanes > 0)
+}
+
+// A directed road between two nodes, before it is added to the world.
+struct Segment{
+    from: i64,
+    to: i64,
+    way: usize,
+    lanes: u8,
+    speed_limit: f32,
+}
+
+impl World{
+    // Replace the world with the drivable roads of an OpenStreetMap extract.
+    // A file that cannot be read leaves the world unchanged.
+    pub fn load_osm(&mut self,path:&Path) -> Result<(),String>{
+        let data = read_osm_file(path)?;
+        self.load_osm_data(&data);
+        Ok(())
+    }
+
+    // Build roads and connections from OSM nodes and ways.
+    // Every pair of consecutive nodes becomes one road per direction of travel.
+    pub fn load_osm_data(&mut self,data:&OsmData){
+        self.reset();
+
+        let mut segments:Vec<Segment> = Vec::new();
+        for (way_index,way) in data.ways.iter().enumerate(){
+            let highway = match way.tags.get("highway"){
+                Some(highway) => highway,
+                None => continue,
+            };
+            let (default_speed,default_lanes) = match highway_defaults(highway){
+                Some(defaults) => defaults,
+                None => continue,
+            };
+            if way.nodes.len() < 2 || way.nodes.iter().any(|node| !data.nodes.contains_key(node)){
+                continue;
+            }
+
+            //Direction of travel, motorways and roundabouts are one way unless tagged otherwise
+            let implied_oneway = highway == "motorway" || matches!(way.tags.get("junction").map(|value| value.as_str()),Some("roundabout") | Some("circular"));
+            let (forward,backward) = match way.tags.get("oneway").map(|value| value.as_str()){
+                Some("yes") | Some("true") | Some("1") => (true,false),
+                Some("-1") | Some("reverse") => (false,true),
+                Some("no") | Some("false") | Some("0") => (true,true),
+                _ => (true,!implied_oneway),
+            };
+
+            let speed = |key:&str| way.tags.get(key).and_then(|value| parse_maxspeed(value));
+            let default_speed = speed("maxspeed").unwrap_or(default_speed / 3.6);
+            let total_lanes = parse_lanes(way.tags.get("lanes"));
+            let lanes = |key:&str| parse_lanes(way.tags.get(key)).or_else(|| match total_lanes{
+                //Two way roads share their lanes between both directions
+                Some(total) if forward && backward => Some((total / 2).max(1)),
+                Some(total) => Some(total),
+                None => None,
+            }).unwrap_or(default_lanes);
+
+            for pair in way.nodes.windows(2){
+                if pair[0] == pair[1]{
+                    continue;
+                }
+                if forward{
+                    segments.push(Segment{from: pair[0],to: pair[1],way: way_index,lanes: lanes("lanes:forward"),speed_limit: speed("maxspeed:forward").unwrap_or(default_speed)});
+                }
+                if backward{
+                    segments.push(Segment{from: pair[1],to: pair[0],way: way_index,lanes: lanes("lanes:backward"),speed_limit: speed("maxspeed:backward").unwrap_or(default_speed)});
+                }
+            }
+        }
+        if segments.is_empty(){
+            return;
+        }
+
+        //Nodes shared by several ways or ending a way are junctions
+        let mut node_ways:HashMap<i64,HashSet<usize>> = HashMap::new();
+        let mut way_ends:HashSet<i64> = HashSet::new();
+        for segment in segments.iter(){
+            node_ways.entry(segment.from).or_default().insert(segment.way);
+            node_ways.entry(segment.to).or_default().insert(segment.way);
+        }
+        let used_ways:HashSet<usize> = segments.iter().map(|segment| segment.way).collect();
+        for &way_index in used_ways.iter(){
+            let way = &data.ways[way_index];
+            if let (Some(first),Some(last)) = (way.nodes.first(),way.nodes.last()){
+                way_ends.insert(*first);
+                way_ends.insert(*last);
+            }
+        }
+        let is_junction = |node:i64| way_ends.contains(&node) || node_ways.get(&node).map_or(0,|ways| ways.len()) > 1;
+
+        //Project around the mean position of the used nodes
+        let used:Vec<&OsmNode> = node_ways.keys().map(|node| &data.nodes[node]).collect();
+        let origin = (
+            used.iter().map(|node| node.lat).sum::<f64>() / used.len() as f64,
+            used.iter().map(|node| node.lon).sum::<f64>() / used.len() as f64,
+        );
+        self.geo_origin = Some(origin);
+
+        //Connect every road to the roads leaving its end node, without turning back unless it is a dead end
+        let mut leaving:HashMap<i64,Vec<usize>> = HashMap::new();
+        let mut arriving:HashMap<i64,Vec<usize>> = HashMap::new();
+        for (index,segment) in segments.iter().enumerate(){
+            leaving.entry(segment.from).or_default().push(index);
+            arriving.entry(segment.to).or_default().push(index);
+        }
+        let successors = |index:usize| -> Vec<usize>{
+            let segment = &segments[index];
+            let next = leaving.get(&segment.to).cloned().unwrap_or_default();
+            let forward:Vec<usize> = next.iter().copied().filter(|&other| segments[other].to != segment.from || segments[other].way != segment.way).collect();
+            if forward.is_empty(){ next } else { forward }
+        };
+
+        for (index,segment) in segments.iter().enumerate(){
+            let from = &data.nodes[&segment.from];
+            let to = &data.nodes[&segment.to];
+            let (from_x,from_y) = project(from.lat,from.lon,origin);
+            let (to_x,to_y) = project(to.lat,to.lon,origin);
+            let to_road = successors(index);
+            let from_road = arriving.get(&segment.from).cloned().unwrap_or_default().into_iter().filter(|&other| successors(other).contains(&index)).collect();
+            let end_speed_limit = if to.signal{
+                SIGNAL_SPEED
+            }
+            else if is_junction(segment.to){
+                JUNCTION_SPEED.min(segment.speed_limit)
+            }
+            else{
+                segment.speed_limit
+            };
+            self.add_road((from_x,from_y,0.0),(to_x,to_y,0.0),segment.lanes,segment.speed_limit,from_road,to_road,end_speed_limit);
+            self.roads[index].signal = to.signal;
+        }
+
+        //Preprocess the network so vehicles can be routed quickly
+        self.build_route_index();
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // A primary one way street with a signal at its end, continued by a residential two way street.
+    // The building is not a road.
+    const XML:&str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="52.5" lon="13.4"/>
+  <node id="2" lat="52.5" lon="13.401"/>
+  <node id="3" lat="52.5" lon="13.402">
+    <tag k="highway" v="traffic_signals"/>
+  </node>
+  <node id="4" lat="52.501" lon="13.402"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="primary"/>
+    <tag k="oneway" v="yes"/>
+    <tag k="lanes" v="2"/>
+    <tag k="maxspeed" v="50"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="12">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="4"/>
+    <nd ref="1"/>
+    <tag k="building" v="yes"/>
+  </way>
+</osm>"#;
+
+    fn varint(mut value:u64,out:&mut Vec<u8>){
+        while value >= 0x80{
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn varint_field(number:u32,value:u64,out:&mut Vec<u8>){
+        varint((number as u64) << 3,out);
+        varint(value,out);
+    }
+
+    fn bytes_field(number:u32,bytes:&[u8],out:&mut Vec<u8>){
+        varint(((number as u64) << 3) | 2,out);
+        varint(bytes.len() as u64,out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn packed(values:&[u64]) -> Vec<u8>{
+        let mut out = Vec::new();
+        for &value in values{
+            varint(value,&mut out);
+        }
+        out
+    }
+
+    // Zigzag coded deltas of the values.
+    fn deltas(values:&[i64]) -> Vec<u64>{
+        let mut previous = 0;
+        values.iter().map(|&value| {
+            let delta = value - previous;
+            previous = value;
+            ((delta << 1) ^ (delta >> 63)) as u64
+        }).collect()
+    }
+
+    // Blob header and blob of a file block.
+    fn file_block(blob_type:&str,blob:&[u8],out:&mut Vec<u8>){
+        let mut header = Vec::new();
+        bytes_field(1,blob_type.as_bytes(),&mut header);
+        varint_field(3,blob.len() as u64,&mut header);
+        out.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(blob);
+    }
+
+    // The XML network as a primitive block, with dense nodes and ways in separate groups.
+    fn primitive_block() -> Vec<u8>{
+        let strings = ["","highway","traffic_signals","primary","oneway","yes","lanes","2","maxspeed","50","residential","building"];
+        let mut table = Vec::new();
+        for string in strings{
+            bytes_field(1,string.as_bytes(),&mut table);
+        }
+
+        //Coordinates in units of the default granularity, 100 nanodegrees
+        let mut dense = Vec::new();
+        bytes_field(1,&packed(&deltas(&[1,2,3,4])),&mut dense);
+        bytes_field(8,&packed(&deltas(&[525_000_000,525_000_000,525_000_000,525_010_000])),&mut dense);
+        bytes_field(9,&packed(&deltas(&[134_000_000,134_010_000,134_020_000,134_020_000])),&mut dense);
+        bytes_field(10,&packed(&[0,0,1,2,0,0]),&mut dense);
+        let mut nodes = Vec::new();
+        bytes_field(2,&dense,&mut nodes);
+
+        let mut ways = Vec::new();
+        for (id,keys,values,refs) in [(10,vec![1,4,6,8],vec![3,5,7,9],vec![1,2,3]),(11,vec![1],vec![10],vec![3,4]),(12,vec![11],vec![5],vec![1,2,4,1])]{
+            let mut way = Vec::new();
+            varint_field(1,id,&mut way);
+            bytes_field(2,&packed(&keys),&mut way);
+            bytes_field(3,&packed(&values),&mut way);
+            bytes_field(8,&packed(&deltas(&refs)),&mut way);
+            bytes_field(3,&way,&mut ways);
+        }
+
+        let mut block = Vec::new();
+        bytes_field(1,&table,&mut block);
+        bytes_field(2,&nodes,&mut block);
+        bytes_field(2,&ways,&mut block);
+        block
+    }
+
+    // A file with a header block, which is skipped, and the data block stored raw or compressed.
+    fn pbf(compressed:bool) -> Vec<u8>{
+        let mut file = Vec::new();
+        let mut header_blob = Vec::new();
+        bytes_field(1,b"not parsed",&mut header_blob);
+        file_block("OSMHeader",&header_blob,&mut file);
+        let mut blob = Vec::new();
+        if compressed{
+            let mut encoder = ZlibEncoder::new(Vec::new(),Compression::default());
+            encoder.write_all(&primitive_block()).unwrap();
+            varint_field(2,primitive_block().len() as u64,&mut blob);
+            bytes_field(3,&encoder.finish().unwrap(),&mut blob);
+        }
+        else{
+            bytes_field(1,&primitive_block(),&mut blob);
+        }
+        file_block("OSMData",&blob,&mut file);
+        file
+    }
+
+    fn assert_same_data(a:&OsmData,b:&OsmData){
+        assert_eq!(a.nodes.len(),b.nodes.len());
+        for (id,node) in a.nodes.iter(){
+            let other = &b.nodes[id];
+            assert!((node.lat - other.lat).abs() < 1e-9 && (node.lon - other.lon).abs() < 1e-9,"Node {} moved",id);
+            assert_eq!(node.signal,other.signal,"Node {} signal",id);
+        }
+        assert_eq!(a.ways.len(),b.ways.len());
+        for (way,other) in a.ways.iter().zip(b.ways.iter()){
+            assert_eq!(way.nodes,other.nodes);
+            assert_eq!(way.tags,other.tags);
+        }
+    }
+
+    #[test]
+    fn xml_builds_roads(){
+        let data = parse_xml(XML).unwrap();
+        let mut world = World::new();
+        world.load_osm_data(&data);
+
+        //The one way street has one road per node pair, the two way street one per direction
+        assert_eq!(world.roads.len(),4);
+        for road in &world.roads[0..2]{
+            assert_eq!(road.lanes,2);
+            assert!((road.speed_limit - 50.0 / 3.6).abs() < 1e-4);
+        }
+        assert!((world.roads[2].speed_limit - 30.0 / 3.6).abs() < 1e-4);
+        assert_eq!(world.roads[2].from,world.roads[3].to);
+        assert!((world.roads[0].length.into_inner() - 67.7).abs() < 0.5,"Road length {}",world.roads[0].length);
+
+        //Both roads arriving at the signal stop there
+        let signals:Vec<bool> = world.roads.iter().map(|road| road.signal).collect();
+        assert_eq!(signals,vec![false,true,false,true]);
+        assert_eq!(world.roads[1].end_speed_limit,SIGNAL_SPEED);
+        assert!((world.roads[0].end_speed_limit - world.roads[0].speed_limit).abs() < 1e-4,"Node 2 is not a junction");
+
+        //Vehicles turn back only at the dead end
+        let mut connections:Vec<(usize,usize)> = world.connections.keys().copied().collect();
+        connections.sort();
+        assert_eq!(connections,vec![(0,1),(1,2),(2,3),(3,2)]);
+        assert!(world.route_index.is_some());
+    }
+
+    #[test]
+    fn pbf_matches_xml(){
+        let xml = parse_xml(XML).unwrap();
+        assert_same_data(&xml,&parse_pbf(&pbf(false)).unwrap());
+        assert_same_data(&xml,&parse_pbf(&pbf(true)).unwrap());
+    }
+
+    #[test]
+    fn invalid_input_is_an_error(){
+        let file = pbf(false);
+        assert!(parse_pbf(&file[..file.len() - 3]).is_err());
+        assert!(parse_xml("<osm><node id=\"1\" lat=\"52.5\"/></osm>").is_err());
+        assert!(parse_xml("<osm>").is_err());
+    }
+
+    #[test]
+    fn unreadable_file_keeps_the_world(){
+        let mut world = World::new();
+        world.load_osm_data(&parse_xml(XML).unwrap());
+        let path = std::env::temp_dir().join(format!("trafast_osm_test_{}.osm.pbf",std::process::id()));
+        std::fs::write(&path,&pbf(false)[..20]).unwrap();
+        let result = world.load_osm(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert_eq!(world.roads.len(),4);
+    }
+
+    #[test]
+    fn tags(){
+        assert_eq!(parse_maxspeed("50"),Some(50.0 / 3.6));
+        assert!((parse_maxspeed("30 mph").unwrap() - 13.41).abs() < 0.01);
+        assert_eq!(parse_maxspeed("signals"),None);
+        assert_eq!(parse_lanes(Some(&"3;2".to_string())),Some(3));
+        assert_eq!(parse_lanes(Some(&"0".to_string())),None);
+    }
+}
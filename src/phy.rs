@@ -1,11 +1,20 @@
 pub use crate::comp::*;
+use crate::trip::TripRecord;
 use ordered_float::OrderedFloat;
+use std::collections::HashMap;
 
 //Update vehicle position and velocity
 pub fn update_comp(t:f32,world:&mut World){
+    world.time += t;
+    world.release_departures();
+
     let vehicles = &mut world.vehicles;
     let mut roads =  &mut world.roads;
-    let mut remove_vehicles:Vec<usize> = Vec::new();
+    let connections = &world.connections;
+    let trips = &mut world.trips;
+    let time = world.time;
+    //Vehicles leaving the network, with whether they arrived at their destination
+    let mut remove_vehicles:Vec<(usize,bool)> = Vec::new();
 
     let mut i = 0;
     for mut vehicle in vehicles.iter_mut(){
@@ -13,23 +22,43 @@ pub fn update_comp(t:f32,world:&mut World){
         //.x1 is for vehicles
         let run_behind:f32 = 1.5;
         roads[vehicle.on_road].obstacle_map.remove(&OrderedFloat(((vehicle.position*10.0).round()/10.0)-0.01-run_behind));
-        let (dist,end_speed_limit) = check_road_obstacle(vehicle,roads);
+        //A vehicle at a road end with no road left to turn into can't reach its destination, it leaves the network
+        if vehicle.path.is_empty() && vehicle.position >= roads[vehicle.on_road].length.into_inner(){
+            println!("Vehicle {} has no road after road {}, removed", vehicle.id, vehicle.on_road);
+            remove_vehicles.push((i,false));
+            i+=1;
+            continue;
+        }
+        let (previous_road,previous_position,previous_velocity) = (vehicle.on_road,vehicle.position,vehicle.velocity);
+        let (dist,end_speed_limit,at_road_end) = check_road_obstacle(vehicle,roads,connections);
         if check_destination_start_break(vehicle){
             let dist:f32 = vehicle.destination_position - vehicle.position;
+            vehicle.control_law = ControlLaw::Destination;
             decrease_speed(&mut vehicle,&t,dist,0.0);
         }
         else{
             if dist != 0.0 && vehicle.velocity > end_speed_limit{
+                vehicle.control_law = if at_road_end { ControlLaw::RoadEnd } else { ControlLaw::Following };
                 decrease_speed(&mut vehicle,&t,dist,end_speed_limit);
             }
             else{
+                vehicle.control_law = if vehicle.velocity < roads[vehicle.on_road].speed_limit { ControlLaw::Accelerating } else { ControlLaw::Cruising };
                 increase_speed(&mut vehicle,roads,&t);
             }
         }
 
+        //Update trip statistics with the distance moved, across the road end when the vehicle changed roads
+        let moved = if vehicle.on_road == previous_road{
+            vehicle.position - previous_position
+        }
+        else{
+            roads[previous_road].length.into_inner() - previous_position + vehicle.position
+        };
+        vehicle.trip.observe(t,vehicle.on_road,moved,roads[vehicle.on_road].speed_limit,previous_velocity,vehicle.velocity);
+
         //Check if vehicle has reached destination
         if vehicle.on_road == vehicle.destination && vehicle.position >= vehicle.destination_position-10.0 && vehicle.velocity == 0.0{
-            remove_vehicles.push(i);
+            remove_vehicles.push((i,true));
         }
         else{
             roads[vehicle.on_road].obstacle_map.insert(OrderedFloat(((vehicle.position*10.0).round()/10.0)-0.01-run_behind),vehicle.velocity);
@@ -38,18 +67,27 @@ pub fn update_comp(t:f32,world:&mut World){
         i+=1;
     }
     
-    //Remove vehicles that have reached destination
+    //Remove vehicles that have reached destination or are stranded, last first so the indices stay valid
     remove_vehicles.sort();
-    for i in remove_vehicles.iter(){
-        vehicles.remove(*i);
+    for &(i,arrived) in remove_vehicles.iter().rev(){
+        let vehicle = vehicles.remove(i);
+        if arrived{
+            trips.push(TripRecord::new(&vehicle,time));
+        }
     }
+    world.record_trajectories();
+    world.update_detectors(t);
+    world.update_link_statistics(t);
 }
 
-fn check_road_obstacle(vehicle:&mut Vehicle,roads:&Vec<Road>) -> (f32,f32){
+//Distance and speed of the nearest obstacle ahead, and whether it is the road end
+fn check_road_obstacle(vehicle:&mut Vehicle,roads:&Vec<Road>,connections:&HashMap<(usize,usize),Connection>) -> (f32,f32,bool){
     let mut nearest_obstacle:OrderedFloat<f32> = roads[vehicle.on_road].length+1.0;
     let vehicle_position:OrderedFloat<f32> = vehicle.position.into();
     let mut nearest_obstacle_speed:f32 = 0.0;
-    if vehicle_position >= roads[vehicle.on_road].length.into(){
+    let mut nearest_key:OrderedFloat<f32> = OrderedFloat(-1.0);
+    //Without a next road the vehicle is removed by update_comp
+    if vehicle_position >= roads[vehicle.on_road].length.into() && !vehicle.path.is_empty(){
         vehicle.on_road = vehicle.path.remove(0);
         vehicle.position = 0.0;
     }
@@ -58,15 +96,24 @@ fn check_road_obstacle(vehicle:&mut Vehicle,roads:&Vec<Road>) -> (f32,f32){
             if *key - vehicle.position < nearest_obstacle{
                 nearest_obstacle = *key - vehicle.position;
                 nearest_obstacle_speed = *roads[vehicle.on_road].obstacle_map.get(key).unwrap();
+                nearest_key = *key;
             }
         }
     }
 
+    //At the road end, the movement into the next road can have its own speed limit
+    let road_end:OrderedFloat<f32> = OrderedFloat((roads[vehicle.on_road].length*10.0).round()/10.0);
+    if nearest_key == road_end && !vehicle.path.is_empty(){
+        if let Some(movement_speed) = connections.get(&(vehicle.on_road,vehicle.path[0])).and_then(|connection| connection.speed_limit){
+            nearest_obstacle_speed = nearest_obstacle_speed.min(movement_speed);
+        }
+    }
+
     if nearest_obstacle < roads[vehicle.on_road].length+1.0{
-        (nearest_obstacle.into(),nearest_obstacle_speed)
+        (nearest_obstacle.into(),nearest_obstacle_speed,nearest_key == road_end)
     }
     else{
-        return (0.0,0.0);
+        return (0.0,0.0,false);
     }
 }
 
@@ -0,0 +1,406 @@
+// Purpose: Record vehicle trajectories (floating car data) to CSV or Parquet files.
+
+use crate::comp::World;
+use crate::rng::seed_comment;
+use crate::vehicle_type::VehicleType;
+use parquet::data_type::{ByteArray, ByteArrayType, FloatType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::KeyValue;
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+// Columns of a trajectory file, in order.
+// Vehicles drive in single file, there is no lane column until they change lanes.
+const COLUMNS: [&str; 10] = ["time", "id", "road", "position", "x", "y", "z", "velocity", "acceleration", "type"];
+
+// Parquet schema matching the columns.
+const PARQUET_SCHEMA: &str = "
+message trajectory {
+    REQUIRED FLOAT time;
+    REQUIRED INT64 id;
+    REQUIRED INT64 road;
+    REQUIRED FLOAT position;
+    REQUIRED FLOAT x;
+    REQUIRED FLOAT y;
+    REQUIRED FLOAT z;
+    REQUIRED FLOAT velocity;
+    REQUIRED FLOAT acceleration;
+    REQUIRED BYTE_ARRAY type (UTF8);
+}
+";
+
+// Rows buffered before a Parquet row group is written.
+const ROW_GROUP_SIZE: usize = 65536;
+
+// Output format of a trajectory file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryFormat{
+    Csv,
+    Parquet,
+}
+
+impl TrajectoryFormat{
+    // Parquet for .parquet files, CSV otherwise.
+    pub fn from_path(path:&Path) -> TrajectoryFormat{
+        match path.extension().and_then(|extension| extension.to_str()){
+            Some("parquet") => TrajectoryFormat::Parquet,
+            _ => TrajectoryFormat::Csv,
+        }
+    }
+}
+
+// State of one vehicle at one time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryRecord{
+    pub time: f32,
+    pub id: usize,
+    pub road: usize,
+    pub position: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub velocity: f32,
+    pub acceleration: f32,
+    pub vehicle_type: VehicleType,
+}
+
+enum TrajectoryWriter{
+    Csv(csv::Writer<File>),
+    Parquet(SerializedFileWriter<File>,Vec<TrajectoryRecord>),
+}
+
+// Writes the state of every vehicle at a fixed interval of simulation time.
+pub struct TrajectoryRecorder{
+    pub interval: f32,
+    next_time: f32,
+    last_time: f32,
+    // Velocity of every vehicle at the previous step, for the realized acceleration.
+    last_velocities: HashMap<usize,f32>,
+    writer: Option<TrajectoryWriter>,
+}
+
+impl TrajectoryRecorder{
+    // Create the output file, the format follows the file extension.
+    // The seed of the run is a comment line in CSV and key-value metadata in Parquet.
+    pub fn new(path:&Path,interval:f32,seed:u64) -> Result<TrajectoryRecorder,String>{
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        let writer = match TrajectoryFormat::from_path(path){
+            TrajectoryFormat::Csv => {
+                writeln!(file,"{}",seed_comment(seed)).map_err(|e| e.to_string())?;
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(COLUMNS).map_err(|e| e.to_string())?;
+                TrajectoryWriter::Csv(writer)
+            }
+            TrajectoryFormat::Parquet => {
+                let schema = Arc::new(parse_message_type(PARQUET_SCHEMA).map_err(|e| e.to_string())?);
+                let properties = Arc::new(WriterProperties::builder()
+                    .set_key_value_metadata(Some(vec![KeyValue::new("seed".to_string(),seed.to_string())]))
+                    .build());
+                let writer = SerializedFileWriter::new(file,schema,properties).map_err(|e| e.to_string())?;
+                TrajectoryWriter::Parquet(writer,Vec::new())
+            }
+        };
+        Ok(TrajectoryRecorder{
+            interval: interval.max(0.0),
+            next_time: 0.0,
+            last_time: f32::NEG_INFINITY,
+            last_velocities: HashMap::new(),
+            writer: Some(writer),
+        })
+    }
+
+    // Observe the world after a simulation step, writing a row per vehicle when the interval has passed.
+    pub fn record(&mut self,world:&World) -> Result<(),String>{
+        //A reloaded scenario starts again at time 0
+        if world.time < self.last_time{
+            self.next_time = world.time;
+            self.last_velocities.clear();
+        }
+        let step = world.time - self.last_time;
+        self.last_time = world.time;
+
+        let mut records = Vec::new();
+        let due = world.time >= self.next_time;
+        for vehicle in world.vehicles.iter(){
+            let acceleration = match self.last_velocities.get(&vehicle.id){
+                Some(&last_velocity) if step > 0.0 && step.is_finite() => (vehicle.velocity - last_velocity) / step,
+                _ => 0.0,
+            };
+            self.last_velocities.insert(vehicle.id,vehicle.velocity);
+            if due{
+                let (x,y,z) = world.roads[vehicle.on_road].point_at(vehicle.position);
+                records.push(TrajectoryRecord{
+                    time: world.time,
+                    id: vehicle.id,
+                    road: vehicle.on_road,
+                    position: vehicle.position,
+                    x,
+                    y,
+                    z,
+                    velocity: vehicle.velocity,
+                    acceleration,
+                    vehicle_type: vehicle.vehicle_type,
+                });
+            }
+        }
+        //Forget vehicles that left the network
+        if self.last_velocities.len() > world.vehicles.len(){
+            let ids:HashSet<usize> = world.vehicles.iter().map(|vehicle| vehicle.id).collect();
+            self.last_velocities.retain(|id,_| ids.contains(id));
+        }
+        if due{
+            //Skip missed records when a step is longer than the interval
+            self.next_time += self.interval;
+            if self.next_time <= world.time{
+                self.next_time = world.time + self.interval;
+            }
+            self.write(&records)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self,records:&[TrajectoryRecord]) -> Result<(),String>{
+        match &mut self.writer{
+            Some(TrajectoryWriter::Csv(writer)) => {
+                for record in records{
+                    writer.write_record(&[
+                        record.time.to_string(),
+                        record.id.to_string(),
+                        record.road.to_string(),
+                        record.position.to_string(),
+                        record.x.to_string(),
+                        record.y.to_string(),
+                        record.z.to_string(),
+                        record.velocity.to_string(),
+                        record.acceleration.to_string(),
+                        record.vehicle_type.name().to_string(),
+                    ]).map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            }
+            Some(TrajectoryWriter::Parquet(writer,buffer)) => {
+                buffer.extend_from_slice(records);
+                if buffer.len() >= ROW_GROUP_SIZE{
+                    write_row_group(writer,buffer)?;
+                    buffer.clear();
+                }
+                Ok(())
+            }
+            None => Err("The recorder is already finished.".to_string()),
+        }
+    }
+
+    // Write buffered rows and close the file.
+    pub fn finish(&mut self) -> Result<(),String>{
+        match self.writer.take(){
+            Some(TrajectoryWriter::Csv(mut writer)) => writer.flush().map_err(|e| e.to_string()),
+            Some(TrajectoryWriter::Parquet(mut writer,buffer)) => {
+                if !buffer.is_empty(){
+                    write_row_group(&mut writer,&buffer)?;
+                }
+                writer.close().map(|_| ()).map_err(|e| e.to_string())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TrajectoryRecorder{
+    fn drop(&mut self){
+        if let Err(error) = self.finish(){
+            println!("Failed to finish trajectory file: {}", error);
+        }
+    }
+}
+
+// Write rows as one Parquet row group, column by column.
+fn write_row_group(writer:&mut SerializedFileWriter<File>,records:&[TrajectoryRecord]) -> Result<(),String>{
+    let floats = |value:fn(&TrajectoryRecord) -> f32| records.iter().map(value).collect::<Vec<f32>>();
+    let integers = |value:fn(&TrajectoryRecord) -> i64| records.iter().map(value).collect::<Vec<i64>>();
+    let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+    for name in COLUMNS{
+        let mut column = row_group.next_column().map_err(|e| e.to_string())?.ok_or("Missing Parquet column.")?;
+        let written = match name{
+            "id" => column.typed::<Int64Type>().write_batch(&integers(|record| record.id as i64),None,None),
+            "road" => column.typed::<Int64Type>().write_batch(&integers(|record| record.road as i64),None,None),
+            "type" => column.typed::<ByteArrayType>().write_batch(&records.iter().map(|record| ByteArray::from(record.vehicle_type.name())).collect::<Vec<ByteArray>>(),None,None),
+            "time" => column.typed::<FloatType>().write_batch(&floats(|record| record.time),None,None),
+            "position" => column.typed::<FloatType>().write_batch(&floats(|record| record.position),None,None),
+            "x" => column.typed::<FloatType>().write_batch(&floats(|record| record.x),None,None),
+            "y" => column.typed::<FloatType>().write_batch(&floats(|record| record.y),None,None),
+            "z" => column.typed::<FloatType>().write_batch(&floats(|record| record.z),None,None),
+            "velocity" => column.typed::<FloatType>().write_batch(&floats(|record| record.velocity),None,None),
+            _ => column.typed::<FloatType>().write_batch(&floats(|record| record.acceleration),None,None),
+        };
+        written.map_err(|e| e.to_string())?;
+        column.close().map_err(|e| e.to_string())?;
+    }
+    row_group.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Read every row of a trajectory file written by the recorder, in file order.
+pub fn read_trajectories(path:&Path) -> Result<Vec<TrajectoryRecord>,String>{
+    let vehicle_type = |name:&str| VehicleType::from_name(name).ok_or(format!("Unknown vehicle type {}.", name));
+    let mut records = Vec::new();
+    match TrajectoryFormat::from_path(path){
+        TrajectoryFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().comment(Some(b'#')).from_path(path).map_err(|e| e.to_string())?;
+            for row in reader.records(){
+                let row = row.map_err(|e| e.to_string())?;
+                let field = |index:usize| row.get(index).ok_or(format!("Missing column {}.", COLUMNS[index]));
+                let number = |index:usize| field(index)?.parse::<f32>().map_err(|e| format!("Invalid {}: {}", COLUMNS[index], e));
+                let integer = |index:usize| field(index)?.parse::<usize>().map_err(|e| format!("Invalid {}: {}", COLUMNS[index], e));
+                records.push(TrajectoryRecord{
+                    time: number(0)?,
+                    id: integer(1)?,
+                    road: integer(2)?,
+                    position: number(3)?,
+                    x: number(4)?,
+                    y: number(5)?,
+                    z: number(6)?,
+                    velocity: number(7)?,
+                    acceleration: number(8)?,
+                    vehicle_type: vehicle_type(field(9)?)?,
+                });
+            }
+        }
+        TrajectoryFormat::Parquet => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let reader = SerializedFileReader::new(file).map_err(|e| e.to_string())?;
+            for row in reader.get_row_iter(None).map_err(|e| e.to_string())?{
+                let row = row.map_err(|e| e.to_string())?;
+                records.push(TrajectoryRecord{
+                    time: row.get_float(0).map_err(|e| e.to_string())?,
+                    id: row.get_long(1).map_err(|e| e.to_string())? as usize,
+                    road: row.get_long(2).map_err(|e| e.to_string())? as usize,
+                    position: row.get_float(3).map_err(|e| e.to_string())?,
+                    x: row.get_float(4).map_err(|e| e.to_string())?,
+                    y: row.get_float(5).map_err(|e| e.to_string())?,
+                    z: row.get_float(6).map_err(|e| e.to_string())?,
+                    velocity: row.get_float(7).map_err(|e| e.to_string())?,
+                    acceleration: row.get_float(8).map_err(|e| e.to_string())?,
+                    vehicle_type: vehicle_type(row.get_string(9).map_err(|e| e.to_string())?)?,
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+impl World{
+    // Start writing trajectories to a CSV or Parquet file every interval seconds of simulation time.
+    pub fn start_recording(&mut self,path:&Path,interval:f32) -> Result<(),String>{
+        self.stop_recording()?;
+        let mut recorder = TrajectoryRecorder::new(path,interval,self.rng.seed())?;
+        recorder.next_time = self.time;
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    // Finish the trajectory file, if recording.
+    pub fn stop_recording(&mut self) -> Result<(),String>{
+        match self.recorder.take(){
+            Some(mut recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Pass the current state to the recorder, called after every simulation step.
+    pub fn record_trajectories(&mut self){
+        if let Some(mut recorder) = self.recorder.take(){
+            match recorder.record(self){
+                Ok(()) => self.recorder = Some(recorder),
+                Err(error) => println!("Stopped recording trajectories: {}", error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::contraction::tests::grid;
+    use crate::phy::update_comp;
+    use crate::rng::SimRng;
+
+    fn temp_path(name:&str) -> std::path::PathBuf{
+        std::env::temp_dir().join(format!("trafast_recorder_test_{}_{}",std::process::id(),name))
+    }
+
+    fn records() -> Vec<TrajectoryRecord>{
+        vec![
+            TrajectoryRecord{time: 0.0,id: 0,road: 3,position: 0.0,x: 100.0,y: -0.1,z: 0.0,velocity: 0.0,acceleration: 0.0,vehicle_type: VehicleType::Car},
+            TrajectoryRecord{time: 0.5,id: 0,road: 3,position: 1.234_567,x: 101.234_567,y: -0.1,z: 2.5,velocity: 4.9,acceleration: 9.8,vehicle_type: VehicleType::Car},
+            TrajectoryRecord{time: 0.5,id: 12,road: 0,position: 70.25,x: 3.0,y: 1e-7,z: 0.0,velocity: 13.9,acceleration: -3.5,vehicle_type: VehicleType::Motorcycle},
+        ]
+    }
+
+    // Write the records, read them back and return what was read.
+    fn round_trip(name:&str,seed:u64) -> (Vec<TrajectoryRecord>,std::path::PathBuf){
+        let path = temp_path(name);
+        let mut recorder = TrajectoryRecorder::new(&path,1.0,seed).unwrap();
+        recorder.write(&records()).unwrap();
+        recorder.finish().unwrap();
+        (read_trajectories(&path).unwrap(),path)
+    }
+
+    #[test]
+    fn csv_round_trip(){
+        let (read,path) = round_trip("round_trip.csv",u64::MAX);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read,records());
+        let lines:Vec<&str> = contents.lines().take(2).collect();
+        assert_eq!(lines,vec!["# seed: 18446744073709551615",&COLUMNS.join(",")]);
+    }
+
+    #[test]
+    fn parquet_round_trip(){
+        let (read,path) = round_trip("round_trip.parquet",42);
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata().key_value_metadata().cloned();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read,records());
+        assert_eq!(metadata,Some(vec![KeyValue::new("seed".to_string(),"42".to_string())]));
+    }
+
+    #[test]
+    fn recording_follows_the_interval(){
+        let mut world = grid(3);
+        world.rng = SimRng::new(3);
+        world.build_route_index();
+        world.spawn_vehicle(0,0.0,VehicleType::Car);
+        world.spawn_vehicle(5,20.0,VehicleType::Bus);
+        let path = temp_path("interval.csv");
+        world.start_recording(&path,1.0).unwrap();
+        for _ in 0..35{
+            update_comp(0.1,&mut world);
+        }
+        world.stop_recording().unwrap();
+        let read = read_trajectories(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        //Both vehicles at the first step reaching every whole second
+        let times:Vec<f32> = read.iter().map(|record| record.time).collect();
+        assert_eq!(times.len(),8,"Times {:?}",times);
+        for (second,pair) in times.chunks(2).enumerate(){
+            assert_eq!(pair[0],pair[1]);
+            assert!(pair[0] - second as f32 > -1e-3 && pair[0] - second as f32 <= 0.1 + 1e-3,"Times {:?}",times);
+        }
+        //The last rows are the state after the last recorded step, the point is on the road
+        let last = read[read.len() - 1];
+        let road = &world.roads[last.road];
+        let (x,y,_) = road.point_at(last.position);
+        assert!((last.x - x).abs() < 1e-3 && (last.y - y).abs() < 1e-3);
+        assert_eq!((last.id,last.vehicle_type),(1,VehicleType::Bus));
+        assert!(read.iter().filter(|record| record.id == 0).any(|record| record.acceleration > 0.0),"Accelerations are recorded");
+    }
+}
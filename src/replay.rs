@@ -0,0 +1,218 @@
+// Purpose: Playback of recorded trajectories, independent of the simulation and the frame rate.
+
+use crate::recorder::{read_trajectories, TrajectoryRecord};
+use bevy::prelude::Resource;
+use std::path::Path;
+
+// Playback speeds are kept between these factors of real time.
+const MIN_SPEED: f32 = 1.0 / 16.0;
+const MAX_SPEED: f32 = 256.0;
+
+// Recorded vehicle states grouped into frames by time, with the playback position.
+#[derive(Resource)]
+pub struct Replay{
+    // Sorted by time, then by vehicle id
+    records: Vec<TrajectoryRecord>,
+    // Time and first record of every frame
+    frames: Vec<(f32,usize)>,
+    pub time: f32,
+    // Replayed seconds per real second
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Replay{
+    pub fn new(mut records:Vec<TrajectoryRecord>) -> Result<Replay,String>{
+        if records.is_empty(){
+            return Err("The trajectory file has no records.".to_string());
+        }
+        records.sort_by(|a,b| a.time.total_cmp(&b.time).then(a.id.cmp(&b.id)));
+        let mut frames:Vec<(f32,usize)> = Vec::new();
+        for (index,record) in records.iter().enumerate(){
+            if frames.last().is_none_or(|frame| frame.0 != record.time){
+                frames.push((record.time,index));
+            }
+        }
+        Ok(Replay{
+            time: frames[0].0,
+            records,
+            frames,
+            speed: 1.0,
+            paused: false,
+        })
+    }
+
+    // Read a CSV or Parquet trajectory file written by the recorder.
+    pub fn load(path:&Path) -> Result<Replay,String>{
+        Replay::new(read_trajectories(path)?)
+    }
+
+    pub fn start_time(&self) -> f32{
+        self.frames[0].0
+    }
+
+    pub fn end_time(&self) -> f32{
+        self.frames[self.frames.len() - 1].0
+    }
+
+    // Move the playback forward by t seconds of real time, pausing at the end.
+    pub fn advance(&mut self,t:f32){
+        if self.paused{
+            return;
+        }
+        self.time = (self.time + t * self.speed).min(self.end_time());
+        if self.time >= self.end_time(){
+            self.paused = true;
+        }
+    }
+
+    // Jump to a time within the recording.
+    pub fn seek(&mut self,time:f32){
+        self.time = time.clamp(self.start_time(),self.end_time());
+    }
+
+    // Pause and move by a number of recorded frames, backwards if negative.
+    pub fn step(&mut self,frames:isize){
+        self.paused = true;
+        let index = (self.frame_index(self.time) as isize + frames).clamp(0,self.frames.len() as isize - 1);
+        self.time = self.frames[index as usize].0;
+    }
+
+    // Double or halve the playback speed.
+    pub fn change_speed(&mut self,faster:bool){
+        let speed = if faster { self.speed * 2.0 } else { self.speed / 2.0 };
+        self.speed = speed.clamp(MIN_SPEED,MAX_SPEED);
+    }
+
+    // Index of the last frame at or before the given time.
+    fn frame_index(&self,time:f32) -> usize{
+        self.frames.partition_point(|frame| frame.0 <= time).saturating_sub(1)
+    }
+
+    fn frame(&self,index:usize) -> &[TrajectoryRecord]{
+        let start = self.frames[index].1;
+        let end = self.frames.get(index + 1).map_or(self.records.len(),|frame| frame.1);
+        &self.records[start..end]
+    }
+
+    // Vehicles at the playback time, moved towards their next recorded state between frames.
+    pub fn vehicles(&self) -> Vec<TrajectoryRecord>{
+        let index = self.frame_index(self.time);
+        let mut vehicles = self.frame(index).to_vec();
+        if index + 1 >= self.frames.len(){
+            return vehicles;
+        }
+        let next = self.frame(index + 1);
+        let fraction = (self.time - self.frames[index].0) / (self.frames[index + 1].0 - self.frames[index].0);
+        let lerp = |from:f32,to:f32| from + (to - from) * fraction;
+        for vehicle in vehicles.iter_mut(){
+            //Both frames are sorted by id
+            if let Ok(next_index) = next.binary_search_by_key(&vehicle.id,|record| record.id){
+                let to = &next[next_index];
+                vehicle.x = lerp(vehicle.x,to.x);
+                vehicle.y = lerp(vehicle.y,to.y);
+                vehicle.z = lerp(vehicle.z,to.z);
+                vehicle.velocity = lerp(vehicle.velocity,to.velocity);
+                if vehicle.road == to.road{
+                    vehicle.position = lerp(vehicle.position,to.position);
+                }
+            }
+        }
+        vehicles
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::vehicle_type::VehicleType;
+
+    fn record(time:f32,id:usize,road:usize,position:f32) -> TrajectoryRecord{
+        TrajectoryRecord{time,id,road,position,x: position,y: id as f32,z: 0.0,velocity: 10.0 + time,acceleration: 1.0,vehicle_type: VehicleType::Car}
+    }
+
+    // Three frames out of order, vehicle 1 leaves after the second and vehicle 0 changes road before the third.
+    fn replay() -> Replay{
+        Replay::new(vec![
+            record(2.0,0,1,5.0),
+            record(1.0,1,0,0.0),
+            record(0.0,1,0,0.0),
+            record(1.0,0,0,90.0),
+            record(0.0,0,0,80.0),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn empty_input_is_rejected(){
+        assert!(Replay::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn frames_are_grouped_by_time(){
+        let replay = replay();
+        assert_eq!(replay.frames,vec![(0.0,0),(1.0,2),(2.0,4)]);
+        assert_eq!((replay.start_time(),replay.end_time()),(0.0,2.0));
+        assert_eq!(replay.time,0.0);
+        let ids:Vec<usize> = replay.frame(0).iter().map(|record| record.id).collect();
+        assert_eq!(ids,vec![0,1]);
+    }
+
+    #[test]
+    fn vehicles_are_interpolated_between_frames(){
+        let mut replay = replay();
+        replay.seek(0.25);
+        let vehicles = replay.vehicles();
+        assert_eq!(vehicles.len(),2);
+        assert_eq!((vehicles[0].x,vehicles[0].position,vehicles[0].velocity),(82.5,82.5,10.25));
+
+        //The position along the road is kept when the next frame is on another road, the point still moves
+        //Vehicles missing from the next frame stay where they were recorded
+        replay.seek(1.5);
+        let vehicles = replay.vehicles();
+        assert_eq!(vehicles.len(),2);
+        assert_eq!((vehicles[0].x,vehicles[0].position,vehicles[0].road),(47.5,90.0,0));
+        assert_eq!((vehicles[1].x,vehicles[1].velocity),(0.0,11.0));
+
+        //The last frame is shown as recorded
+        replay.seek(10.0);
+        assert_eq!(replay.time,2.0);
+        let vehicles = replay.vehicles();
+        assert_eq!((vehicles.len(),vehicles[0].road,vehicles[0].position),(1,1,5.0));
+    }
+
+    #[test]
+    fn steps_stop_at_both_ends(){
+        let mut replay = replay();
+        replay.seek(0.5);
+        replay.step(1);
+        assert!(replay.paused);
+        assert_eq!(replay.time,1.0);
+        replay.step(5);
+        assert_eq!(replay.time,2.0);
+        replay.step(-1);
+        assert_eq!(replay.time,1.0);
+        replay.step(-5);
+        assert_eq!(replay.time,0.0);
+        replay.seek(-3.0);
+        assert_eq!(replay.time,0.0);
+    }
+
+    #[test]
+    fn advance_follows_the_speed_and_pauses_at_the_end(){
+        let mut replay = replay();
+        replay.change_speed(true);
+        replay.advance(0.25);
+        assert_eq!(replay.time,0.5);
+        replay.paused = true;
+        replay.advance(0.25);
+        assert_eq!(replay.time,0.5);
+        replay.paused = false;
+        replay.advance(10.0);
+        assert_eq!(replay.time,2.0);
+        assert!(replay.paused);
+        for _ in 0..20{
+            replay.change_speed(false);
+        }
+        assert_eq!(replay.speed,MIN_SPEED);
+    }
+}
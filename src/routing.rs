@@ -0,0 +1,284 @@
+// Purpose: Route finding over the road graph.
+
+use crate::comp::World;
+use crate::contraction::ContractionHierarchy;
+use crate::vehicle_type::VehicleType;
+use ordered_float::OrderedFloat;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Reverse;
+
+// A route between two roads and its cost.
+// The cost is the distance travelled from the end of the first road to the end of the last road.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route{
+    pub roads: Vec<usize>,
+    pub cost: f32,
+}
+
+impl World{
+    // Helper function to get adjacent roads a vehicle type may turn into for a given road index
+    pub(crate) fn get_adjacent_roads(&self, road_index: usize, vehicle_type: VehicleType) -> Vec<usize> {
+        if road_index < self.roads.len() {
+            let road = &self.roads[road_index];
+            road.to_road.iter().copied().filter(|&next_road| {
+                match self.get_connection(road_index, next_road) {
+                    Some(connection) => connection.allows(vehicle_type),
+                    None => true,
+                }
+            }).collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Helper function to get the distance between two roads
+    fn get_distance_between_roads(&self, road_index_1: usize, road_index_2: usize) -> f32 {
+        if road_index_1 < self.roads.len() && road_index_2 < self.roads.len() {
+            let road_1 = &self.roads[road_index_1];
+            let road_2 = &self.roads[road_index_2];
+            // For simplicity, assume 3D Euclidean distance between roads' end points
+            ((road_1.to.0 - road_2.from.0).powi(2) + (road_1.to.1 - road_2.from.1).powi(2)).sqrt()
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    // Cost of moving from the end of a road to the end of the next road, including the turn penalty.
+    fn get_link_cost(&self, road_index: usize, next_road: usize) -> f32 {
+        if next_road < self.roads.len() {
+            let turn_penalty = self.get_connection(road_index, next_road).map_or(0.0, |connection| connection.turn_penalty);
+            self.get_distance_between_roads(road_index, next_road) + self.roads[next_road].length.into_inner() + turn_penalty
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    // Heuristic function to estimate the cost from a given road to the destination road.
+    fn heuristic(&self, road_index: usize, destination_road: usize) -> OrderedFloat<f32> {
+        if road_index == destination_road {
+            return OrderedFloat(0.0);
+        }
+        OrderedFloat(self.get_distance_between_roads(road_index, destination_road))
+    }
+
+    // Total cost of a route given as a list of roads.
+    pub fn route_cost(&self, roads: &[usize]) -> f32 {
+        roads.windows(2).map(|link| self.get_link_cost(link[0], link[1])).sum()
+    }
+
+    // Link costs of every connection a vehicle type may use.
+    fn get_link_costs(&self, vehicle_type: VehicleType) -> HashMap<(usize,usize),f32> {
+        let mut link_costs = HashMap::new();
+        for road_index in 0..self.roads.len() {
+            for next_road in self.get_adjacent_roads(road_index, vehicle_type) {
+                link_costs.insert((road_index, next_road), self.get_link_cost(road_index, next_road));
+            }
+        }
+        link_costs
+    }
+
+    // Build the contraction hierarchy used to answer shortest path queries on large networks.
+    pub fn build_route_index(&mut self) {
+        let mut links = Vec::new();
+        let mut positions = Vec::new();
+        for (road_index, road) in self.roads.iter().enumerate() {
+            for &next_road in road.to_road.iter() {
+                links.push((road_index, next_road));
+            }
+            positions.push(((road.from.0 + road.to.0) / 2.0, (road.from.1 + road.to.1) / 2.0));
+        }
+        let mut index = ContractionHierarchy::new(&positions, &links);
+        for vehicle_type in VehicleType::ALL {
+            index.customize(vehicle_type, self.get_link_costs(vehicle_type));
+        }
+        self.route_index = Some(index);
+    }
+
+    // Update the route index after the cost or restrictions of a link changed.
+    // The index is dropped if the link is new to the network topology.
+    pub(crate) fn update_route_index(&mut self, road_index: usize, next_road: usize) {
+        let covered = match &self.route_index {
+            Some(index) => index.covers_link(road_index, next_road),
+            None => return,
+        };
+        if !covered {
+            self.route_index = None;
+            return;
+        }
+        let mut costs = Vec::new();
+        for vehicle_type in VehicleType::ALL {
+            let allowed = self.get_adjacent_roads(road_index, vehicle_type).contains(&next_road);
+            let cost = if allowed { self.get_link_cost(road_index, next_road) } else { f32::INFINITY };
+            costs.push((vehicle_type, cost));
+        }
+        if let Some(index) = &mut self.route_index {
+            for (vehicle_type, cost) in costs {
+                index.update_link_cost(vehicle_type, road_index, next_road, cost);
+            }
+        }
+    }
+
+    // Function to find the shortest path, using the route index when it is built and A* otherwise
+    pub(crate) fn find_shortest_path(&self, start_road: usize, destination_road: usize, vehicle_type: VehicleType) -> Vec<usize> {
+        if let Some(index) = &self.route_index {
+            if let Some(route) = index.query(vehicle_type, start_road, destination_road) {
+                return route.roads;
+            }
+            if start_road < index.road_count() && destination_road < index.road_count() {
+                return vec![start_road];
+            }
+        }
+        match self.find_shortest_path_avoiding(start_road, destination_road, vehicle_type, &HashSet::new(), &HashSet::new()) {
+            Some(route) => route.roads,
+            None => vec![start_road],
+        }
+    }
+
+    // Whether a vehicle type can drive from one road to another, following its turn restrictions.
+    pub(crate) fn can_reach(&self, start_road: usize, destination_road: usize, vehicle_type: VehicleType) -> bool {
+        self.find_shortest_path_avoiding(start_road, destination_road, vehicle_type, &HashSet::new(), &HashSet::new()).is_some()
+    }
+
+    // A* search that skips the given roads and links, returns None if the destination can't be reached.
+    pub(crate) fn find_shortest_path_avoiding(&self, start_road: usize, destination_road: usize, vehicle_type: VehicleType, banned_roads: &HashSet<usize>, banned_links: &HashSet<(usize,usize)>) -> Option<Route> {
+        let mut distances = HashMap::new();
+        let mut previous = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        distances.insert(start_road, OrderedFloat(0.0));
+        queue.push(Reverse((self.heuristic(start_road, destination_road), start_road)));
+
+        while let Some(Reverse((_, current_road))) = queue.pop() {
+            if current_road == destination_road {
+                // Reconstruct the path
+                let mut path = Vec::new();
+                let mut road = destination_road;
+                while road != start_road {
+                    path.push(road);
+                    road = previous[&road];
+                }
+                path.push(start_road);
+                path.reverse();
+                return Some(Route{
+                    roads: path,
+                    cost: distances[&destination_road].into_inner(),
+                });
+            }
+
+            let current_distance = distances[&current_road];
+            for next_road in self.get_adjacent_roads(current_road, vehicle_type) {
+                if banned_roads.contains(&next_road) || banned_links.contains(&(current_road, next_road)) {
+                    continue;
+                }
+                let total_distance = current_distance + OrderedFloat(self.get_link_cost(current_road, next_road));
+
+                if !distances.contains_key(&next_road) || total_distance < distances[&next_road] {
+                    distances.insert(next_road, total_distance);
+                    previous.insert(next_road, current_road);
+                    queue.push(Reverse((total_distance + self.heuristic(next_road, destination_road), next_road)));
+                }
+            }
+        }
+        None
+    }
+
+    // Find up to k loopless routes between two roads for a vehicle type, cheapest first, using Yen's algorithm.
+    pub fn k_shortest_paths(&self, start_road: usize, destination_road: usize, k: usize, vehicle_type: VehicleType) -> Vec<Route> {
+        let mut routes: Vec<Route> = Vec::new();
+        if k == 0 {
+            return routes;
+        }
+        match self.find_shortest_path_avoiding(start_road, destination_road, vehicle_type, &HashSet::new(), &HashSet::new()) {
+            Some(route) => routes.push(route),
+            None => return routes,
+        }
+        let mut candidates: Vec<Route> = Vec::new();
+
+        while routes.len() < k {
+            let last = routes[routes.len()-1].roads.clone();
+            for i in 0..last.len()-1 {
+                let spur_road = last[i];
+                let root = &last[..=i];
+
+                // Remove the links already used by routes sharing this root
+                let mut banned_links = HashSet::new();
+                for route in routes.iter() {
+                    if route.roads.len() > i + 1 && &route.roads[..=i] == root {
+                        banned_links.insert((route.roads[i], route.roads[i+1]));
+                    }
+                }
+                // Keep the route loopless by removing the root roads before the spur
+                let banned_roads: HashSet<usize> = root[..i].iter().copied().collect();
+
+                if let Some(spur) = self.find_shortest_path_avoiding(spur_road, destination_road, vehicle_type, &banned_roads, &banned_links) {
+                    let mut roads = root[..i].to_vec();
+                    roads.extend(spur.roads);
+                    let candidate = Route{
+                        cost: self.route_cost(&roads),
+                        roads,
+                    };
+                    if !candidates.iter().chain(routes.iter()).any(|route| route.roads == candidate.roads) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+
+            // Move the cheapest candidate to the result
+            let cheapest = candidates.iter().enumerate().min_by_key(|(_, route)| OrderedFloat(route.cost)).map(|(i, _)| i);
+            match cheapest {
+                Some(i) => routes.push(candidates.remove(i)),
+                None => break,
+            }
+        }
+        routes
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::contraction::tests::grid;
+    use crate::vehicle_type::VehicleType;
+    use std::collections::HashSet;
+
+    #[test]
+    fn k_shortest_paths_are_loopless_and_ascending(){
+        let mut world = grid(4);
+        //Some turns are for buses only, so cars take other routes
+        let links:Vec<(usize,usize)> = world.connections.keys().copied().filter(|&(from,to)| (from + to) % 5 == 0).collect();
+        for (from,to) in links{
+            world.add_connection(from,to,Some(vec![VehicleType::Bus]),0.0,None);
+        }
+        let (start,destination) = (0,world.roads.len() - 1);
+        for vehicle_type in [VehicleType::Car,VehicleType::Bus]{
+            let routes = world.k_shortest_paths(start,destination,8,vehicle_type);
+            assert_eq!(routes.len(),8,"The grid has more than 8 routes for {:?}",vehicle_type);
+            let shortest = world.find_shortest_path_avoiding(start,destination,vehicle_type,&HashSet::new(),&HashSet::new()).unwrap();
+            assert!((routes[0].cost - shortest.cost).abs() < 1e-3);
+            for (i,route) in routes.iter().enumerate(){
+                assert_eq!((route.roads[0],route.roads[route.roads.len() - 1]),(start,destination));
+                assert!((world.route_cost(&route.roads) - route.cost).abs() < 1e-3,"Route {:?} does not cost {}",route.roads,route.cost);
+                assert_eq!(route.roads.iter().collect::<HashSet<_>>().len(),route.roads.len(),"Route {:?} has a loop",route.roads);
+                for link in route.roads.windows(2){
+                    assert!(world.connections.get(&(link[0],link[1])).is_some_and(|connection| connection.allows(vehicle_type)),
+                        "{:?} may not turn from {} to {}",vehicle_type,link[0],link[1]);
+                }
+                if i > 0{
+                    assert!(routes[i - 1].cost <= route.cost + 1e-3,"Routes are not in ascending cost");
+                }
+                assert!(routes[..i].iter().all(|other| other.roads != route.roads),"Route {:?} is found twice",route.roads);
+            }
+        }
+    }
+
+    #[test]
+    fn k_shortest_paths_stop_when_routes_run_out(){
+        let world = grid(2);
+        //Without U-turns, a square of two way streets has a single route between two roads
+        let start = 0;
+        let destination = (0..world.roads.len()).find(|&road| world.roads[road].from == world.roads[start].to && world.roads[road].to != world.roads[start].from).unwrap();
+        let routes = world.k_shortest_paths(start,destination,10,VehicleType::Car);
+        assert_eq!(routes.len(),1);
+        assert_eq!(routes[0].roads,vec![start,destination]);
+        assert!(world.k_shortest_paths(start,destination,0,VehicleType::Car).is_empty());
+    }
+}
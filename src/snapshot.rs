@@ -0,0 +1,185 @@
+// Purpose: Binary snapshots of the full simulation state, restored exactly to branch runs.
+
+use crate::comp::{Connection, Departure, Road, Vehicle, World};
+use crate::contraction::ContractionHierarchy;
+use crate::detector::{AreaDetector, PointDetector};
+use crate::link_stats::LinkStatistics;
+use crate::rng::SimRng;
+use crate::trip::TripRecord;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Written at the start of every snapshot file.
+const SNAPSHOT_MAGIC: [u8; 8] = *b"TRAFSNAP";
+
+// Incremented whenever the snapshot layout changes.
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Everything a run needs to continue: network, vehicles with their obstacle map entries and IDs,
+// signal states, random streams, pending departures, trips and measurements.
+// The trajectory recorder writes to a file and is not part of the state.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<'a>{
+    version: u32,
+    time: f32,
+    next_vehicle_id: usize,
+    rng: Cow<'a,SimRng>,
+    roads: Cow<'a,[Road]>,
+    vehicles: Cow<'a,[Vehicle]>,
+    //The distributions are internally tagged, which bincode cannot read back, so they are stored as JSON
+    vehicle_types: String,
+    connections: Cow<'a,HashMap<(usize,usize),Connection>>,
+    route_index: Cow<'a,Option<ContractionHierarchy>>,
+    geo_origin: Option<(f64,f64)>,
+    departures: Cow<'a,[Departure]>,
+    trips: Cow<'a,[TripRecord]>,
+    point_detectors: Cow<'a,[PointDetector]>,
+    area_detectors: Cow<'a,[AreaDetector]>,
+    link_statistics: Cow<'a,Option<LinkStatistics>>,
+}
+
+impl World{
+    // Encode the simulation state.
+    pub fn snapshot(&self) -> Result<Vec<u8>,String>{
+        let snapshot = Snapshot{
+            version: SNAPSHOT_VERSION,
+            time: self.time,
+            next_vehicle_id: self.next_vehicle_id,
+            rng: Cow::Borrowed(&self.rng),
+            roads: Cow::Borrowed(&self.roads),
+            vehicles: Cow::Borrowed(&self.vehicles),
+            vehicle_types: serde_json::to_string(&self.vehicle_types).map_err(|e| e.to_string())?,
+            connections: Cow::Borrowed(&self.connections),
+            route_index: Cow::Borrowed(&self.route_index),
+            geo_origin: self.geo_origin,
+            departures: Cow::Borrowed(&self.departures),
+            trips: Cow::Borrowed(&self.trips),
+            point_detectors: Cow::Borrowed(&self.point_detectors),
+            area_detectors: Cow::Borrowed(&self.area_detectors),
+            link_statistics: Cow::Borrowed(&self.link_statistics),
+        };
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes,&snapshot).map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+
+    // Replace the simulation state with an encoded snapshot, the world is unchanged if it is invalid.
+    pub fn restore(&mut self,bytes:&[u8]) -> Result<(),String>{
+        let data = bytes.strip_prefix(&SNAPSHOT_MAGIC[..]).ok_or("Not a snapshot file.")?;
+        //The version comes first so older layouts are reported instead of misread
+        let version:u32 = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        if version != SNAPSHOT_VERSION{
+            return Err(format!("Unsupported snapshot version {}, expected {}.", version, SNAPSHOT_VERSION));
+        }
+        let snapshot:Snapshot = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        let vehicle_types = serde_json::from_str(&snapshot.vehicle_types).map_err(|e| e.to_string())?;
+
+        self.time = snapshot.time;
+        self.next_vehicle_id = snapshot.next_vehicle_id;
+        self.rng = snapshot.rng.into_owned();
+        self.roads = snapshot.roads.into_owned();
+        self.vehicles = snapshot.vehicles.into_owned();
+        self.vehicle_types = vehicle_types;
+        self.connections = snapshot.connections.into_owned();
+        self.route_index = snapshot.route_index.into_owned();
+        self.geo_origin = snapshot.geo_origin;
+        self.departures = snapshot.departures.into_owned();
+        self.trips = snapshot.trips.into_owned();
+        self.point_detectors = snapshot.point_detectors.into_owned();
+        self.area_detectors = snapshot.area_detectors.into_owned();
+        self.link_statistics = snapshot.link_statistics.into_owned();
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self,path:&Path) -> Result<(),String>{
+        let bytes = self.snapshot()?;
+        std::fs::write(path,bytes).map_err(|e| e.to_string())
+    }
+
+    pub fn load_snapshot(&mut self,path:&Path) -> Result<(),String>{
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        self.restore(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::contraction::tests::grid;
+    use crate::phy::update_comp;
+    use crate::rng::{DEMAND_STREAM, DRIVER_STREAM};
+    use crate::vehicle_type::{Distribution, VehicleType, VehicleTypeDistributions};
+    use rand::Rng;
+
+    const STEP: f32 = 0.1;
+
+    // A seeded grid with spread out drivers, standing vehicles and a random flow.
+    fn seeded_world() -> World{
+        let mut world = grid(4);
+        world.rng = SimRng::new(7);
+        world.vehicle_types.insert(VehicleType::Car,VehicleTypeDistributions{
+            acceleration: Some(Distribution::Uniform{min: 1.0,max: 3.0}),
+            ..Default::default()
+        });
+        world.build_route_index();
+        for road in [0,5,9]{
+            world.spawn_vehicle(road,10.0,VehicleType::Car);
+        }
+        let destination = world.roads.len() - 1;
+        for departure in world.flow_departures(0,destination,1800.0,120.0,VehicleType::Car).unwrap(){
+            world.add_departure(departure);
+        }
+        world
+    }
+
+    fn run(world:&mut World,steps:usize){
+        for _ in 0..steps{
+            update_comp(STEP,world);
+        }
+    }
+
+    // Everything the continued runs must agree on.
+    fn state(world:&mut World) -> String{
+        let vehicles:Vec<(usize,f32,f32,usize)> = world.vehicles.iter().map(|vehicle| (vehicle.id,vehicle.position,vehicle.velocity,vehicle.on_road)).collect();
+        let draws:(u64,u64) = (world.rng.stream(DEMAND_STREAM).gen(),world.rng.stream(DRIVER_STREAM).gen());
+        format!("{} {} {:?} {} {} {:?}",world.time,world.next_vehicle_id,vehicles,world.departures.len(),world.trips.len(),draws)
+    }
+
+    #[test]
+    fn restored_run_continues_like_the_original(){
+        let mut world = seeded_world();
+        run(&mut world,300);
+        assert!(!world.vehicles.is_empty());
+        let bytes = world.snapshot().unwrap();
+
+        let mut restored = World::new();
+        restored.restore(&bytes).unwrap();
+        for world in [&mut world,&mut restored]{
+            //New vehicles draw from the restored streams and get the next ids
+            world.spawn_vehicle(3,0.0,VehicleType::Car);
+            run(world,600);
+        }
+        assert_eq!(state(&mut restored),state(&mut world));
+    }
+
+    #[test]
+    fn invalid_snapshots_keep_the_world(){
+        let mut world = seeded_world();
+        let bytes = world.snapshot().unwrap();
+        let expected = state(&mut seeded_world());
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(world.restore(&wrong_magic),Err("Not a snapshot file.".to_string()));
+
+        //The version follows the magic bytes
+        let mut newer = bytes.clone();
+        newer[SNAPSHOT_MAGIC.len()..SNAPSHOT_MAGIC.len() + 4].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(world.restore(&newer).unwrap_err().contains("Unsupported snapshot version"));
+
+        assert!(world.restore(&bytes[..bytes.len() / 2]).is_err());
+        assert_eq!(state(&mut world),expected);
+    }
+}
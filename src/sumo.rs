@@ -0,0 +1,581 @@
+// Purpose: Import and export SUMO networks (.net.xml) and routes (.rou.xml).
+
+use crate::comp::{Departure, World, ARRIVAL_MARGIN, JUNCTION_SPEED, SIGNAL_SPEED};
+use crate::rng::DEMAND_STREAM;
+use crate::vehicle_type::VehicleType;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+
+// Lane width used when exporting lane shapes, the SUMO default.
+const LANE_WIDTH: f32 = 3.2;
+
+// Default end time of flows without one, in seconds.
+const DEFAULT_FLOW_END: f32 = 3600.0;
+
+// SUMO vehicle class matching a vehicle type.
+fn vehicle_class(vehicle_type:VehicleType) -> &'static str{
+    match vehicle_type{
+        VehicleType::Car => "passenger",
+        VehicleType::Van => "delivery",
+        VehicleType::Truck => "truck",
+        VehicleType::Bus => "bus",
+        VehicleType::Motorcycle => "motorcycle",
+    }
+}
+
+// Vehicle type matching a SUMO vehicle class, other classes are treated as cars.
+fn from_vehicle_class(class:&str) -> VehicleType{
+    match class{
+        "delivery" => VehicleType::Van,
+        "truck" | "trailer" => VehicleType::Truck,
+        "bus" | "coach" => VehicleType::Bus,
+        "motorcycle" | "moped" => VehicleType::Motorcycle,
+        _ => VehicleType::Car,
+    }
+}
+
+// Check a vehicle type against the allow/disallow attributes of a lane.
+fn lane_permits(lane:&roxmltree::Node,vehicle_type:VehicleType) -> bool{
+    let class = vehicle_class(vehicle_type);
+    if let Some(allow) = lane.attribute("allow"){
+        return allow.split_whitespace().any(|allowed| allowed == class || allowed == "all");
+    }
+    if let Some(disallow) = lane.attribute("disallow"){
+        return !disallow.split_whitespace().any(|disallowed| disallowed == class || disallowed == "all");
+    }
+    true
+}
+
+// Parse a SUMO shape "x,y[,z] x,y[,z] ..." into points.
+fn parse_shape(shape:&str) -> Vec<Point>{
+    shape.split_whitespace().filter_map(|point| {
+        let mut values = point.split(',').map(|value| value.parse::<f32>());
+        let x = values.next()?.ok()?;
+        let y = values.next()?.ok()?;
+        let z = values.next().and_then(|value| value.ok()).unwrap_or(0.0);
+        Some((x,y,z))
+    }).collect()
+}
+
+fn parse_attribute(node:&roxmltree::Node,name:&str) -> Option<f32>{
+    node.attribute(name)?.parse().ok()
+}
+
+// Escape text for use in an XML attribute.
+fn escape(text:&str) -> String{
+    text.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;").replace('"',"&quot;")
+}
+
+// Point of a shape.
+type Point = (f32,f32,f32);
+
+// Position of a junction and whether it has a signal.
+type Junction = (f32,f32,bool);
+
+// Parameters of a vType from a route file.
+#[derive(Clone, Copy)]
+struct SumoType{
+    vehicle_type: VehicleType,
+    acceleration: Option<f32>,
+    break_decceleration: Option<f32>,
+}
+
+impl World{
+    // Replace the world with a SUMO network and optionally add the vehicles and flows of a route file.
+    // Both files are read before the world changes.
+    pub fn load_sumo(&mut self,net_path:&Path,route_path:Option<&Path>) -> Result<(),String>{
+        let net = std::fs::read_to_string(net_path).map_err(|e| format!("Unable to open {}: {}", net_path.display(), e))?;
+        let routes = match route_path{
+            Some(route_path) => Some(std::fs::read_to_string(route_path).map_err(|e| format!("Unable to open {}: {}", route_path.display(), e))?),
+            None => None,
+        };
+        let edge_ids = self.load_sumo_net(&net)?;
+        if let Some(routes) = routes{
+            self.load_sumo_routes(&routes,&edge_ids)?;
+        }
+        Ok(())
+    }
+
+    // Build roads and connections from a SUMO network, returns the road index of every edge id.
+    // Every normal edge becomes a road along the middle of its lanes, internal edges are skipped.
+    pub fn load_sumo_net(&mut self,contents:&str) -> Result<HashMap<String,usize>,String>{
+        let document = roxmltree::Document::parse(contents).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+        self.reset();
+
+        let junction_types:HashMap<&str,&str> = root.children()
+            .filter(|node| node.has_tag_name("junction"))
+            .filter_map(|junction| Some((junction.attribute("id")?,junction.attribute("type").unwrap_or("priority"))))
+            .collect();
+
+        let mut edge_ids = HashMap::new();
+        let mut road_lanes = Vec::new();
+        for edge in root.children().filter(|node| node.has_tag_name("edge")){
+            if edge.attribute("function").is_some_and(|function| function == "internal"){
+                continue;
+            }
+            let id = edge.attribute("id").ok_or("Edge without id")?;
+            let lanes:Vec<roxmltree::Node> = edge.children().filter(|node| node.has_tag_name("lane")).collect();
+            if lanes.is_empty(){
+                continue;
+            }
+
+            //The road runs along the middle of the lane shapes
+            let shapes:Vec<Vec<Point>> = lanes.iter().map(|lane| parse_shape(lane.attribute("shape").unwrap_or(""))).filter(|shape| shape.len() >= 2).collect();
+            if shapes.is_empty(){
+                return Err(format!("Edge {} without lane shapes", id));
+            }
+            let count = shapes.len() as f32;
+            let average = |point:fn(&Vec<Point>) -> Point| {
+                let sum = shapes.iter().map(point).fold((0.0,0.0,0.0),|a,b| (a.0+b.0,a.1+b.1,a.2+b.2));
+                (sum.0/count,sum.1/count,sum.2/count)
+            };
+            let from = average(|shape| shape[0]);
+            let to = average(|shape| shape[shape.len()-1]);
+
+            let speed_limit = lanes.iter().filter_map(|lane| parse_attribute(lane,"speed")).fold(0.0,f32::max);
+            let speed_limit = if speed_limit > 0.0 { speed_limit } else { 13.89 };
+            let junction_type = edge.attribute("to").and_then(|to| junction_types.get(to)).copied().unwrap_or("priority");
+            let signal = junction_type.starts_with("traffic_light");
+            let end_speed_limit = if signal{
+                SIGNAL_SPEED
+            }
+            else if junction_type == "dead_end"{
+                speed_limit
+            }
+            else{
+                JUNCTION_SPEED.min(speed_limit)
+            };
+
+            let road_index = self.roads.len();
+            self.add_road(from,to,lanes.len().min(u8::MAX as usize) as u8,speed_limit,Vec::new(),Vec::new(),end_speed_limit);
+            self.roads[road_index].signal = signal;
+            edge_ids.insert(id.to_string(),road_index);
+            road_lanes.push(lanes);
+        }
+
+        //Connections between lanes become movements between roads, limited to the types allowed on the target lanes
+        for connection in root.children().filter(|node| node.has_tag_name("connection")){
+            let from = connection.attribute("from").and_then(|id| edge_ids.get(id));
+            let to = connection.attribute("to").and_then(|id| edge_ids.get(id));
+            if let (Some(&from),Some(&to)) = (from,to){
+                if self.get_connection(from,to).is_some(){
+                    continue;
+                }
+                let allowed:Vec<VehicleType> = VehicleType::ALL.iter().copied().filter(|&vehicle_type| road_lanes[to].iter().any(|lane| lane_permits(lane,vehicle_type))).collect();
+                let allowed_types = if allowed.len() == VehicleType::ALL.len() { None } else { Some(allowed) };
+                self.add_connection(from,to,allowed_types,0.0,None);
+            }
+        }
+
+        self.build_route_index();
+        Ok(edge_ids)
+    }
+
+    // Schedule the vehicles, trips and flows of a SUMO route file.
+    pub fn load_sumo_routes(&mut self,contents:&str,edge_ids:&HashMap<String,usize>) -> Result<(),String>{
+        let document = roxmltree::Document::parse(contents).map_err(|e| e.to_string())?;
+        let root = document.root_element();
+
+        let mut types:HashMap<String,SumoType> = HashMap::new();
+        for vtype in root.descendants().filter(|node| node.has_tag_name("vType")){
+            let id = vtype.attribute("id").ok_or("vType without id")?;
+            types.insert(id.to_string(),SumoType{
+                vehicle_type: from_vehicle_class(vtype.attribute("vClass").unwrap_or("passenger")),
+                acceleration: parse_attribute(&vtype,"accel"),
+                break_decceleration: parse_attribute(&vtype,"decel").map(|decel| -decel),
+            });
+        }
+
+        let parse_edges = |edges:&str| -> Result<Vec<usize>,String>{
+            edges.split_whitespace().map(|edge| edge_ids.get(edge).copied().ok_or(format!("Unknown edge {}", edge))).collect()
+        };
+        let mut routes:HashMap<String,Vec<usize>> = HashMap::new();
+        for route in root.children().filter(|node| node.has_tag_name("route")){
+            if let (Some(id),Some(edges)) = (route.attribute("id"),route.attribute("edges")){
+                routes.insert(id.to_string(),parse_edges(edges)?);
+            }
+        }
+
+        for element in root.children().filter(|node| node.is_element()){
+            let tag = element.tag_name().name();
+            if tag != "vehicle" && tag != "trip" && tag != "flow"{
+                continue;
+            }
+
+            //The route is given inline, by id, or only by its first and last edge
+            let inline = element.children().find(|node| node.has_tag_name("route")).and_then(|route| route.attribute("edges"));
+            let route = match (inline,element.attribute("route")){
+                (Some(edges),_) => parse_edges(edges)?,
+                (None,Some(id)) => routes.get(id).cloned().ok_or(format!("Unknown route {}", id))?,
+                (None,None) => {
+                    let from = element.attribute("from").ok_or("Trip without route or from edge")?;
+                    let to = element.attribute("to").ok_or("Trip without route or to edge")?;
+                    let from = parse_edges(from)?.first().copied().ok_or("Trip with empty from edge")?;
+                    let to = parse_edges(to)?.first().copied().ok_or("Trip with empty to edge")?;
+                    vec![from,to]
+                }
+            };
+            if route.is_empty(){
+                continue;
+            }
+            //A trip only fixes its ends, the route in between is found by routing
+            let fixed_route = if inline.is_some() || element.attribute("route").is_some() { route.clone() } else { Vec::new() };
+
+            let sumo_type = element.attribute("type").and_then(|id| types.get(id)).copied();
+            let vehicle_type = sumo_type.map_or(VehicleType::Car,|sumo_type| sumo_type.vehicle_type);
+            let on_road = route[0];
+            let destination = route[route.len()-1];
+            let destination_length = self.roads[destination].length.into_inner();
+            let position = parse_attribute(&element,"departPos").unwrap_or(0.0).max(0.0);
+            let velocity = match element.attribute("departSpeed"){
+                Some("max") | Some("desired") | Some("speedLimit") => self.roads[on_road].speed_limit,
+                Some(speed) => speed.parse().unwrap_or(0.0),
+                None => 0.0,
+            };
+            //Negative arrival positions count from the end of the road
+            let destination_position = match parse_attribute(&element,"arrivalPos"){
+                Some(arrival) if arrival < 0.0 => destination_length + arrival,
+                Some(arrival) => arrival,
+                None => destination_length - ARRIVAL_MARGIN,
+            }.clamp(0.0,(destination_length - ARRIVAL_MARGIN).max(0.0));
+
+            //Departure times of the vehicles of this element
+            let times:Vec<f32> = if tag == "flow"{
+                let begin = parse_attribute(&element,"begin").unwrap_or(0.0);
+                let end = parse_attribute(&element,"end").unwrap_or(DEFAULT_FLOW_END);
+                let period = parse_attribute(&element,"period").or_else(|| parse_attribute(&element,"vehsPerHour").map(|per_hour| 3600.0 / per_hour));
+                let number = element.attribute("number").and_then(|number| number.trim().parse::<usize>().ok());
+                match (period,number,parse_attribute(&element,"probability")){
+                    (Some(period),_,_) if period > 0.0 => {
+                        (0..).map(|i| begin + i as f32 * period).take_while(|&time| time < end).collect()
+                    }
+                    //Exactly the given number of vehicles, spread evenly, rounding never adds one
+                    (None,Some(number),_) => {
+                        let period = (end - begin) / number.max(1) as f32;
+                        (0..number).map(|i| begin + i as f32 * period).collect()
+                    }
+                    //Random departures, one chance per second
+                    (_,_,Some(probability)) => {
+                        let rng = self.rng.stream(DEMAND_STREAM);
+                        (0..((end - begin).max(0.0) as usize)).map(|i| begin + i as f32).filter(|_| rng.gen::<f32>() < probability).collect()
+                    }
+                    _ => return Err(format!("Flow {} without period, vehsPerHour, number or probability", element.attribute("id").unwrap_or(""))),
+                }
+            }
+            else{
+                vec![parse_attribute(&element,"depart").unwrap_or(0.0)]
+            };
+
+            for time in times{
+                let (acceleration,break_decceleration,watch_distance) = self.sample_vehicle_params(vehicle_type);
+                self.add_departure(Departure{
+                    time,
+                    vehicle_type,
+                    position,
+                    velocity,
+                    acceleration: sumo_type.and_then(|sumo_type| sumo_type.acceleration).unwrap_or(acceleration),
+                    break_decceleration: sumo_type.and_then(|sumo_type| sumo_type.break_decceleration).unwrap_or(break_decceleration),
+                    on_road,
+                    watch_distance,
+                    destination,
+                    destination_position,
+                    route: fixed_route.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Write the world as a SUMO network and route file.
+    // Roads become edges "r<index>", road ends become junctions, and every vehicle gets its own vType.
+    // Signalized junctions are written with a single always green phase.
+    pub fn save_sumo(&self,net_path:&Path,route_path:&Path) -> std::io::Result<()>{
+        std::fs::write(net_path,self.sumo_net())?;
+        std::fs::write(route_path,self.sumo_routes())
+    }
+
+    // Junction id of every road end, road ends at the same point share a junction.
+    fn sumo_junctions(&self) -> (Vec<(String,String)>,BTreeMap<String,Junction>){
+        let key = |point:Point| format!("j{}_{}",(point.0*10.0).round() as i64,(point.1*10.0).round() as i64).replace('-',"m");
+        let mut ends = Vec::new();
+        let mut junctions = BTreeMap::new();
+        for road in self.roads.iter(){
+            let from = key(road.from);
+            let to = key(road.to);
+            junctions.entry(from.clone()).or_insert((road.from.0,road.from.1,false));
+            let junction = junctions.entry(to.clone()).or_insert((road.to.0,road.to.1,false));
+            junction.2 |= road.signal;
+            ends.push((from,to));
+        }
+        (ends,junctions)
+    }
+
+    pub fn sumo_net(&self) -> String{
+        let (ends,junctions) = self.sumo_junctions();
+        let mut net = String::new();
+        let (mut min_x,mut min_y,mut max_x,mut max_y) = (0.0f32,0.0f32,0.0f32,0.0f32);
+        for road in self.roads.iter(){
+            for point in [road.from,road.to]{
+                min_x = min_x.min(point.0);
+                min_y = min_y.min(point.1);
+                max_x = max_x.max(point.0);
+                max_y = max_y.max(point.1);
+            }
+        }
+        writeln!(net,"<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(net,"<!-- seed: {} -->",self.rng.seed()).unwrap();
+        writeln!(net,"<net version=\"1.9\">").unwrap();
+        writeln!(net,"    <location netOffset=\"0.00,0.00\" convBoundary=\"{:.2},{:.2},{:.2},{:.2}\" origBoundary=\"{:.2},{:.2},{:.2},{:.2}\" projParameter=\"!\"/>",min_x,min_y,max_x,max_y,min_x,min_y,max_x,max_y).unwrap();
+
+        for (index,road) in self.roads.iter().enumerate(){
+            writeln!(net,"    <edge id=\"r{}\" from=\"{}\" to=\"{}\" priority=\"-1\">",index,ends[index].0,ends[index].1).unwrap();
+            //Lanes are spread around the road line, lane 0 on the right
+            let direction = ((road.to.0-road.from.0)/road.length.into_inner(),(road.to.1-road.from.1)/road.length.into_inner());
+            let right = (direction.1,-direction.0);
+            for lane in 0..road.lanes.max(1){
+                let offset = ((road.lanes.max(1) - 1) as f32 / 2.0 - lane as f32) * LANE_WIDTH;
+                writeln!(net,"        <lane id=\"r{}_{}\" index=\"{}\" speed=\"{:.2}\" length=\"{:.2}\" shape=\"{:.2},{:.2} {:.2},{:.2}\"/>",
+                    index,lane,lane,road.speed_limit,road.length.into_inner(),
+                    road.from.0+right.0*offset,road.from.1+right.1*offset,road.to.0+right.0*offset,road.to.1+right.1*offset).unwrap();
+            }
+            writeln!(net,"    </edge>").unwrap();
+        }
+
+        //Signals get a single always green phase over all their links
+        let mut signal_links:BTreeMap<&String,usize> = BTreeMap::new();
+        let mut connections = String::new();
+        for (index,road) in self.roads.iter().enumerate(){
+            for &next_road in road.to_road.iter(){
+                if next_road >= self.roads.len(){
+                    continue;
+                }
+                let junction = &ends[index].1;
+                if junctions[junction].2{
+                    let link = signal_links.entry(junction).or_insert(0);
+                    writeln!(connections,"    <connection from=\"r{}\" to=\"r{}\" fromLane=\"0\" toLane=\"0\" tl=\"{}\" linkIndex=\"{}\"/>",index,next_road,junction,link).unwrap();
+                    *link += 1;
+                }
+                else{
+                    writeln!(connections,"    <connection from=\"r{}\" to=\"r{}\" fromLane=\"0\" toLane=\"0\"/>",index,next_road).unwrap();
+                }
+            }
+        }
+        for (junction,links) in signal_links.iter(){
+            writeln!(net,"    <tlLogic id=\"{}\" type=\"static\" programID=\"0\" offset=\"0\">",junction).unwrap();
+            writeln!(net,"        <phase duration=\"90\" state=\"{}\"/>","G".repeat(*links)).unwrap();
+            writeln!(net,"    </tlLogic>").unwrap();
+        }
+
+        for (id,&(x,y,signal)) in junctions.iter(){
+            let incoming:Vec<String> = self.roads.iter().enumerate()
+                .filter(|(index,_)| &ends[*index].1 == id)
+                .flat_map(|(index,road)| (0..road.lanes.max(1)).map(move |lane| format!("r{}_{}",index,lane)))
+                .collect();
+            let junction_type = if signal && signal_links.contains_key(id){
+                "traffic_light"
+            }
+            else if incoming.is_empty(){
+                "dead_end"
+            }
+            else{
+                "priority"
+            };
+            writeln!(net,"    <junction id=\"{}\" type=\"{}\" x=\"{:.2}\" y=\"{:.2}\" incLanes=\"{}\" intLanes=\"\"/>",id,junction_type,x,y,incoming.join(" ")).unwrap();
+        }
+        net.push_str(&connections);
+        writeln!(net,"</net>").unwrap();
+        net
+    }
+
+    pub fn sumo_routes(&self) -> String{
+        let mut routes = String::new();
+        writeln!(routes,"<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(routes,"<!-- seed: {} -->",self.rng.seed()).unwrap();
+        writeln!(routes,"<routes>").unwrap();
+        let edges = |roads:&[usize]| roads.iter().map(|road| format!("r{}",road)).collect::<Vec<String>>().join(" ");
+
+        //Vehicles in the network depart now from where they are
+        let mut entries = Vec::new();
+        for vehicle in self.vehicles.iter(){
+            let mut route = vec![vehicle.on_road];
+            route.extend(vehicle.path.iter());
+            entries.push((format!("{}",vehicle.id),self.time,vehicle.vehicle_type,vehicle.acceleration,vehicle.break_decceleration,vehicle.position,vehicle.velocity,vehicle.destination_position,route));
+        }
+        //Waiting vehicles keep their departure time, earliest first
+        for (index,departure) in self.departures.iter().rev().enumerate(){
+            let route = if departure.route.is_empty(){
+                self.find_shortest_path(departure.on_road,departure.destination,departure.vehicle_type)
+            }
+            else{
+                departure.route.clone()
+            };
+            entries.push((format!("departure{}",index),departure.time,departure.vehicle_type,departure.acceleration,departure.break_decceleration,departure.position,departure.velocity,departure.destination_position,route));
+        }
+
+        for (id,depart,vehicle_type,acceleration,break_decceleration,position,velocity,arrival,route) in entries{
+            let id = escape(&id);
+            let params = vehicle_type.params();
+            writeln!(routes,"    <vType id=\"{}_type\" vClass=\"{}\" accel=\"{:.2}\" decel=\"{:.2}\" length=\"{:.2}\" width=\"{:.2}\"/>",id,vehicle_class(vehicle_type),acceleration,-break_decceleration,params.length,params.width).unwrap();
+            writeln!(routes,"    <vehicle id=\"{}\" type=\"{}_type\" depart=\"{:.2}\" departPos=\"{:.2}\" departSpeed=\"{:.2}\" arrivalPos=\"{:.2}\">",id,id,depart,position,velocity,arrival).unwrap();
+            writeln!(routes,"        <route edges=\"{}\"/>",edges(&route)).unwrap();
+            writeln!(routes,"    </vehicle>").unwrap();
+        }
+        writeln!(routes,"</routes>").unwrap();
+        routes
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // A two lane edge to a signal, continued by an edge closed to trucks up to a dead end, and an edge back.
+    // The internal edge of the signal is skipped.
+    const NET:&str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<net version="1.9">
+    <edge id=":B_0" function="internal">
+        <lane id=":B_0_0" index="0" speed="13.89" length="5.00" shape="100.00,-1.60 105.00,-1.60"/>
+    </edge>
+    <edge id="e1" from="A" to="B" priority="-1">
+        <lane id="e1_0" index="0" speed="13.89" length="100.00" shape="0.00,-1.60 100.00,-1.60"/>
+        <lane id="e1_1" index="1" speed="13.89" length="100.00" shape="0.00,1.60 100.00,1.60"/>
+    </edge>
+    <edge id="e2" from="B" to="C" priority="-1">
+        <lane id="e2_0" index="0" speed="8.33" length="100.00" disallow="truck" shape="100.00,0.00 200.00,0.00"/>
+    </edge>
+    <edge id="e3" from="C" to="B" priority="-1">
+        <lane id="e3_0" index="0" speed="8.33" length="100.00" shape="200.00,3.20 100.00,3.20"/>
+    </edge>
+    <junction id="A" type="dead_end" x="0.00" y="0.00" incLanes="" intLanes=""/>
+    <junction id="B" type="traffic_light" x="100.00" y="0.00" incLanes="e1_0 e1_1 e3_0" intLanes=":B_0_0"/>
+    <junction id="C" type="dead_end" x="200.00" y="0.00" incLanes="e2_0" intLanes=""/>
+    <connection from="e1" to="e2" fromLane="0" toLane="0" via=":B_0_0" tl="B" linkIndex="0"/>
+    <connection from="e1" to="e2" fromLane="1" toLane="0" tl="B" linkIndex="1"/>
+    <connection from=":B_0" to="e2" fromLane="0" toLane="0"/>
+    <connection from="e2" to="e3" fromLane="0" toLane="0"/>
+    <connection from="e3" to="e2" fromLane="0" toLane="0" tl="B" linkIndex="2"/>
+</net>"#;
+
+    // A vehicle on a named route, a trip, a flow with a period and an inline route, and flows of a number of trips.
+    const ROUTES:&str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<routes>
+    <vType id="bus" vClass="bus" accel="1.2" decel="4.0"/>
+    <route id="through" edges="e1 e2"/>
+    <vehicle id="v0" type="bus" route="through" depart="5" departSpeed="max"/>
+    <trip id="t0" depart="10" from="e1" to="e2" arrivalPos="-20"/>
+    <flow id="f0" begin="0" end="300" period="60">
+        <route edges="e2 e3"/>
+    </flow>
+    <flow id="f1" begin="0" end="90" number="3" from="e1" to="e3"/>
+    <flow id="f2" begin="0" end="13" number="11" from="e2" to="e3"/>
+</routes>"#;
+
+    fn network() -> (World,HashMap<String,usize>){
+        let mut world = World::new();
+        let edge_ids = world.load_sumo_net(NET).unwrap();
+        (world,edge_ids)
+    }
+
+    #[test]
+    fn net_builds_roads(){
+        let (world,edge_ids) = network();
+        assert_eq!(world.roads.len(),3);
+        assert!(!edge_ids.contains_key(":B_0"));
+        let (e1,e2,e3) = (edge_ids["e1"],edge_ids["e2"],edge_ids["e3"]);
+
+        //Roads run along the middle of their lanes
+        assert_eq!((world.roads[e1].from,world.roads[e1].to),((0.0,0.0,0.0),(100.0,0.0,0.0)));
+        assert_eq!(world.roads[e1].lanes,2);
+        assert!(world.roads[e1].signal && world.roads[e3].signal);
+        assert_eq!(world.roads[e1].end_speed_limit,SIGNAL_SPEED);
+        assert!(!world.roads[e2].signal);
+        assert_eq!(world.roads[e2].end_speed_limit,world.roads[e2].speed_limit);
+
+        //Lane connections become one movement per pair of roads, limited to the types of the target lanes
+        let mut connections:Vec<(usize,usize)> = world.connections.keys().copied().collect();
+        connections.sort();
+        let mut expected = vec![(e1,e2),(e2,e3),(e3,e2)];
+        expected.sort();
+        assert_eq!(connections,expected);
+        let through = world.get_connection(e1,e2).unwrap();
+        assert!(!through.allows(VehicleType::Truck) && through.allows(VehicleType::Car) && through.allows(VehicleType::Bus));
+        assert!(world.get_connection(e2,e3).unwrap().allowed_types.is_none());
+    }
+
+    #[test]
+    fn routes_schedule_departures(){
+        let (mut world,edge_ids) = network();
+        world.load_sumo_routes(ROUTES,&edge_ids).unwrap();
+        let (e1,e2,e3) = (edge_ids["e1"],edge_ids["e2"],edge_ids["e3"]);
+
+        //One vehicle, one trip, 5 departures of the periodic flow, 3 and 11 of the counted flows
+        assert_eq!(world.departures.len(),21);
+        let bus = world.departures.iter().find(|departure| departure.time == 5.0).unwrap();
+        assert_eq!(bus.vehicle_type,VehicleType::Bus);
+        assert_eq!((bus.acceleration,bus.break_decceleration),(1.2,-4.0));
+        assert_eq!(bus.velocity,world.roads[e1].speed_limit);
+        assert_eq!(bus.route,vec![e1,e2]);
+
+        //Trips are routed when they depart
+        let trip = world.departures.iter().find(|departure| departure.time == 10.0).unwrap();
+        assert_eq!((trip.on_road,trip.destination),(e1,e2));
+        assert!(trip.route.is_empty());
+        assert!((trip.destination_position - 80.0).abs() < 1e-3);
+
+        let mut periodic:Vec<f32> = world.departures.iter().filter(|departure| departure.route == vec![e2,e3]).map(|departure| departure.time).collect();
+        periodic.sort_by(|a,b| a.total_cmp(b));
+        assert_eq!(periodic,vec![0.0,60.0,120.0,180.0,240.0]);
+        let counted:Vec<f32> = world.departures.iter().filter(|departure| (departure.on_road,departure.destination) == (e1,e3) && departure.route.is_empty()).map(|departure| departure.time).collect();
+        assert_eq!(counted.len(),3);
+        //13/11 s is not exact in f32, 11 periods end just before 13 s
+        let uneven:Vec<f32> = world.departures.iter().filter(|departure| departure.on_road == e2 && departure.route.is_empty()).map(|departure| departure.time).collect();
+        assert_eq!(uneven.len(),11);
+        assert!(uneven.iter().all(|&time| (0.0..13.0).contains(&time)));
+
+        //Departures are kept latest first
+        assert!(world.departures.windows(2).all(|pair| pair[0].time >= pair[1].time));
+    }
+
+    #[test]
+    fn invalid_routes_are_errors(){
+        let (mut world,edge_ids) = network();
+        for routes in [
+            r#"<routes><trip id="t" depart="0" from="" to="e2"/></routes>"#,
+            r#"<routes><trip id="t" depart="0" from="e1" to=" "/></routes>"#,
+            r#"<routes><trip id="t" depart="0" from="e1" to="e9"/></routes>"#,
+            r#"<routes><vehicle id="v" depart="0" route="missing"/></routes>"#,
+            r#"<routes><flow id="f" begin="0" end="10" from="e1" to="e2"/></routes>"#,
+        ]{
+            assert!(world.load_sumo_routes(routes,&edge_ids).is_err(),"{} was read",routes);
+        }
+        assert!(world.departures.is_empty());
+    }
+
+    #[test]
+    fn malformed_net_keeps_the_world(){
+        let (mut world,_) = network();
+        assert!(world.load_sumo_net("<net><edge id=\"e1\">").is_err());
+        assert_eq!(world.roads.len(),3);
+        assert!(world.load_sumo(Path::new("missing.net.xml"),None).is_err());
+        assert_eq!(world.roads.len(),3);
+    }
+
+    #[test]
+    fn exported_net_reads_back(){
+        let (world,_) = network();
+        let net = world.sumo_net();
+        assert!(net.contains(&format!("<!-- seed: {} -->",world.rng.seed())));
+        let mut copy = World::new();
+        let edge_ids = copy.load_sumo_net(&net).unwrap();
+        assert_eq!(copy.roads.len(),world.roads.len());
+        for (index,road) in world.roads.iter().enumerate(){
+            let other = &copy.roads[edge_ids[&format!("r{}",index)]];
+            assert_eq!((road.lanes,road.signal),(other.lanes,other.signal));
+            assert!((road.speed_limit - other.speed_limit).abs() < 0.01);
+        }
+        assert_eq!(copy.connections.len(),world.connections.len());
+    }
+}
@@ -0,0 +1,224 @@
+// Purpose: Per-vehicle trip records and network-wide trip statistics.
+
+use crate::comp::{Vehicle, World};
+use crate::rng::csv_writer_with_seed;
+use crate::vehicle_type::VehicleType;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Speed below which a vehicle counts as stopped, in m/s.
+const STOP_SPEED: f32 = 0.1;
+
+// Running statistics of a vehicle's trip, updated every simulation step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TripStats{
+    pub departure_time: f32,
+    pub route: Vec<usize>,
+    pub distance: f32,
+    // Time the distance would take at the speed limits.
+    pub free_flow_time: f32,
+    pub stops: u32,
+    pub time_stopped: f32,
+    pub max_deceleration: f32,
+    stopped: bool,
+}
+
+impl TripStats{
+    pub fn new(departure_time:f32,on_road:usize) -> TripStats{
+        TripStats{
+            departure_time,
+            route: vec![on_road],
+            distance: 0.0,
+            free_flow_time: 0.0,
+            stops: 0,
+            time_stopped: 0.0,
+            max_deceleration: 0.0,
+            //Waiting at the start does not count as a stop
+            stopped: true,
+        }
+    }
+
+    // Add a step of t seconds in which the vehicle moved the given distance on a road.
+    pub fn observe(&mut self,t:f32,on_road:usize,distance:f32,speed_limit:f32,previous_velocity:f32,velocity:f32){
+        if self.route.last() != Some(&on_road){
+            self.route.push(on_road);
+        }
+        self.distance += distance.max(0.0);
+        if speed_limit > 0.0{
+            self.free_flow_time += distance.max(0.0) / speed_limit;
+        }
+        if t > 0.0{
+            self.max_deceleration = self.max_deceleration.max((previous_velocity - velocity) / t);
+        }
+        //A stop starts when the vehicle comes to a halt
+        let stopped = velocity < STOP_SPEED;
+        if stopped{
+            self.time_stopped += t;
+            if !self.stopped{
+                self.stops += 1;
+            }
+        }
+        self.stopped = stopped;
+    }
+}
+
+// Summary of a finished trip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TripRecord{
+    pub id: usize,
+    #[serde(rename = "type")]
+    pub vehicle_type: VehicleType,
+    pub departure_time: f32,
+    pub arrival_time: f32,
+    pub travel_time: f32,
+    pub route: Vec<usize>,
+    pub distance: f32,
+    // Travel time beyond the time at the speed limits.
+    pub delay: f32,
+    pub stops: u32,
+    pub time_stopped: f32,
+    pub max_deceleration: f32,
+}
+
+impl TripRecord{
+    pub fn new(vehicle:&Vehicle,arrival_time:f32) -> TripRecord{
+        let trip = &vehicle.trip;
+        let travel_time = arrival_time - trip.departure_time;
+        TripRecord{
+            id: vehicle.id,
+            vehicle_type: vehicle.vehicle_type,
+            departure_time: trip.departure_time,
+            arrival_time,
+            travel_time,
+            route: trip.route.clone(),
+            distance: trip.distance,
+            delay: (travel_time - trip.free_flow_time).max(0.0),
+            //The halt at the destination is not a stop
+            stops: if trip.stopped { trip.stops.saturating_sub(1) } else { trip.stops },
+            time_stopped: trip.time_stopped,
+            max_deceleration: trip.max_deceleration,
+        }
+    }
+}
+
+// Network-wide statistics over the finished trips.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TripStatistics{
+    pub trips: usize,
+    pub total_distance: f32,
+    pub total_travel_time: f32,
+    pub total_delay: f32,
+    pub total_time_stopped: f32,
+    pub mean_travel_time: f32,
+    pub mean_delay: f32,
+    pub mean_stops: f32,
+    // Total distance over total travel time.
+    pub mean_speed: f32,
+    pub max_deceleration: f32,
+}
+
+#[derive(Serialize)]
+struct TripOutput<'a>{
+    seed: u64,
+    statistics: TripStatistics,
+    trips: &'a [TripRecord],
+}
+
+impl World{
+    pub fn trip_statistics(&self) -> TripStatistics{
+        let mut statistics = TripStatistics{
+            trips: self.trips.len(),
+            ..Default::default()
+        };
+        if self.trips.is_empty(){
+            return statistics;
+        }
+        let mut total_stops = 0;
+        for trip in self.trips.iter(){
+            statistics.total_distance += trip.distance;
+            statistics.total_travel_time += trip.travel_time;
+            statistics.total_delay += trip.delay;
+            statistics.total_time_stopped += trip.time_stopped;
+            statistics.max_deceleration = statistics.max_deceleration.max(trip.max_deceleration);
+            total_stops += trip.stops;
+        }
+        let count = self.trips.len() as f32;
+        statistics.mean_travel_time = statistics.total_travel_time / count;
+        statistics.mean_delay = statistics.total_delay / count;
+        statistics.mean_stops = total_stops as f32 / count;
+        if statistics.total_travel_time > 0.0{
+            statistics.mean_speed = statistics.total_distance / statistics.total_travel_time;
+        }
+        statistics
+    }
+
+    // Write the finished trips to a .json file with their statistics, or to CSV otherwise.
+    pub fn save_trips(&self,path:&Path) -> Result<(),String>{
+        if path.extension().and_then(|extension| extension.to_str()) == Some("json"){
+            let output = TripOutput{seed: self.rng.seed(),statistics: self.trip_statistics(),trips: &self.trips};
+            let contents = serde_json::to_string_pretty(&output).map_err(|e| e.to_string())?;
+            return std::fs::write(path,contents).map_err(|e| e.to_string());
+        }
+        let mut writer = csv_writer_with_seed(path,self.rng.seed())?;
+        writer.write_record(["id","type","departure_time","arrival_time","travel_time","route","distance","delay","stops","time_stopped","max_deceleration"]).map_err(|e| e.to_string())?;
+        for trip in self.trips.iter(){
+            //The route is a space separated list of road indices
+            let route:Vec<String> = trip.route.iter().map(|road| road.to_string()).collect();
+            writer.write_record(&[
+                trip.id.to_string(),
+                trip.vehicle_type.name().to_string(),
+                trip.departure_time.to_string(),
+                trip.arrival_time.to_string(),
+                trip.travel_time.to_string(),
+                route.join(" "),
+                trip.distance.to_string(),
+                trip.delay.to_string(),
+                trip.stops.to_string(),
+                trip.time_stopped.to_string(),
+                trip.max_deceleration.to_string(),
+            ]).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::contraction::tests::grid;
+    use crate::phy::update_comp;
+
+    #[test]
+    fn arriving_vehicle_records_its_trip(){
+        let mut world = grid(2);
+        let id = world.spawn_vehicle(0,0.0,VehicleType::Car);
+        let destination_position = world.vehicles[0].destination_position;
+        //The destination is drawn at random, so the route may take several roads
+        let route:Vec<usize> = std::iter::once(0).chain(world.vehicles[0].path.iter().copied()).collect();
+        let mut steps = 0;
+        while world.trips.is_empty(){
+            assert!(steps < 1000,"The vehicle did not arrive");
+            update_comp(0.1,&mut world);
+            steps += 1;
+        }
+        assert!(world.vehicles.is_empty());
+
+        let trip = &world.trips[0];
+        assert_eq!((trip.id,trip.vehicle_type),(id,VehicleType::Car));
+        assert_eq!(trip.route,route);
+        assert_eq!(trip.departure_time,0.0);
+        assert!((trip.arrival_time - world.time).abs() < 1e-4);
+        assert!((trip.travel_time - steps as f32 * 0.1).abs() < 1e-2,"Travel time {} after {} steps",trip.travel_time,steps);
+        //The vehicle halts within 10 meters of its arrival position on the last road
+        let before:f32 = route[..route.len() - 1].iter().map(|&road| world.roads[road].length.into_inner()).sum();
+        assert!(trip.distance > before + destination_position - 10.5 && trip.distance < before + destination_position + 0.5,"Distance {}",trip.distance);
+        //Starting and stopping makes the trip slower than at the speed limit of the roads
+        assert!((trip.delay - (trip.travel_time - trip.distance / 13.9)).abs() < 1e-2,"Delay {}",trip.delay);
+        assert!(trip.delay > 0.0);
+        assert_eq!(trip.stops,0);
+
+        let statistics = world.trip_statistics();
+        assert_eq!(statistics.trips,1);
+        assert_eq!((statistics.mean_travel_time,statistics.mean_delay),(trip.travel_time,trip.delay));
+    }
+}
@@ -0,0 +1,198 @@
+// Purpose: Named vehicle types with default parameters and per-type parameter distributions.
+
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+// Kind of vehicle, used for default parameters and rendering.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VehicleType{
+    #[default]
+    Car,
+    Van,
+    Truck,
+    Bus,
+    Motorcycle,
+}
+
+// Default driving parameters, dimensions and color of a vehicle type.
+#[derive(Debug, Clone, Copy)]
+pub struct VehicleTypeParams{
+    pub acceleration: f32,
+    pub break_decceleration: f32,
+    pub watch_distance: f32,
+    pub length: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: [f32; 3],
+}
+
+impl VehicleType{
+    pub const ALL: [VehicleType; 5] = [VehicleType::Car, VehicleType::Van, VehicleType::Truck, VehicleType::Bus, VehicleType::Motorcycle];
+
+    // Name used in scenario and output files.
+    pub fn name(&self) -> &'static str{
+        match self{
+            VehicleType::Car => "car",
+            VehicleType::Van => "van",
+            VehicleType::Truck => "truck",
+            VehicleType::Bus => "bus",
+            VehicleType::Motorcycle => "motorcycle",
+        }
+    }
+
+    // Type with the given name, as written by name().
+    pub fn from_name(name:&str) -> Option<VehicleType>{
+        VehicleType::ALL.into_iter().find(|vehicle_type| vehicle_type.name() == name)
+    }
+
+    pub fn params(&self) -> VehicleTypeParams{
+        match self{
+            VehicleType::Car => VehicleTypeParams{
+                acceleration: 3.0,
+                break_decceleration: -8.0,
+                watch_distance: 150.0,
+                length: 4.5,
+                width: 1.8,
+                height: 1.5,
+                color: [0.8, 0.2, 0.2],
+            },
+            VehicleType::Van => VehicleTypeParams{
+                acceleration: 2.5,
+                break_decceleration: -7.0,
+                watch_distance: 175.0,
+                length: 5.5,
+                width: 2.0,
+                height: 2.2,
+                color: [0.9, 0.6, 0.1],
+            },
+            VehicleType::Truck => VehicleTypeParams{
+                acceleration: 1.2,
+                break_decceleration: -5.0,
+                watch_distance: 250.0,
+                length: 12.0,
+                width: 2.5,
+                height: 3.5,
+                color: [0.2, 0.4, 0.8],
+            },
+            VehicleType::Bus => VehicleTypeParams{
+                acceleration: 1.5,
+                break_decceleration: -5.0,
+                watch_distance: 250.0,
+                length: 12.0,
+                width: 2.55,
+                height: 3.2,
+                color: [0.9, 0.8, 0.1],
+            },
+            VehicleType::Motorcycle => VehicleTypeParams{
+                acceleration: 4.5,
+                break_decceleration: -9.0,
+                watch_distance: 120.0,
+                length: 2.2,
+                width: 0.8,
+                height: 1.4,
+                color: [0.2, 0.8, 0.3],
+            },
+        }
+    }
+}
+
+// Smallest magnitude of a sampled acceleration and break decceleration, so a wide distribution never flips their sign.
+const MIN_ACCELERATION: f32 = 0.1;
+const MIN_BREAK_DECCELERATION: f32 = 0.1;
+
+// Distribution a vehicle parameter is sampled from.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "distribution", rename_all = "snake_case")]
+pub enum Distribution{
+    Constant{value: f32},
+    Normal{mean: f32, std_dev: f32},
+    TruncatedNormal{mean: f32, std_dev: f32, min: f32, max: f32},
+    Uniform{min: f32, max: f32},
+}
+
+impl Distribution{
+    // Check that the parameters are finite, the deviation is not negative and the ranges are not empty.
+    pub fn validate(&self) -> Result<(),String>{
+        let finite = |values:&[f32]| values.iter().all(|value| value.is_finite());
+        match *self{
+            Distribution::Constant{value} if !finite(&[value]) => Err(format!("constant value {} is not finite", value)),
+            Distribution::Normal{mean,std_dev} | Distribution::TruncatedNormal{mean,std_dev,..} if !finite(&[mean,std_dev]) || std_dev < 0.0 => {
+                Err(format!("normal distribution with mean {} and standard deviation {}", mean, std_dev))
+            }
+            Distribution::TruncatedNormal{min,max,..} | Distribution::Uniform{min,max} if !finite(&[min,max]) || min > max => {
+                Err(format!("range {} to {}", min, max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self,rng:&mut R) -> f32{
+        match *self{
+            Distribution::Constant{value} => value,
+            //Invalid parameters are rejected on load, the mean is the fallback
+            Distribution::Normal{mean,std_dev} => match Normal::new(mean,std_dev){
+                Ok(normal) => rng.sample(normal),
+                Err(_) => mean,
+            },
+            Distribution::TruncatedNormal{mean,std_dev,min,max} => {
+                let Ok(normal) = Normal::new(mean,std_dev) else { return mean.clamp(min,max) };
+                //Resample until the value falls in range, clamp if it keeps missing
+                for _ in 0..100{
+                    let value = rng.sample(normal);
+                    if value >= min && value <= max{
+                        return value;
+                    }
+                }
+                mean.clamp(min,max)
+            }
+            Distribution::Uniform{min,max} => {
+                if max > min{
+                    rng.gen_range(min..max)
+                }
+                else{
+                    min
+                }
+            }
+        }
+    }
+}
+
+// Per-type parameter distributions given in the scenario file.
+// Parameters without a distribution use the type default.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct VehicleTypeDistributions{
+    #[serde(default)]
+    pub acceleration: Option<Distribution>,
+    #[serde(default)]
+    pub break_deceleration: Option<Distribution>,
+    #[serde(default)]
+    pub watch_distance: Option<Distribution>,
+}
+
+impl VehicleTypeDistributions{
+    // Drop the invalid distributions of a type loaded from a scenario, those parameters use the type default.
+    pub fn remove_invalid(&mut self,vehicle_type:VehicleType){
+        for (name,distribution) in [("acceleration",&mut self.acceleration),("break_deceleration",&mut self.break_deceleration),("watch_distance",&mut self.watch_distance)]{
+            if let Some(Err(error)) = distribution.as_ref().map(|distribution| distribution.validate()){
+                println!("Invalid {} of {}: {}, using the default", name, vehicle_type.name(), error);
+                *distribution = None;
+            }
+        }
+    }
+
+    // Sample (acceleration, break decceleration, watch distance) for one vehicle of the given type.
+    pub fn sample<R: Rng>(&self,vehicle_type:VehicleType,rng:&mut R) -> (f32,f32,f32){
+        let params = vehicle_type.params();
+        let sample = |distribution:&Option<Distribution>,default:f32,rng:&mut R| match distribution{
+            Some(distribution) => distribution.sample(rng),
+            None => default,
+        };
+        //Accelerating vehicles speed up and breaking ones slow down, whatever the distribution draws
+        let acceleration = sample(&self.acceleration,params.acceleration,rng).max(MIN_ACCELERATION);
+        let break_decceleration = sample(&self.break_deceleration,params.break_decceleration,rng).min(-MIN_BREAK_DECCELERATION);
+        let watch_distance = sample(&self.watch_distance,params.watch_distance,rng).max(0.0);
+        (acceleration,break_decceleration,watch_distance)
+    }
+}